@@ -1,88 +1,111 @@
-use crate::{errors, viewer, Format, Molecule};
+use crate::Commands;
+
+use seqtools::errors::{Location, SeqtoolsError};
+use seqtools::faidx::{IndexedReader, Region};
+use seqtools::formats::flatfile;
+use seqtools::index::{self, IdIndex};
+use seqtools::intervals::{self, Feature};
+use seqtools::kmers::KmerCounts;
+use seqtools::locate::{Pattern, Search, Strand};
+use seqtools::pairs::{Mates, Pair, PairWriter, Repair};
+use seqtools::reader::{ErrorHandler, RecordReader};
+use seqtools::record::Record;
+use seqtools::report::{OutputFormat, RowWriter, Table};
+use seqtools::stats::{self, DuplicateGroups, Frequencies, LengthStats};
+use seqtools::transform::{self, Chain, RecordTransform};
+use seqtools::writer::{RecordWriter, WriteOptions};
+use seqtools::{init_writer, ops, viewer, Compression, Format, Input, Molecule};
 
 use std::collections::{BTreeMap, HashMap, HashSet};
 use std::error::Error;
 use std::fs::File;
-use std::io::{BufRead, BufReader, Write};
-use std::path::{Path, PathBuf};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
 
 use histogram::Histogram;
-use needletail::parser::{self, LineEnding};
-use needletail::FastxReader;
-use rand::Rng;
-use rand_distr::{Distribution, Normal};
+use needletail::parser::LineEnding;
 use serde_json::json;
 use textplots::{Chart, Plot, Shape};
 
-const DNA: &[u8] = b"ACGT";
-const RNA: &[u8] = b"ACGU";
-const PROTEIN: &[u8] = b"ACDEFGHIKLMNPQRSTVWY";
-
-struct SumStats {
-    min: u64,
-    max: u64,
-    mean: u64,
-    std: u64,
-    median: u64,
-    q1: u64,
-    q3: u64,
-}
-
-impl SumStats {
-    fn from_hist(hist: &Histogram) -> Result<Self, Box<dyn Error>> {
-        let (min, max) = (hist.minimum()?, hist.maximum()?);
-        let mean = hist.mean()?;
-        let std = hist.stddev().unwrap_or(0);
-        let (median, q1, q3) = (
-            hist.percentile(50.)?,
-            hist.percentile(25.)?,
-            hist.percentile(75.)?,
-        );
+/// Output settings shared by all the commands that write sequences
+pub struct Output {
+    pub compression: Option<Compression>,
+    pub line_ending: LineEnding,
+    pub line_width: usize,
+    pub phylip_interleaved: bool,
+    /// Where to write the mates 2 of read pairs, instead of after their mate 1
+    pub out2: Option<PathBuf>,
+}
 
-        Ok(SumStats {
-            min,
-            max,
-            mean,
-            std,
-            median,
-            q1,
-            q3,
-        })
+impl Output {
+    /// Open a record writer on `out`. The name map of strict PHYLIP output
+    /// goes next to it, in `<out>.names`.
+    fn writer(&self, out: Option<PathBuf>, to: Option<Format>) -> io::Result<RecordWriter> {
+        let name_map = out.as_ref().map(|path| {
+            let mut name = path.clone().into_os_string();
+            name.push(".names");
+            PathBuf::from(name)
+        });
+        let options = WriteOptions {
+            line_ending: self.line_ending,
+            line_width: self.line_width,
+            phylip_interleaved: self.phylip_interleaved,
+            name_map,
+        };
+
+        Ok(RecordWriter::new(
+            init_writer(out, self.compression)?,
+            to,
+            options,
+        ))
     }
+}
 
-    fn print_row(&self) {
-        eprintln!(
-            "Min: {}\tMax: {}\tMean: {}\tSdev: {}\tQ1: {}\tMedian: {}\tQ3: {}",
-            self.min, self.max, self.mean, self.std, self.q1, self.median, self.q3
-        );
+/// The input files of a command, their mates, and what to do with their
+/// malformed records
+pub struct Inputs {
+    pub files: Vec<Input>,
+    pub mates: Mates,
+    pub errors: ErrorHandler,
+}
+
+impl Inputs {
+    fn open(&self, input: &Input) -> Result<RecordReader, SeqtoolsError> {
+        input.open_with(&self.errors)
     }
 
-    fn print_parsable(&self) {
-        println!("Min\tMax\tMean\tSdev\tQ1\tMedian\tQ3",);
-        println!(
-            "{}\t{}\t{}\t{}\t{}\t{}\t{}",
-            self.min, self.max, self.mean, self.std, self.q1, self.median, self.q3
-        );
+    fn len(&self) -> usize {
+        self.files.len()
     }
 
-    fn print_col(&self) {
-        println!("Min:\t{}", self.min);
-        println!("Max:\t{}", self.max);
-        println!("Mean:\t{}", self.mean);
-        println!("Sdev:\t{}", self.std);
-        println!("Q1:\t{}", self.q1);
-        println!("Median:\t{}", self.median);
-        println!("Q3:\t{}", self.q3);
+    fn paired(&self) -> bool {
+        self.mates != Mates::None
     }
 }
 
-fn init_reader(
-    input: Option<PathBuf>,
-) -> Result<Box<dyn FastxReader>, needletail::errors::ParseError> {
-    match input {
-        Some(path) => needletail::parse_fastx_file(path),
-        None => needletail::parse_fastx_stdin(),
-    }
+fn print_row(stats: &LengthStats) {
+    println!(
+        "Min: {}\tMax: {}\tMean: {}\tSdev: {}\tQ1: {}\tMedian: {}\tQ3: {}",
+        stats.min, stats.max, stats.mean, stats.std, stats.q1, stats.median, stats.q3
+    );
+}
+
+fn print_parsable(stats: &LengthStats) {
+    println!("Min\tMax\tMean\tSdev\tQ1\tMedian\tQ3",);
+    println!(
+        "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+        stats.min, stats.max, stats.mean, stats.std, stats.q1, stats.median, stats.q3
+    );
+}
+
+fn print_col(stats: &LengthStats) {
+    println!("Min:\t{}", stats.min);
+    println!("Max:\t{}", stats.max);
+    println!("Mean:\t{}", stats.mean);
+    println!("Sdev:\t{}", stats.std);
+    println!("Q1:\t{}", stats.q1);
+    println!("Median:\t{}", stats.median);
+    println!("Q3:\t{}", stats.q3);
 }
 
 fn draw_hist(hist: &mut Histogram) -> Result<(), Box<dyn Error>> {
@@ -104,58 +127,147 @@ fn draw_hist(hist: &mut Histogram) -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-pub fn count(input: Option<PathBuf>) -> Result<(), Box<dyn Error>> {
-    let mut reader = init_reader(input)?;
+/// Read one value per line from a file
+fn read_lines(path: PathBuf) -> Result<Vec<String>, Box<dyn Error>> {
+    let buf_reader = BufReader::new(File::open(path)?);
+    let mut lines = vec![];
+    for line in buf_reader.lines() {
+        lines.push(line?);
+    }
 
-    let mut count = 0;
-    while let Some(r) = reader.next() {
-        match r {
-            Ok(_) => count += 1,
-            Err(e) => return Err(e.into()),
-        }
+    Ok(lines)
+}
+
+/// Gather values given on the command line and in a file, one value per line
+fn gather_values(
+    values: Option<Vec<String>>,
+    file: Option<PathBuf>,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    if values.is_none() && file.is_none() {
+        return Err(SeqtoolsError::invalid_argument(
+            "You must specify values on the command line or in a file with --ids-file",
+        )
+        .into());
+    }
+
+    let mut all = values.unwrap_or_default();
+    if let Some(file) = file {
+        all.extend(read_lines(file)?);
+    }
+
+    Ok(all)
+}
+
+/// Print stats as a table with one row per input file
+fn print_files(rows: &[(String, LengthStats)]) {
+    println!("File\tMin\tMax\tMean\tSdev\tQ1\tMedian\tQ3");
+    for (file, stats) in rows {
+        println!(
+            "{file}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            stats.min, stats.max, stats.mean, stats.std, stats.q1, stats.median, stats.q3
+        );
+    }
+}
+
+/// Round a percentage to 2 decimals for machine readable output
+fn percent_value(p: f64) -> serde_json::Value {
+    json!((p * 100.).round() / 100.)
+}
+
+fn stats_row(file: &str, stats: &LengthStats) -> Vec<serde_json::Value> {
+    vec![
+        json!(file),
+        json!(stats.min),
+        json!(stats.max),
+        json!(stats.mean),
+        json!(stats.std),
+        json!(stats.q1),
+        json!(stats.median),
+        json!(stats.q3),
+    ]
+}
+
+pub fn count(inputs: &Inputs, format: OutputFormat) -> Result<(), Box<dyn Error>> {
+    let mut table = Table::new(&["file", "count"]);
+    let mut total = 0;
+    for input in inputs.files.iter() {
+        let count = stats::count(&mut inputs.open(input)?)?;
+        table.push(vec![json!(input.to_string()), json!(count)]);
+        total += count;
     }
 
-    println!("{count}");
+    if inputs.len() > 1 {
+        table.push(vec![json!("total"), json!(total)]);
+        table.print(format)?;
+    } else if format == OutputFormat::Text {
+        println!("{total}");
+    } else {
+        table.print(format)?;
+    }
 
     Ok(())
 }
 
 pub fn length(
-    input: Option<PathBuf>,
+    inputs: &Inputs,
     stats: bool,
     histogram: bool,
     tabular: bool,
+    format: OutputFormat,
 ) -> Result<(), Box<dyn Error>> {
-    let mut reader = init_reader(input)?;
-
     if stats || histogram {
         let mut hist = Histogram::new();
-
-        while let Some(r) = reader.next() {
-            let record = r?;
-            let l = record.seq().len();
-            hist.increment(l as u64)
-                .expect("Error incrementing histogram");
+        let mut rows = vec![];
+        for input in inputs.files.iter() {
+            let file_hist = stats::length_histogram(&mut inputs.open(input)?)?;
+            hist.merge(&file_hist);
+            rows.push((input.to_string(), LengthStats::from_hist(&file_hist)?));
+        }
+        let stats = LengthStats::from_hist(&hist)?;
+        if inputs.len() > 1 {
+            rows.push(("total".to_string(), stats.clone()));
         }
-
-        let stats = SumStats::from_hist(&hist)?;
 
         if histogram {
             draw_hist(&mut hist)?;
-            stats.print_row();
+        }
+
+        if format != OutputFormat::Text {
+            let mut table =
+                Table::new(&["file", "min", "max", "mean", "sdev", "q1", "median", "q3"]);
+            for (file, stats) in rows.iter() {
+                table.push(stats_row(file, stats));
+            }
+            table.print(format)?;
+        } else if inputs.len() > 1 {
+            print_files(&rows);
+        } else if histogram {
+            print_row(&stats);
         } else if tabular {
-            stats.print_parsable();
+            print_parsable(&stats);
         } else {
-            stats.print_col();
+            print_col(&stats);
         }
     } else {
-        while let Some(r) = reader.next() {
-            let record = r?;
-            println!(
-                "{}\t{}",
-                std::str::from_utf8(record.id())?,
-                record.seq().len()
-            );
+        let mut table = Table::new(&["file", "id", "length"]);
+        for input in inputs.files.iter() {
+            for seq_length in stats::lengths(&mut inputs.open(input)?)? {
+                if format == OutputFormat::Text {
+                    if inputs.len() > 1 {
+                        print!("{input}\t");
+                    }
+                    println!("{}\t{}", seq_length.id, seq_length.length);
+                } else {
+                    table.push(vec![
+                        json!(input.to_string()),
+                        json!(seq_length.id),
+                        json!(seq_length.length),
+                    ]);
+                }
+            }
+        }
+        if format != OutputFormat::Text {
+            table.print(format)?;
         }
     }
 
@@ -169,524 +281,1159 @@ pub fn generate_random(
     sequence_type: Molecule,
     out: Option<PathBuf>,
     format: Format,
-    line_ending: LineEnding,
+    output: &Output,
 ) -> Result<(), Box<dyn Error>> {
-    let mut writer = match out {
-        Some(ref path) => Box::new(std::fs::File::create(Path::new(path))?) as Box<dyn Write>,
-        None => Box::new(std::io::stdout()) as Box<dyn Write>,
-    };
-
-    let charset = match sequence_type {
-        Molecule::DNA => DNA,
-        Molecule::RNA => RNA,
-        Molecule::Protein => PROTEIN,
-    };
-
-    let mut rng = rand::thread_rng();
-    let mut hist = Histogram::new();
-
-    let normal = Normal::new(len, std)?;
-
-    for i in 0..num {
-        let id_str = format!("S{i}");
-        let id = id_str.as_bytes();
+    let mut writer = output.writer(out, Some(format))?;
+    ops::generate_random(&mut writer, num, len, std, sequence_type)
+}
 
-        let x: u64 = normal.sample(&mut rng) as u64;
-        hist.increment(x)?;
+/// Print the k-mer counts of the input sequences, all together or per
+/// sequence, or their spectrum
+pub fn kmers(
+    inputs: &Inputs,
+    k: usize,
+    canonical: bool,
+    per_sequence: bool,
+    spectrum: bool,
+    format: OutputFormat,
+) -> Result<(), Box<dyn Error>> {
+    let mut stdout = io::stdout().lock();
+    if per_sequence {
+        let columns = if format == OutputFormat::Text && inputs.len() == 1 {
+            &["id", "kmer", "count"][..]
+        } else {
+            &["file", "id", "kmer", "count"][..]
+        };
+        let mut rows = RowWriter::new(&mut stdout, columns, format)?;
+        for input in inputs.files.iter() {
+            let mut reader = inputs.open(input)?;
+            stats::kmers_per_sequence(&mut reader, k, canonical, |seq_kmers| {
+                for (kmer, count) in seq_kmers.kmers.sorted() {
+                    let mut row = vec![json!(seq_kmers.id), json!(kmer), json!(count)];
+                    if columns.len() == 4 {
+                        row.insert(0, json!(input.to_string()));
+                    }
+                    rows.write(&row)?;
+                }
+                Ok(())
+            })?;
+        }
+        rows.finish()?;
+        return Ok(());
+    }
 
-        let seq: String = (0..x)
-            .map(|_| {
-                let idx = rng.gen_range(0..charset.len());
-                charset[idx] as char
-            })
-            .collect();
+    let mut counts = KmerCounts::new(k, canonical)?;
+    for input in inputs.files.iter() {
+        counts.merge(stats::kmers(&mut inputs.open(input)?, k, canonical)?);
+    }
 
-        match format {
-            Format::Fasta => parser::write_fasta(id, seq.as_bytes(), &mut writer, line_ending),
-            Format::Fastq => {
-                parser::write_fastq(id, seq.as_bytes(), None, &mut writer, line_ending)
+    let table = if spectrum {
+        let spectrum = counts.spectrum();
+        if !spectrum.is_empty() {
+            let mut hist = Histogram::new();
+            for (count, kmers) in spectrum.iter() {
+                hist.increment_by(*count, *kmers)?;
             }
-        }?;
-    }
+            draw_hist(&mut hist)?;
+        }
+        let mut table = Table::new(&["count", "kmers"]);
+        for (count, kmers) in spectrum {
+            table.push(vec![json!(count), json!(kmers)]);
+        }
+        table
+    } else {
+        let mut table = Table::new(&["kmer", "count"]);
+        for (kmer, count) in counts.sorted() {
+            table.push(vec![json!(kmer), json!(count)]);
+        }
+        table
+    };
+    table.write(&mut stdout, format)?;
 
     Ok(())
 }
 
-pub fn frequencies(input: Option<PathBuf>, per_sequence: bool) -> Result<(), Box<dyn Error>> {
-    let mut reader = init_reader(input)?;
-
+pub fn frequencies(
+    inputs: &Inputs,
+    per_sequence: bool,
+    format: OutputFormat,
+) -> Result<(), Box<dyn Error>> {
     if per_sequence {
-        while let Some(r) = reader.next() {
-            let mut counter: HashMap<u8, u32> = HashMap::new();
-            let record = r?;
-            for c in record.seq().iter() {
-                counter
-                    .entry(*c)
-                    .and_modify(|count| *count += 1)
-                    .or_insert(0);
-            }
-            print!("{}", std::str::from_utf8(record.id())?);
-            let total: u32 = counter.values().sum();
-            let mut keys: Vec<&u8> = counter.keys().collect();
-            keys.sort();
-
-            for key in keys {
-                let val = counter.get(key).unwrap();
-                let p = (*val as f64 / total as f64) * 100.;
-                print!("\t{}: {} {p:.2}%", *key as char, val);
+        let mut table = Table::new(&["file", "id", "char", "count", "percent"]);
+        for input in inputs.files.iter() {
+            for seq_freqs in stats::frequencies_per_sequence(&mut inputs.open(input)?)? {
+                let freqs = seq_freqs.frequencies;
+                if format != OutputFormat::Text {
+                    for (key, val) in freqs.counts.iter() {
+                        table.push(vec![
+                            json!(input.to_string()),
+                            json!(seq_freqs.id),
+                            json!((*key as char).to_string()),
+                            json!(val),
+                            percent_value(freqs.percent(*key)),
+                        ]);
+                    }
+                    continue;
+                }
+
+                if inputs.len() > 1 {
+                    print!("{input}\t");
+                }
+                print!("{}", seq_freqs.id);
+                for (key, val) in freqs.counts.iter() {
+                    let p = freqs.percent(*key);
+                    print!("\t{}: {} {p:.2}%", *key as char, val);
+                }
+                println!();
             }
-            println!();
+        }
+        if format != OutputFormat::Text {
+            table.print(format)?;
         }
     } else {
-        let mut counter: HashMap<u8, u32> = HashMap::new();
-        while let Some(r) = reader.next() {
-            let record = r?;
-            for c in record.seq().iter() {
-                counter
-                    .entry(*c)
-                    .and_modify(|count| *count += 1)
-                    .or_insert(0);
-            }
+        let mut rows = vec![];
+        let mut total = Frequencies::default();
+        for input in inputs.files.iter() {
+            let freqs = stats::frequencies(&mut inputs.open(input)?)?;
+            total.merge(&freqs);
+            rows.push((input.to_string(), freqs));
+        }
+        if inputs.len() > 1 {
+            rows.push(("total".to_string(), total));
         }
-        let total: u32 = counter.values().sum();
-        let mut keys: Vec<&u8> = counter.keys().collect();
-        keys.sort();
 
-        for key in keys {
-            let val = counter.get(key).unwrap();
-            let p = (*val as f64 / total as f64) * 100.;
-            println!("{}\t{}\t{p:.2} %", *key as char, val);
+        let mut table = Table::new(&["file", "char", "count", "percent"]);
+        for (file, freqs) in rows {
+            for (key, val) in freqs.counts.iter() {
+                let p = freqs.percent(*key);
+                if format != OutputFormat::Text {
+                    table.push(vec![
+                        json!(file),
+                        json!((*key as char).to_string()),
+                        json!(val),
+                        percent_value(p),
+                    ]);
+                    continue;
+                }
+
+                if inputs.len() > 1 {
+                    print!("{file}\t");
+                }
+                println!("{}\t{}\t{p:.2} %", *key as char, val);
+            }
+        }
+        if format != OutputFormat::Text {
+            table.print(format)?;
         }
     }
 
     Ok(())
 }
 
-pub fn ids(input: Option<PathBuf>) -> Result<(), Box<dyn Error>> {
-    let mut reader = init_reader(input)?;
-
-    while let Some(r) = reader.next() {
-        let record = r?;
-        match std::str::from_utf8(record.id()) {
-            Ok(id) => println!("{id}"),
-            Err(e) => {
-                let msg = format!("Error reading id: {e}");
-                return Err(errors::SeqError::new(&msg, record.id()).into());
-            }
-        };
+pub fn ids(inputs: &Inputs) -> Result<(), Box<dyn Error>> {
+    for input in inputs.files.iter() {
+        for id in stats::ids(&mut inputs.open(input)?)? {
+            println!("{id}");
+        }
     }
 
     Ok(())
 }
 
-pub fn convert(
-    input: Option<PathBuf>,
-    to: Format,
-    out: Option<PathBuf>,
-    line_ending: LineEnding,
-) -> Result<(), Box<dyn Error>> {
-    let mut reader = init_reader(input)?;
-    let mut writer = match out {
-        Some(ref path) => Box::new(std::fs::File::create(Path::new(path))?) as Box<dyn Write>,
-        None => Box::new(std::io::stdout()) as Box<dyn Write>,
+/// Patterns to search for, given on the command line and in a file, and the
+/// options of the search
+pub fn build_search(
+    patterns: Vec<String>,
+    patterns_file: Option<PathBuf>,
+    degenerate: bool,
+    regex: bool,
+    strand: Strand,
+    max_distance: usize,
+    edit: bool,
+) -> Result<Search, Box<dyn Error>> {
+    let mut texts = patterns;
+    if let Some(file) = patterns_file {
+        texts.extend(
+            read_lines(file)?
+                .into_iter()
+                .filter(|line| !line.is_empty()),
+        );
+    }
+    if texts.is_empty() {
+        return Err(SeqtoolsError::invalid_argument(
+            "You must specify patterns on the command line or in a file with --patterns-file",
+        )
+        .into());
+    }
+    let patterns = texts
+        .iter()
+        .map(|text| match (degenerate, regex) {
+            (true, _) => Pattern::degenerate(text),
+            (_, true) => Pattern::regex(text),
+            _ => Ok(Pattern::literal(text)),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let search = Search {
+        patterns,
+        strand,
+        max_distance,
+        edit,
     };
+    search.check()?;
+    Ok(search)
+}
 
-    match to {
-        Format::Fasta => {
-            while let Some(r) = reader.next() {
-                let record = r?;
-                let (id, seq): (&[u8], &[u8]) = (record.id(), &record.seq());
-                parser::write_fasta(id, seq, &mut writer, line_ending)?;
+/// Search patterns in the input sequences and print the matches as BED lines,
+/// or as a table with 1-based inclusive positions
+pub fn locate(
+    inputs: &Inputs,
+    search: &Search,
+    bed: bool,
+    format: OutputFormat,
+) -> Result<(), Box<dyn Error>> {
+    let mut table = Table::new(&[
+        "file", "id", "pattern", "strand", "start", "end", "distance", "match",
+    ]);
+    let mut stdout = io::stdout().lock();
+    for input in inputs.files.iter() {
+        let mut reader = inputs.open(input)?;
+        while let Some(record) = reader.next() {
+            let record = record?;
+            let hits = search.find(&record.seq);
+            if hits.is_empty() {
+                continue;
             }
-        }
-        Format::Fastq => {
-            while let Some(r) = reader.next() {
-                let record = r?;
-                let (id, seq): (&[u8], &[u8]) = (record.id(), &record.seq());
-                parser::write_fastq(id, seq, None, &mut writer, line_ending)?;
+            let id = record.id_str().map_err(|e| {
+                SeqtoolsError::record(format!("Error reading id: {e}"), &record.id)
+                    .with_location(reader.location())
+            })?;
+
+            for hit in hits {
+                let pattern = &search.patterns[hit.pattern].text;
+                let strand = if hit.reverse { "-" } else { "+" };
+                let matched = String::from_utf8_lossy(&hit.matched);
+                if bed {
+                    let name = id.split_whitespace().next().unwrap_or_default();
+                    writeln!(
+                        stdout,
+                        "{name}\t{}\t{}\t{pattern}\t{}\t{strand}",
+                        hit.start, hit.end, hit.distance
+                    )?;
+                } else if format == OutputFormat::Text {
+                    if inputs.len() > 1 {
+                        write!(stdout, "{input}\t")?;
+                    }
+                    writeln!(
+                        stdout,
+                        "{id}\t{pattern}\t{strand}\t{}\t{}\t{}\t{matched}",
+                        hit.start + 1,
+                        hit.end,
+                        hit.distance
+                    )?;
+                } else {
+                    table.push(vec![
+                        json!(input.to_string()),
+                        json!(id),
+                        json!(pattern),
+                        json!(strand),
+                        json!(hit.start + 1),
+                        json!(hit.end),
+                        json!(hit.distance),
+                        json!(matched),
+                    ]);
+                }
             }
         }
-    };
+    }
+    if !bed && format != OutputFormat::Text {
+        table.write(&mut stdout, format)?;
+    }
 
     Ok(())
 }
 
-pub fn select_by_ids(
-    input: Option<PathBuf>,
-    ids: Option<Vec<String>>,
-    ids_file: Option<PathBuf>,
-    out: Option<PathBuf>,
-    line_ending: LineEnding,
-) -> Result<(), Box<dyn Error>> {
-    let mut to_select: HashSet<String> = HashSet::new();
-    match (ids, ids_file) {
-        (None, None) => return Err(errors::MainError::new("").into()),
-        (Some(ids), None) => {
-            for id in ids {
-                to_select.insert(id);
-            }
-        }
-        (None, Some(file)) => {
-            let file = File::open(file)?;
-            let buf_reader = BufReader::new(file);
-            for id in buf_reader.lines() {
-                let id = id?;
-                to_select.insert(id);
-            }
-        }
-        (Some(ids), Some(file)) => {
-            for id in ids {
-                to_select.insert(id);
-            }
-            let file = File::open(file)?;
-            let buf_reader = BufReader::new(file);
-            for id in buf_reader.lines() {
-                let id = id?;
-                to_select.insert(id);
-            }
+/// Read a tab delimited `<old_name>\t<new_name>` file
+fn read_name_map(map_file: Option<PathBuf>) -> Result<HashMap<String, String>, Box<dyn Error>> {
+    let mut new_names: HashMap<String, String> = HashMap::new();
+    let file = match map_file {
+        Some(path) => File::open(path)?,
+        None => {
+            return Err(SeqtoolsError::invalid_argument(
+                "You must specify a name-mapping file or --number",
+            )
+            .into())
         }
     };
+    let buf_reader = BufReader::new(file);
+    for (i, line) in buf_reader.lines().enumerate() {
+        let line = line?;
+        let split: Vec<String> = line.split('\t').map(|s| s.to_owned()).collect();
+        if split.len() != 2 {
+            return Err(SeqtoolsError::invalid_argument(format!(
+                "Line {} of the map file should be '<old_name>\\t<new_name>'",
+                i + 1
+            ))
+            .into());
+        }
+        new_names.insert(split[0].clone(), split[1].clone());
+    }
 
-    let mut reader = init_reader(input)?;
-    let mut writer = match out {
-        Some(ref path) => Box::new(std::fs::File::create(Path::new(path))?) as Box<dyn Write>,
-        None => Box::new(std::io::stdout()) as Box<dyn Write>,
-    };
+    Ok(new_names)
+}
 
-    while let Some(r) = reader.next() {
-        let record = r?;
-        let (id, seq): (&[u8], &[u8]) = (record.id(), &record.seq());
-        let id_s = String::from(std::str::from_utf8(id)?);
-        if to_select.contains(&id_s) {
-            parser::write_fasta(id, seq, &mut writer, line_ending)?;
-        }
+/// Prints the number and ids of the records removed by a de-duplicate step
+/// once the input has been exhausted.
+struct ReportDuplicates {
+    dedup: ops::Deduplicate,
+    verbose: u8,
+}
+
+impl RecordTransform for ReportDuplicates {
+    fn apply(&mut self, record: Record, out: &mut Vec<Record>) -> Result<(), Box<dyn Error>> {
+        self.dedup.apply(record, out)
     }
 
-    Ok(())
+    fn finish(&mut self, out: &mut Vec<Record>) -> Result<(), Box<dyn Error>> {
+        self.dedup.finish(out)?;
+        self.report();
+
+        Ok(())
+    }
+
+    fn apply_pair(&mut self, pair: Pair, out: &mut Vec<Pair>) -> Result<(), Box<dyn Error>> {
+        self.dedup.apply_pair(pair, out)
+    }
+
+    fn finish_pairs(&mut self, out: &mut Vec<Pair>) -> Result<(), Box<dyn Error>> {
+        self.dedup.finish_pairs(out)?;
+        self.report();
+
+        Ok(())
+    }
 }
 
-pub fn select_by_index(
-    input: Option<PathBuf>,
-    indices: Option<Vec<String>>,
-    indices_file: Option<PathBuf>,
+impl ReportDuplicates {
+    /// Print the number of removed records, and their ids if verbose
+    fn report(&self) {
+        let removed = self.dedup.removed();
+        if self.verbose > 0 {
+            eprint!("{}", removed.len());
+            if self.verbose > 1 {
+                eprint!(": {}", removed.join(" "));
+            }
+            eprintln!()
+        }
+    }
+}
+
+/// A command that rewrites records, split into its transform and output options
+struct Rewrite {
+    transform: Box<dyn RecordTransform>,
     out: Option<PathBuf>,
-    line_ending: LineEnding,
-) -> Result<(), Box<dyn Error>> {
-    let mut to_select: HashSet<usize> = HashSet::new();
-    match (indices, indices_file) {
-        (None, None) => return Err(errors::MainError::new("").into()),
-        (Some(indices), None) => {
-            for index in indices {
-                to_select.insert(index.parse::<usize>()?);
+    to: Option<Format>,
+}
+
+/// Build the transform behind a record rewriting command.
+/// Returns `None` for commands that do not rewrite records.
+fn build_rewrite(command: Commands) -> Result<Option<Rewrite>, Box<dyn Error>> {
+    let (transform, out, to): (Box<dyn RecordTransform>, _, _) = match command {
+        Commands::Convert { to, out } => (Box::new(Chain::default()), out, Some(to)),
+        Commands::Select {
+            ids,
+            use_indices,
+            ids_file,
+            out,
+            to,
+        } => {
+            let values = gather_values(ids, ids_file)?;
+            if use_indices {
+                let mut to_select: HashSet<usize> = HashSet::new();
+                for index in values {
+                    let index = index.parse::<usize>().map_err(|e| {
+                        SeqtoolsError::invalid_argument(format!("Invalid index '{index}': {e}"))
+                    })?;
+                    to_select.insert(index);
+                }
+                (Box::new(ops::select_by_index(to_select)), out, to)
+            } else {
+                let to_select: HashSet<String> = values.into_iter().collect();
+                (Box::new(ops::select_by_ids(to_select)), out, to)
             }
         }
-        (None, Some(file)) => {
-            let file = File::open(file)?;
-            let buf_reader = BufReader::new(file);
-            for index in buf_reader.lines() {
-                let index = index?;
-                to_select.insert(index.parse::<usize>()?);
+        Commands::Rename {
+            number,
+            map_file,
+            out,
+            to,
+        } => {
+            if number {
+                (Box::new(ops::index_rename()), out, to)
+            } else {
+                let new_names = read_name_map(map_file)?;
+                (Box::new(ops::map_rename(new_names)), out, to)
             }
         }
-        (Some(indices), Some(file)) => {
-            for index in indices {
-                to_select.insert(index.parse::<usize>()?);
+        Commands::AddId {
+            to_add,
+            as_prefix,
+            out,
+            to,
+        } => (Box::new(ops::add_id(to_add, as_prefix)), out, to),
+        Commands::Trim {
+            n_char,
+            from_start,
+            out,
+            to,
+        } => (Box::new(ops::trim(n_char, from_start)), out, to),
+        Commands::Clip { max_len, out, to } => (Box::new(ops::clip(max_len)), out, to),
+        Commands::Revcomp {
+            ids,
+            ids_file,
+            out,
+            to,
+        } => {
+            let only = match (&ids, &ids_file) {
+                (None, None) => None,
+                _ => Some(gather_values(ids, ids_file)?.into_iter().collect()),
+            };
+            (Box::new(ops::reverse_complement(only)), out, to)
+        }
+        Commands::Translate {
+            table,
+            frame,
+            to_stop,
+            out,
+            to,
+        } => (
+            Box::new(ops::translate(table, frame.offsets(), to_stop)),
+            out,
+            to,
+        ),
+        Commands::Subseq {
+            regions,
+            bed,
+            gff,
+            flank,
+            out,
+            to,
+        } => {
+            if regions.is_empty() && bed.is_none() && gff.is_none() {
+                return Err(SeqtoolsError::invalid_argument(
+                    "You must specify regions on the command line, or in a file with --bed or --gff",
+                )
+                .into());
+            }
+            let mut features = regions
+                .iter()
+                .map(|region| Feature::parse(region))
+                .collect::<Result<Vec<_>, _>>()?;
+            if let Some(bed) = bed {
+                features.extend(intervals::read_bed(&bed)?);
             }
-            let file = File::open(file)?;
-            let buf_reader = BufReader::new(file);
-            for index in buf_reader.lines() {
-                let index = index?;
-                to_select.insert(index.parse::<usize>()?);
+            if let Some(gff) = gff {
+                features.extend(intervals::read_gff(&gff)?);
             }
+            (Box::new(ops::subseq(features, flank)), out, to)
+        }
+        Commands::DeDuplicate { out, verbose } => {
+            let dedup = ops::Deduplicate::new();
+            (Box::new(ReportDuplicates { dedup, verbose }), out, None)
         }
+        _ => return Ok(None),
     };
 
-    let mut reader = init_reader(input)?;
-    let mut writer = match out {
-        Some(ref path) => Box::new(std::fs::File::create(Path::new(path))?) as Box<dyn Write>,
-        None => Box::new(std::io::stdout()) as Box<dyn Write>,
+    Ok(Some(Rewrite { transform, out, to }))
+}
+
+/// Run `transform` over the input records and write the results to `out`
+fn run_transform(
+    inputs: &Inputs,
+    out: Option<PathBuf>,
+    to: Option<Format>,
+    output: &Output,
+    transform: &mut dyn RecordTransform,
+) -> Result<(), Box<dyn Error>> {
+    let mut writer = output.writer(out, to)?;
+    if !inputs.paired() {
+        return transform::process_inputs(&inputs.files, &inputs.errors, &mut writer, transform);
+    }
+
+    let writer2 = match &output.out2 {
+        Some(out2) => Some(output.writer(Some(out2.clone()), to)?),
+        None => None,
     };
+    let mut writer = PairWriter::new(writer, writer2);
+    transform::process_pairs(
+        &inputs.files,
+        &inputs.mates,
+        &inputs.errors,
+        &mut writer,
+        transform,
+    )
+}
 
-    let mut cursor: usize = 0;
-    while let Some(r) = reader.next() {
-        let record = r?;
-        let (id, seq): (&[u8], &[u8]) = (record.id(), &record.seq());
-        if to_select.contains(&cursor) {
-            parser::write_fasta(id, seq, &mut writer, line_ending)?;
+/// Run a command that rewrites records (convert, select, rename, ...)
+pub fn rewrite(inputs: &Inputs, command: Commands, output: &Output) -> Result<(), Box<dyn Error>> {
+    if let Commands::Select {
+        ids,
+        use_indices: false,
+        ids_file,
+        out,
+        to,
+    } = &command
+    {
+        if let Some(indexes) = id_indexes(inputs)? {
+            let values = gather_values(ids.clone(), ids_file.clone())?;
+            return select_indexed(indexes, values, out.clone(), *to, output);
         }
-        cursor += 1;
     }
 
-    Ok(())
+    let mut rewrite = build_rewrite(command)?
+        .ok_or_else(|| SeqtoolsError::invalid_argument("This command does not rewrite records"))?;
+
+    run_transform(
+        inputs,
+        rewrite.out,
+        rewrite.to,
+        output,
+        rewrite.transform.as_mut(),
+    )
+}
+
+/// The id indexes of the inputs, if they are all indexed files. Read pairs
+/// are always read in step, without the index.
+fn id_indexes(inputs: &Inputs) -> Result<Option<Vec<IdIndex>>, Box<dyn Error>> {
+    if inputs.paired() {
+        return Ok(None);
+    }
+    let mut indexes = vec![];
+    for input in inputs.files.iter() {
+        let Input::File(path) = input else {
+            return Ok(None);
+        };
+        match IdIndex::open(path)? {
+            Some(index) => indexes.push(index),
+            None => return Ok(None),
+        }
+    }
+
+    Ok(Some(indexes))
 }
 
-pub fn map_rename_sequences(
-    input: Option<PathBuf>,
-    map_file: Option<PathBuf>,
+/// Select records through the id indexes of the inputs. Records are written
+/// in the order of `ids`, file by file, and missing ids are ignored like when
+/// scanning the files.
+fn select_indexed(
+    indexes: Vec<IdIndex>,
+    ids: Vec<String>,
     out: Option<PathBuf>,
-    line_ending: LineEnding,
+    to: Option<Format>,
+    output: &Output,
 ) -> Result<(), Box<dyn Error>> {
-    let mut new_names: HashMap<String, String> = HashMap::new();
-    let file = match map_file {
-        Some(path) => File::open(path)?,
-        None => {
-            return Err(
-                errors::MainError::new("You must specify a name-mapping file or --number").into(),
-            )
-        }
-    };
-    let buf_reader = BufReader::new(file);
-    for index in buf_reader.lines() {
-        if let Ok(index) = index {
-            let split: Vec<String> = index.split('\t').map(|s| s.to_owned()).collect();
-            if split.len() != 2 {
-                return Err(errors::MainError::new(
-                    "You must specify '<old_name>\\t<new_name} in your map rename file",
-                )
-                .into());
+    let mut seen = HashSet::new();
+    let ids: Vec<String> = ids
+        .into_iter()
+        .filter(|id| seen.insert(id.clone()))
+        .collect();
+
+    let mut writer = output.writer(out, to)?;
+    for mut index in indexes {
+        for id in ids.iter() {
+            for record in index.fetch(id)? {
+                writer.write(record)?;
             }
-            new_names.insert(split[0].clone(), split[1].clone());
-        } else {
-            return Err(errors::MainError::new("Error parsing map file.").into());
         }
     }
+    writer.finish()?;
 
-    let mut reader = init_reader(input)?;
-    let mut writer = match out {
-        Some(ref path) => Box::new(std::fs::File::create(Path::new(path))?) as Box<dyn Write>,
-        None => Box::new(std::io::stdout()) as Box<dyn Write>,
-    };
-
-    while let Some(r) = reader.next() {
-        let record = r?;
-        let (id, seq): (&[u8], &[u8]) = (record.id(), &record.seq());
-        let id_s = String::from(std::str::from_utf8(id)?);
+    Ok(())
+}
 
-        match new_names.get(&id_s) {
-            Some(new) => parser::write_fasta(new.as_bytes(), seq, &mut writer, line_ending)?,
-            None => parser::write_fasta(id, seq, &mut writer, line_ending)?,
+/// Build the id index of every input file
+pub fn index(inputs: &Inputs) -> Result<(), Box<dyn Error>> {
+    for input in inputs.files.iter() {
+        let Input::File(path) = input else {
+            return Err(SeqtoolsError::invalid_argument(
+                "Only files can be indexed, give them with --in",
+            )
+            .into());
         };
+        index::build(path)?;
     }
 
     Ok(())
 }
 
-pub fn index_rename_sequences(
-    input: Option<PathBuf>,
+/// Run several rewriting commands one after the other in a single pass.
+/// The output format is `to` if given, otherwise the format of the last
+/// step that specifies one, otherwise the format of the input records.
+pub fn pipe(
+    inputs: &Inputs,
+    steps: Vec<Commands>,
     out: Option<PathBuf>,
-    line_ending: LineEnding,
+    to: Option<Format>,
+    output: &Output,
 ) -> Result<(), Box<dyn Error>> {
-    let mut reader = init_reader(input)?;
-    let mut writer = match out {
-        Some(ref path) => Box::new(std::fs::File::create(Path::new(path))?) as Box<dyn Write>,
-        None => Box::new(std::io::stdout()) as Box<dyn Write>,
-    };
+    let mut chain = Chain::default();
+    let mut format = None;
 
-    let mut cursor: usize = 0;
-    while let Some(r) = reader.next() {
-        let record = r?;
-        let new_id = format!("{cursor}");
-        let seq: &[u8] = &record.seq();
-        parser::write_fasta(new_id.as_bytes(), seq, &mut writer, line_ending)?;
-        cursor += 1;
+    for step in steps {
+        if inputs.paired() && matches!(step, Commands::Subseq { .. }) {
+            return Err(SeqtoolsError::invalid_argument(
+                "subseq cannot be a step of a pipe over paired-end input",
+            )
+            .into());
+        }
+        let rewrite = build_rewrite(step)?.ok_or_else(|| {
+            SeqtoolsError::invalid_argument(
+                "Only commands that rewrite records can be used in a pipe",
+            )
+        })?;
+        if rewrite.out.is_some() {
+            return Err(SeqtoolsError::invalid_argument(
+                "Steps of a pipe cannot have their own output file, use the --out option of pipe",
+            )
+            .into());
+        }
+        format = rewrite.to.or(format);
+        chain.push(rewrite.transform);
     }
 
-    Ok(())
+    run_transform(inputs, out, to.or(format), output, &mut chain)
 }
 
-pub fn add_id(
-    input: Option<PathBuf>,
-    to_add: String,
-    as_prefix: bool,
+/// Write the mates of `--in` and `--in2` files interleaved in a single output
+pub fn interleave(
+    inputs: &Inputs,
     out: Option<PathBuf>,
-    line_ending: LineEnding,
+    to: Option<Format>,
+    output: &Output,
 ) -> Result<(), Box<dyn Error>> {
-    let mut reader = init_reader(input)?;
-    let mut writer = match out {
-        Some(ref path) => Box::new(std::fs::File::create(Path::new(path))?) as Box<dyn Write>,
-        None => Box::new(std::io::stdout()) as Box<dyn Write>,
-    };
-
-    while let Some(r) = reader.next() {
-        let record = r?;
-        let (id, seq): (&[u8], &[u8]) = (record.id(), &record.seq());
-        let new_id = if as_prefix {
-            format!("{to_add}{}", std::str::from_utf8(id)?)
-        } else {
-            format!("{}{to_add}", std::str::from_utf8(id)?)
-        };
-        parser::write_fasta(new_id.as_bytes(), seq, &mut writer, line_ending)?;
+    if !matches!(inputs.mates, Mates::Files(_)) {
+        return Err(SeqtoolsError::invalid_argument(
+            "interleave needs the files of mates 2, given with --in2",
+        )
+        .into());
+    }
+    if output.out2.is_some() {
+        return Err(SeqtoolsError::invalid_argument(
+            "interleave writes both mates to --out, --out2 cannot be used",
+        )
+        .into());
     }
 
-    Ok(())
+    let mut writer = PairWriter::new(output.writer(out, to)?, None);
+    transform::process_pairs(
+        &inputs.files,
+        &inputs.mates,
+        &inputs.errors,
+        &mut writer,
+        &mut Chain::default(),
+    )
 }
 
-pub fn trim(
-    input: Option<PathBuf>,
-    to_trim: usize,
-    from_start: bool,
+/// Write the mates of interleaved inputs to `out` and `--out2`
+pub fn deinterleave(
+    inputs: &Inputs,
     out: Option<PathBuf>,
-    line_ending: LineEnding,
+    to: Option<Format>,
+    output: &Output,
 ) -> Result<(), Box<dyn Error>> {
-    let mut reader = init_reader(input)?;
-    let mut writer = match out {
-        Some(ref path) => Box::new(std::fs::File::create(Path::new(path))?) as Box<dyn Write>,
-        None => Box::new(std::io::stdout()) as Box<dyn Write>,
-    };
-
-    while let Some(r) = reader.next() {
-        let record = r?;
-        let (id, seq): (&[u8], &[u8]) = (record.id(), &record.seq());
+    if matches!(inputs.mates, Mates::Files(_)) {
+        return Err(SeqtoolsError::invalid_argument(
+            "deinterleave reads interleaved files, --in2 cannot be used",
+        )
+        .into());
+    }
+    let out2 = output.out2.clone().ok_or_else(|| {
+        SeqtoolsError::invalid_argument("deinterleave needs the output file of mates 2, --out2")
+    })?;
+
+    let mut writer = PairWriter::new(
+        output.writer(out, to)?,
+        Some(output.writer(Some(out2), to)?),
+    );
+    transform::process_pairs(
+        &inputs.files,
+        &Mates::Interleaved,
+        &inputs.errors,
+        &mut writer,
+        &mut Chain::default(),
+    )
+}
 
-        if from_start {
-            parser::write_fasta(id, &seq[to_trim..seq.len()], &mut writer, line_ending)?;
+/// Pair up mates by read name and write the reads without a mate to
+/// `singletons`
+pub fn repair(
+    inputs: &Inputs,
+    out: Option<PathBuf>,
+    singletons: Option<PathBuf>,
+    to: Option<Format>,
+    output: &Output,
+) -> Result<(), Box<dyn Error>> {
+    let writer2 = match &output.out2 {
+        Some(out2) => Some(output.writer(Some(out2.clone()), to)?),
+        None => None,
+    };
+    let mut writer = PairWriter::new(output.writer(out, to)?, writer2);
+    let mut repair = Repair::new();
+    let mut format = None;
+
+    for (i, input) in inputs.files.iter().enumerate() {
+        let mut reader = inputs.open(input)?;
+        format = format.or(reader.format());
+        writer.set_input_format(reader.format());
+        if let Mates::Files(files) = &inputs.mates {
+            // Read both files in step, so that mates that are still in sync
+            // do not wait in memory
+            let mut mates = inputs.open(&files[i])?;
+            loop {
+                let first = reader.next().transpose()?;
+                let second = mates.next().transpose()?;
+                if first.is_none() && second.is_none() {
+                    break;
+                }
+                if let Some(pair) = first.and_then(|record| repair.push_first(record)) {
+                    writer.write(pair)?;
+                }
+                if let Some(pair) = second.and_then(|record| repair.push_second(record)) {
+                    writer.write(pair)?;
+                }
+            }
         } else {
-            parser::write_fasta(id, &seq[0..seq.len() - to_trim], &mut writer, line_ending)?;
+            for record in reader {
+                if let Some(pair) = repair.push(record?) {
+                    writer.write(pair)?;
+                }
+            }
         }
     }
+    writer.finish()?;
+
+    let orphans = repair.orphans();
+    match singletons {
+        Some(path) => {
+            let mut writer = output.writer(Some(path), to)?;
+            writer.set_input_format(format);
+            for record in orphans {
+                writer.write(record)?;
+            }
+            writer.finish()?;
+        }
+        None if !orphans.is_empty() => eprintln!(
+            "Dropped {} reads without a mate, keep them with --singletons",
+            orphans.len()
+        ),
+        None => {}
+    }
 
     Ok(())
 }
 
-pub fn clip(
-    input: Option<PathBuf>,
-    max_len: usize,
+/// Write the sequences of the features of GenBank or EMBL inputs whose key is
+/// in `keys`
+pub fn features(
+    inputs: &Inputs,
+    keys: Vec<String>,
     out: Option<PathBuf>,
-    line_ending: LineEnding,
+    to: Format,
+    output: &Output,
 ) -> Result<(), Box<dyn Error>> {
-    let mut reader = init_reader(input)?;
-    let mut writer = match out {
-        Some(ref path) => Box::new(std::fs::File::create(Path::new(path))?) as Box<dyn Write>,
-        None => Box::new(std::io::stdout()) as Box<dyn Write>,
-    };
-
-    while let Some(r) = reader.next() {
-        let record = r?;
-        let (id, seq): (&[u8], &[u8]) = (record.id(), &record.seq());
-        let len = seq.len().min(max_len);
-        parser::write_fasta(id, &seq[0..len], &mut writer, line_ending)?;
+    let mut writer = output.writer(out, Some(to))?;
+    for input in inputs.files.iter() {
+        for (index, entry) in input.open_flatfile()?.enumerate() {
+            let location = Location {
+                file: Some(input.to_string()),
+                record: Some(index),
+                line: None,
+            };
+            let (entry, line) = entry.map_err(|e| e.with_location(location.clone()))?;
+            let records = flatfile::feature_records(&entry, &keys).map_err(|e| {
+                e.with_location(Location {
+                    line: Some(line),
+                    ..location
+                })
+            })?;
+            for record in records {
+                writer.write(record)?;
+            }
+        }
     }
+    writer.finish()?;
 
     Ok(())
 }
 
-pub fn check_duplicates(input: Option<PathBuf>, show_names: bool) -> Result<(), Box<dyn Error>> {
-    let mut reader = init_reader(input)?;
-
-    let mut duplicates = HashMap::new();
+/// Build the index of the input file, or extract regions through it
+pub fn faidx(
+    inputs: &Inputs,
+    regions: Vec<String>,
+    bed: Option<PathBuf>,
+    out: Option<PathBuf>,
+    output: &Output,
+) -> Result<(), Box<dyn Error>> {
+    let path = match &inputs.files[..] {
+        [Input::File(path)] => path,
+        _ => {
+            return Err(SeqtoolsError::invalid_argument(
+                "faidx needs a single input file, given with --in",
+            )
+            .into())
+        }
+    };
+    if regions.is_empty() && bed.is_none() {
+        IndexedReader::create(path)?;
+        return Ok(());
+    }
 
-    while let Some(r) = reader.next() {
-        let record = r?;
-        let seq = String::from_utf8(record.seq().to_vec())?;
-        let id = String::from_utf8(record.id().to_vec())?;
+    let mut reader = IndexedReader::open(path)?;
+    let mut to_fetch = vec![];
+    for region in regions {
+        to_fetch.push(Region::parse(&region, reader.index())?);
+    }
+    if let Some(bed) = bed {
+        to_fetch.extend(Region::read_bed(&bed)?);
+    }
 
-        let entry = duplicates.entry(seq.clone()).or_insert(vec![]);
-        (*entry).push(id.trim().to_string());
+    let mut writer = output.writer(out, None)?;
+    for region in to_fetch {
+        writer.write(reader.fetch(&region)?)?;
     }
+    writer.finish()?;
 
-    let mut count = 0;
-    let mut duplicated = vec![];
-    for v in duplicates.values() {
-        if v.len() > 1 {
-            count += v.len();
-            duplicated.push(v);
-        }
+    Ok(())
+}
+
+pub fn check_duplicates(
+    inputs: &Inputs,
+    show_names: bool,
+    format: OutputFormat,
+) -> Result<(), Box<dyn Error>> {
+    let mut groups = BTreeMap::new();
+    let mut files = vec![];
+    for input in inputs.files.iter() {
+        let file_groups = stats::group_by_sequence(&mut inputs.open(input)?)?;
+        files.push((
+            input.to_string(),
+            DuplicateGroups::from_groups(&file_groups),
+        ));
+        stats::merge_groups(&mut groups, file_groups);
     }
+    let duplicates = DuplicateGroups::from_groups(&groups);
 
-    if show_names {
-        let json = json!({
-            "duplicates": duplicated,
-            "total": count,
+    if format != OutputFormat::Text {
+        if inputs.len() > 1 {
+            files.push(("total".to_string(), duplicates));
+        }
+        let table = if show_names {
+            let mut table = Table::new(&["file", "group", "size", "ids"]);
+            for (file, file_duplicates) in files {
+                for (i, ids) in file_duplicates.groups.iter().enumerate() {
+                    table.push(vec![json!(file), json!(i), json!(ids.len()), json!(ids)]);
+                }
+            }
+            table
+        } else {
+            let mut table = Table::new(&["file", "duplicates"]);
+            for (file, file_duplicates) in files {
+                table.push(vec![json!(file), json!(file_duplicates.total())]);
+            }
+            table
+        };
+        table.print(format)?;
+    } else if show_names {
+        let mut json = json!({
+            "duplicates": duplicates.groups,
+            "total": duplicates.total(),
         });
+        if inputs.len() > 1 {
+            json["files"] = files
+                .iter()
+                .map(|(file, file_duplicates)| {
+                    json!({
+                        "file": file,
+                        "duplicates": file_duplicates.groups,
+                        "total": file_duplicates.total(),
+                    })
+                })
+                .collect();
+        }
         println!("{}", json)
+    } else if inputs.len() > 1 {
+        for (file, file_duplicates) in files {
+            println!("{file}\t{}", file_duplicates.total());
+        }
+        println!("total\t{}", duplicates.total());
     } else {
-        println!("{count}");
+        println!("{}", duplicates.total());
     }
 
     Ok(())
 }
 
-pub fn remove_duplicates(
-    input: Option<PathBuf>,
-    out: Option<PathBuf>,
-    verbose: u8,
-    line_ending: LineEnding,
-) -> Result<(), Box<dyn Error>> {
-    let mut reader = init_reader(input)?;
-    let mut writer = match out {
-        Some(ref path) => Box::new(std::fs::File::create(Path::new(path))?) as Box<dyn Write>,
-        None => Box::new(std::io::stdout()) as Box<dyn Write>,
+pub fn view_alignment(inputs: &Inputs) -> Result<(), Box<dyn Error>> {
+    let input = match inputs.files.as_slice() {
+        [input @ Input::File(_)] => input,
+        [Input::Stdin] => return Err(SeqtoolsError::invalid_argument("Alignment file must be specified. Reading from stdin is not supported when using the view command.").into()),
+        _ => return Err(SeqtoolsError::invalid_argument("The view command can only show a single alignment file.").into()),
     };
 
-    let mut duplicates = BTreeMap::new();
-    let mut removed = vec![];
+    let mut reader = inputs.open(input)?;
+    let mut ids = vec![];
+    let mut seqs = vec![];
+    while let Some(record) = reader.next() {
+        let record = record?;
+        let id = String::from_utf8_lossy(&record.id).trim().to_string();
+        let seq = String::from_utf8(record.seq).map_err(|e| {
+            SeqtoolsError::record(format!("Error reading sequence: {e}"), id.as_bytes())
+                .with_location(reader.location())
+        })?;
+        ids.push(id);
+        seqs.push(seq);
+    }
+
+    viewer::render_view(ids, seqs, input.to_string())?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use super::*;
+
+    /// A directory in the temporary directory, removed with its files
+    struct TempDir(PathBuf);
 
-    while let Some(r) = reader.next() {
-        let record = r?;
-        let seq = String::from_utf8(record.seq().to_vec())?;
-        let id = String::from_utf8(record.id().to_vec())?;
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("seqtools-{}-{name}", std::process::id()));
+            fs::create_dir_all(&path).unwrap();
+            TempDir(path)
+        }
 
-        let entry = duplicates.entry(seq.clone()).or_insert(vec![]);
-        (*entry).push(id.trim().to_string());
+        fn file(&self, name: &str, content: &str) -> PathBuf {
+            let path = self.0.join(name);
+            fs::write(&path, content).unwrap();
+            path
+        }
     }
 
-    let grouped_sequences: Vec<_> = duplicates
-        .into_iter()
-        .map(|(seq, mut ids)| {
-            ids.sort();
-            (seq, ids)
-        })
-        .collect();
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
 
-    for (seq, ids) in grouped_sequences {
-        for (i, id) in ids.iter().enumerate() {
-            if i == 0 {
-                parser::write_fasta(id.as_bytes(), seq.as_bytes(), &mut writer, line_ending)?;
-            } else {
-                removed.push(id.clone())
-            }
+    fn output(out2: Option<PathBuf>) -> Output {
+        Output {
+            compression: None,
+            line_ending: LineEnding::Unix,
+            line_width: 0,
+            phylip_interleaved: false,
+            out2,
         }
     }
 
-    if verbose > 0 {
-        eprint!("{}", removed.len());
-        if verbose > 1 {
-            eprint!(": {}", removed.join(" "));
+    fn inputs(files: &[&PathBuf], mates: Mates) -> Inputs {
+        Inputs {
+            files: files
+                .iter()
+                .map(|path| Input::File(path.to_path_buf()))
+                .collect(),
+            mates,
+            errors: ErrorHandler::default(),
         }
-        eprintln!()
     }
 
-    Ok(())
-}
+    fn ids(path: &Path) -> Vec<String> {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .filter_map(|line| line.strip_prefix('>'))
+            .map(String::from)
+            .collect()
+    }
 
-pub fn view_alignment(input: Option<PathBuf>) -> Result<(), Box<dyn Error>> {
-    let mut reader = match input.clone() {
-        Some(path) => needletail::parse_fastx_file(path),
-        None => return Err("Alignment file must be specified. Reading from stdin is not supported when using the view command.".into()),
-    }?;
+    #[test]
+    fn interleaves_and_deinterleaves() {
+        let dir = TempDir::new("interleave");
+        let r1 = dir.file("r1.fa", ">a/1\nAC\n>b/1\nGG\n");
+        let r2 = dir.file("r2.fa", ">a/2\nGT\n>b/2\nCC\n");
+        let interleaved = dir.0.join("interleaved.fa");
+
+        let mates = Mates::Files(vec![Input::File(r2.clone())]);
+        interleave(
+            &inputs(&[&r1], mates),
+            Some(interleaved.clone()),
+            None,
+            &output(None),
+        )
+        .unwrap();
+        assert_eq!(ids(&interleaved), ["a/1", "a/2", "b/1", "b/2"]);
+
+        let (out1, out2) = (dir.0.join("out1.fa"), dir.0.join("out2.fa"));
+        deinterleave(
+            &inputs(&[&interleaved], Mates::None),
+            Some(out1.clone()),
+            None,
+            &output(Some(out2.clone())),
+        )
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(out1).unwrap(),
+            fs::read_to_string(&r1).unwrap()
+        );
+        assert_eq!(
+            fs::read_to_string(out2).unwrap(),
+            fs::read_to_string(&r2).unwrap()
+        );
 
-    let mut ids = vec![];
-    let mut seqs = vec![];
-    while let Some(r) = reader.next() {
-        let record = r.unwrap();
-        seqs.push(String::from_utf8(record.seq().to_vec()).unwrap());
-        ids.push(
-            String::from_utf8(record.id().to_vec())
-                .unwrap()
-                .trim()
-                .to_string(),
+        assert!(deinterleave(
+            &inputs(&[&interleaved], Mates::None),
+            None,
+            None,
+            &output(None)
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn repairs_files_with_orphans() {
+        let dir = TempDir::new("repair-files");
+        let r1 = dir.file("r1.fa", ">a/1\nA\n>b/1\nC\n>c/1\nG\n>d/1\nT\n");
+        let r2 = dir.file("r2.fa", ">c/2\nG\n>a/2\nA\n>e/2\nT\n>b/2\nC\n>f/2\nA\n");
+        let (out1, out2) = (dir.0.join("out1.fa"), dir.0.join("out2.fa"));
+        let singletons = dir.0.join("singletons.fa");
+
+        let mates = Mates::Files(vec![Input::File(r2)]);
+        repair(
+            &inputs(&[&r1], mates),
+            Some(out1.clone()),
+            Some(singletons.clone()),
+            None,
+            &output(Some(out2.clone())),
+        )
+        .unwrap();
+        assert_eq!(ids(&out1), ["a/1", "c/1", "b/1"]);
+        assert_eq!(ids(&out2), ["a/2", "c/2", "b/2"]);
+        assert_eq!(ids(&singletons), ["e/2", "d/1", "f/2"]);
+    }
+
+    #[test]
+    fn repairs_interleaved_files() {
+        let dir = TempDir::new("repair-interleaved");
+        let input = dir.file("in.fa", ">a/1\nA\n>b/1\nC\n>a/2\nA\n>c/2\nG\n>b/2\nC\n");
+        let (out, singletons) = (dir.0.join("out.fa"), dir.0.join("singletons.fa"));
+
+        repair(
+            &inputs(&[&input], Mates::Interleaved),
+            Some(out.clone()),
+            Some(singletons.clone()),
+            None,
+            &output(None),
+        )
+        .unwrap();
+        assert_eq!(ids(&out), ["a/1", "a/2", "b/1", "b/2"]);
+        assert_eq!(ids(&singletons), ["c/2"]);
+    }
+
+    fn pipe_steps(args: &str) -> Vec<Commands> {
+        crate::parse_steps(args.split(' ').map(String::from).collect()).unwrap()
+    }
+
+    #[test]
+    fn pipes_steps_in_a_single_pass() {
+        let dir = TempDir::new("pipe");
+        let reads = dir.file(
+            "reads.fq",
+            "@a\nACGTA\n+\nABCDE\n@b\nTTTT\n+\nIIII\n@c\nGG\n+\n!!\n",
+        );
+        let out = dir.0.join("out.fq");
+
+        let steps = pipe_steps("select a c, trim 1 --from-start, add-id _x");
+        pipe(
+            &inputs(&[&reads], Mates::None),
+            steps,
+            Some(out.clone()),
+            None,
+            &output(None),
+        )
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(&out).unwrap(),
+            "@a_x\nCGTA\n+\nBCDE\n@c_x\nG\n+\n!\n"
+        );
+
+        let steps = "convert --to fasta, clip 2";
+        pipe(
+            &inputs(&[&reads], Mates::None),
+            pipe_steps(steps),
+            Some(out.clone()),
+            None,
+            &output(None),
+        )
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(&out).unwrap(),
+            ">a\nAC\n>b\nTT\n>c\nGG\n"
+        );
+        pipe(
+            &inputs(&[&reads], Mates::None),
+            pipe_steps(steps),
+            Some(out.clone()),
+            Some(Format::Tsv),
+            &output(None),
+        )
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(&out).unwrap(),
+            "a\tAC\tAB\nb\tTT\tII\nc\tGG\t!!\n"
         );
     }
 
-    let title = match input {
-        Some(p) => p
-            .to_str()
-            .ok_or("Could not convert path to string")?
-            .to_string(),
-        None => "Reading from Stdin".to_string(),
-    };
+    #[test]
+    fn pipes_read_pairs() {
+        let dir = TempDir::new("pipe-pairs");
+        let r1 = dir.file("r1.fa", ">a/1\nACGT\n>b/1\nGGGG\n");
+        let r2 = dir.file("r2.fa", ">a/2\nTTTT\n>b/2\nCCCC\n");
+        let (out1, out2) = (dir.0.join("out1.fa"), dir.0.join("out2.fa"));
+
+        let mates = Mates::Files(vec![Input::File(r2)]);
+        pipe(
+            &inputs(&[&r1], mates),
+            pipe_steps("select b, clip 2"),
+            Some(out1.clone()),
+            None,
+            &output(Some(out2.clone())),
+        )
+        .unwrap();
+        assert_eq!(fs::read_to_string(out1).unwrap(), ">b/1\nGG\n");
+        assert_eq!(fs::read_to_string(out2).unwrap(), ">b/2\nCC\n");
+    }
 
-    viewer::render_view(ids, seqs, title).unwrap();
+    #[test]
+    fn rejects_invalid_pipe_steps() {
+        let dir = TempDir::new("pipe-errors");
+        let reads = dir.file("reads.fa", ">a/1\nACGT\n");
+        let out = dir.0.join("out.fa");
+        let run = |steps: &str, mates: Mates| {
+            let err = pipe(
+                &inputs(&[&reads], mates),
+                pipe_steps(steps),
+                Some(out.clone()),
+                None,
+                &output(None),
+            )
+            .unwrap_err();
+            err.downcast::<SeqtoolsError>().unwrap().exit_code()
+        };
 
-    Ok(())
+        assert_eq!(run("clip 2, count", Mates::None), 2);
+        assert_eq!(run("clip 2 --out other.fa", Mates::None), 2);
+        assert_eq!(run("subseq a:1-2", Mates::Interleaved), 2);
+    }
 }
@@ -1,46 +1,325 @@
+//! Errors returned by seqtools, along with the place in the input where they
+//! happened and the exit code the command line utility uses for them.
 use std::error::Error;
 use std::fmt::{self, Display};
+use std::io;
 
-#[derive(Debug)]
-pub struct MainError {
-    details: String,
+use needletail::errors::{ParseError, ParseErrorKind};
+
+/// Exit code for errors that seqtools does not know about
+pub const EXIT_FAILURE: i32 = 1;
+
+/// Position of the record that caused an error
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Location {
+    /// Name of the input file, `stdin` when reading from stdin
+    pub file: Option<String>,
+    /// 0-based index of the record in the file
+    pub record: Option<usize>,
+    /// Line where the record starts, starting at 1
+    pub line: Option<u64>,
+}
+
+impl Location {
+    fn is_empty(&self) -> bool {
+        self.file.is_none() && self.record.is_none() && self.line.is_none()
+    }
+
+    /// Fill in the fields that are missing from `other`
+    fn merge(self, other: Location) -> Location {
+        Location {
+            file: self.file.or(other.file),
+            record: self.record.or(other.record),
+            line: self.line.or(other.line),
+        }
+    }
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = vec![];
+        if let Some(file) = &self.file {
+            parts.push(format!("file {file}"));
+        }
+        if let Some(record) = self.record {
+            parts.push(format!("record {record}"));
+        }
+        if let Some(line) = self.line {
+            parts.push(format!("line {line}"));
+        }
+        write!(f, "{}", parts.join(", "))
+    }
 }
 
 #[derive(Debug)]
-pub struct SeqError {
-    details: String,
-    id: String,
+pub enum SeqtoolsError {
+    /// The input is not valid FASTX
+    Parse { message: String, location: Location },
+    /// A file or stream could not be read or written
+    Io {
+        source: io::Error,
+        location: Location,
+    },
+    /// An argument, option or option file given by the user is invalid
+    InvalidArgument(String),
+    /// A record was read correctly but could not be processed
+    Record {
+        message: String,
+        id: String,
+        location: Location,
+    },
 }
 
-impl MainError {
-    pub fn new(msg: &str) -> Self {
-        MainError {
-            details: String::from(msg),
+impl SeqtoolsError {
+    pub fn invalid_argument(msg: impl Into<String>) -> Self {
+        SeqtoolsError::InvalidArgument(msg.into())
+    }
+
+    /// Error for a single record. Invalid UTF-8 in the id is replaced.
+    pub fn record(msg: impl Into<String>, id: &[u8]) -> Self {
+        SeqtoolsError::Record {
+            message: msg.into(),
+            id: String::from_utf8_lossy(id).into_owned(),
+            location: Location::default(),
+        }
+    }
+
+    /// Turn any error raised while processing the record `id` into a
+    /// `SeqtoolsError`, keeping the variant of errors that already have one.
+    pub fn in_record(err: Box<dyn Error>, id: &[u8], location: Location) -> Self {
+        let err = match err.downcast::<SeqtoolsError>() {
+            Ok(err) => return err.with_location(location),
+            Err(err) => err,
+        };
+        let err = match err.downcast::<ParseError>() {
+            Ok(err) => return SeqtoolsError::from(*err).with_location(location),
+            Err(err) => err,
+        };
+        let err = match err.downcast::<io::Error>() {
+            Ok(err) => return SeqtoolsError::from(*err).with_location(location),
+            Err(err) => err,
+        };
+        SeqtoolsError::record(err.to_string(), id).with_location(location)
+    }
+
+    /// Add location information that is not already known to the error
+    pub fn with_location(self, location: Location) -> Self {
+        match self {
+            SeqtoolsError::Parse {
+                message,
+                location: own,
+            } => SeqtoolsError::Parse {
+                message,
+                location: own.merge(location),
+            },
+            SeqtoolsError::Io {
+                source,
+                location: own,
+            } => SeqtoolsError::Io {
+                source,
+                location: own.merge(location),
+            },
+            SeqtoolsError::Record {
+                message,
+                id,
+                location: own,
+            } => SeqtoolsError::Record {
+                message,
+                id,
+                location: own.merge(location),
+            },
+            other => other,
+        }
+    }
+
+    pub fn location(&self) -> Option<&Location> {
+        match self {
+            SeqtoolsError::Parse { location, .. }
+            | SeqtoolsError::Io { location, .. }
+            | SeqtoolsError::Record { location, .. } => Some(location),
+            SeqtoolsError::InvalidArgument(_) => None,
+        }
+    }
+
+    /// Exit code of the command line utility for this error
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            SeqtoolsError::InvalidArgument(_) => 2,
+            SeqtoolsError::Parse { .. } => 3,
+            SeqtoolsError::Record { .. } => 4,
+            SeqtoolsError::Io { .. } => 5,
         }
     }
 }
 
-impl SeqError {
-    pub fn new(msg: &str, id: &[u8]) -> Self {
-        let id_s = std::str::from_utf8(id).unwrap();
-        SeqError {
-            details: String::from(msg),
-            id: String::from(id_s),
+/// Exit code for any error, using the codes of `SeqtoolsError` when possible
+pub fn exit_code(err: &(dyn Error + 'static)) -> i32 {
+    if let Some(err) = err.downcast_ref::<SeqtoolsError>() {
+        err.exit_code()
+    } else if let Some(err) = err.downcast_ref::<ParseError>() {
+        match err.kind {
+            ParseErrorKind::Io => 5,
+            _ => 3,
         }
+    } else if err.is::<io::Error>() {
+        5
+    } else {
+        EXIT_FAILURE
     }
 }
 
-impl Display for MainError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Error in main thread: {}", self.details)
+impl From<ParseError> for SeqtoolsError {
+    fn from(err: ParseError) -> Self {
+        let line = match err.position.line {
+            0 => None,
+            line => Some(line),
+        };
+        let location = Location {
+            line,
+            ..Default::default()
+        };
+
+        match err.kind {
+            ParseErrorKind::Io => SeqtoolsError::Io {
+                source: io::Error::other(err.msg),
+                location,
+            },
+            _ => SeqtoolsError::Parse {
+                message: err.to_string(),
+                location,
+            },
+        }
+    }
+}
+
+impl From<io::Error> for SeqtoolsError {
+    fn from(source: io::Error) -> Self {
+        SeqtoolsError::Io {
+            source,
+            location: Location::default(),
+        }
     }
 }
 
-impl Display for SeqError {
+impl Display for SeqtoolsError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Error for sequence {}: {}", self.id, self.details)
+        match self {
+            SeqtoolsError::Parse { message, .. } => write!(f, "Invalid input: {message}")?,
+            SeqtoolsError::Io { source, .. } => write!(f, "I/O error: {source}")?,
+            SeqtoolsError::InvalidArgument(message) => write!(f, "Invalid argument: {message}")?,
+            SeqtoolsError::Record { message, id, .. } => {
+                write!(f, "Error for sequence {id}: {message}")?
+            }
+        }
+
+        match self.location() {
+            Some(location) if !location.is_empty() => write!(f, " ({location})"),
+            _ => Ok(()),
+        }
     }
 }
 
-impl Error for MainError {}
-impl Error for SeqError {}
+impl Error for SeqtoolsError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SeqtoolsError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn location(file: &str, record: usize, line: u64) -> Location {
+        Location {
+            file: Some(file.to_string()),
+            record: Some(record),
+            line: Some(line),
+        }
+    }
+
+    fn parse_error() -> ParseError {
+        needletail::parse_fastx_reader(&b"@a\nACGT\n-\nIIII\n"[..])
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap_err()
+    }
+
+    #[test]
+    fn has_exit_codes() {
+        let record = SeqtoolsError::record("too short", b"a");
+        let io = SeqtoolsError::from(io::Error::other("disk full"));
+        assert_eq!(SeqtoolsError::invalid_argument("bad").exit_code(), 2);
+        assert_eq!(SeqtoolsError::from(parse_error()).exit_code(), 3);
+        assert_eq!(record.exit_code(), 4);
+        assert_eq!(io.exit_code(), 5);
+
+        let boxed: Box<dyn Error> = Box::new(record);
+        assert_eq!(exit_code(boxed.as_ref()), 4);
+        assert_eq!(exit_code(&parse_error()), 3);
+        assert_eq!(exit_code(&io::Error::other("disk full")), 5);
+        assert_eq!(exit_code(&std::fmt::Error), EXIT_FAILURE);
+    }
+
+    #[test]
+    fn displays_locations() {
+        let err =
+            SeqtoolsError::record("too short", b"a\xFF").with_location(location("reads.fa", 2, 5));
+        assert_eq!(
+            err.to_string(),
+            "Error for sequence a\u{FFFD}: too short (file reads.fa, record 2, line 5)"
+        );
+        assert_eq!(
+            SeqtoolsError::invalid_argument("bad")
+                .with_location(location("a", 0, 1))
+                .to_string(),
+            "Invalid argument: bad"
+        );
+        assert_eq!(
+            SeqtoolsError::from(io::Error::other("disk full")).to_string(),
+            "I/O error: disk full"
+        );
+        assert!(SeqtoolsError::from(io::Error::other("disk full"))
+            .source()
+            .is_some());
+    }
+
+    #[test]
+    fn keeps_known_locations() {
+        let err = SeqtoolsError::from(parse_error());
+        assert_eq!(err.location().unwrap().line, Some(3));
+        let err = err.with_location(location("reads.fq", 0, 1));
+        assert_eq!(err.location(), Some(&location("reads.fq", 0, 3)));
+        assert!(err.to_string().starts_with("Invalid input: "));
+        assert!(err
+            .to_string()
+            .ends_with("(file reads.fq, record 0, line 3)"));
+    }
+
+    #[test]
+    fn converts_errors_of_records() {
+        let here = location("reads.fa", 1, 3);
+        let err = SeqtoolsError::in_record("too short".into(), b"b", here.clone());
+        assert_eq!(err.exit_code(), 4);
+        assert_eq!(
+            err.to_string(),
+            "Error for sequence b: too short (file reads.fa, record 1, line 3)"
+        );
+
+        let err = SeqtoolsError::in_record(
+            Box::new(SeqtoolsError::invalid_argument("bad")),
+            b"b",
+            here.clone(),
+        );
+        assert_eq!(err.exit_code(), 2);
+        let err =
+            SeqtoolsError::in_record(Box::new(io::Error::other("closed")), b"b", here.clone());
+        assert_eq!(err.exit_code(), 5);
+        assert_eq!(err.location(), Some(&here));
+        let err = SeqtoolsError::in_record(Box::new(parse_error()), b"b", here);
+        assert_eq!(err.exit_code(), 3);
+    }
+}
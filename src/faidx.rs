@@ -0,0 +1,766 @@
+//! Random access to regions of FASTA and FASTQ files through a samtools
+//! compatible `.fai` index. Files compressed with bgzip (BGZF) also get a
+//! `.gzi` index of their compressed blocks, so that regions can be read
+//! without decompressing the whole file.
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::errors::{Location, SeqtoolsError};
+use crate::intervals;
+use crate::record::Record;
+use crate::GZIP_MAGIC;
+
+/// Size of the header of a BGZF block, up to its `BSIZE` field
+const BGZF_HEADER_LEN: usize = 18;
+
+/// A line of the `.fai` index: where the sequence of a record starts and
+/// how its lines are laid out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FaiEntry {
+    pub name: String,
+    /// Number of bases of the sequence
+    pub length: u64,
+    /// Offset of the first base in the uncompressed file
+    pub offset: u64,
+    /// Number of bases per line
+    pub line_bases: u64,
+    /// Number of bytes per line, including the line ending
+    pub line_width: u64,
+    /// Offset of the first quality score, for FASTQ files
+    pub qual_offset: Option<u64>,
+}
+
+impl FaiEntry {
+    /// Offset in the file of the `i`-th base, or quality score, of the record
+    fn position(&self, start: u64, i: u64) -> u64 {
+        start + i / self.line_bases * self.line_width + i % self.line_bases
+    }
+}
+
+/// The `.fai` index of a file
+#[derive(Debug, Clone, Default)]
+pub struct FastaIndex {
+    pub entries: Vec<FaiEntry>,
+    by_name: HashMap<String, usize>,
+}
+
+/// Scans a file line by line to build its index
+struct IndexBuilder {
+    index: FastaIndex,
+    current: Option<FaiEntry>,
+    /// Whether a line shorter than the others was seen in the current record,
+    /// which must then be its last line
+    short_line: bool,
+}
+
+impl IndexBuilder {
+    fn invalid(&self, message: String, line: u64) -> SeqtoolsError {
+        SeqtoolsError::Parse {
+            message,
+            location: Location {
+                record: Some(self.index.entries.len()),
+                line: Some(line),
+                ..Default::default()
+            },
+        }
+    }
+
+    fn start(&mut self, header: &[u8], offset: u64, line: u64) -> Result<(), SeqtoolsError> {
+        self.finish();
+        let header = String::from_utf8_lossy(&header[1..]);
+        let name = header.split_whitespace().next().unwrap_or_default();
+        if self.index.by_name.contains_key(name) {
+            return Err(self.invalid(format!("Duplicate sequence name {name}"), line));
+        }
+        self.current = Some(FaiEntry {
+            name: name.to_string(),
+            length: 0,
+            offset,
+            line_bases: 0,
+            line_width: 0,
+            qual_offset: None,
+        });
+        self.short_line = false;
+
+        Ok(())
+    }
+
+    /// Add a sequence line, `width` bytes long with `bases` bases
+    fn add_line(&mut self, bases: u64, width: u64, line: u64) -> Result<(), SeqtoolsError> {
+        let Some(entry) = self.current.as_mut() else {
+            return Err(self.invalid("Sequence before the first header".to_string(), line));
+        };
+        if entry.line_bases == 0 {
+            entry.line_bases = bases;
+            entry.line_width = width;
+        } else if bases > 0 && (self.short_line || bases > entry.line_bases) {
+            let name = entry.name.clone();
+            return Err(self.invalid(format!("Different line length in sequence {name}"), line));
+        }
+        self.short_line |= bases < entry.line_bases;
+        entry.length += bases;
+
+        Ok(())
+    }
+
+    fn finish(&mut self) {
+        if let Some(entry) = self.current.take() {
+            self.index.push(entry);
+        }
+    }
+}
+
+/// Read a line along with its length in bytes, including the line ending
+fn read_line(reader: &mut dyn BufRead, line: &mut Vec<u8>) -> io::Result<u64> {
+    line.clear();
+    let width = reader.read_until(b'\n', line)?;
+    while line.last().is_some_and(|c| *c == b'\n' || *c == b'\r') {
+        line.pop();
+    }
+
+    Ok(width as u64)
+}
+
+impl FastaIndex {
+    fn push(&mut self, entry: FaiEntry) {
+        self.by_name.insert(entry.name.clone(), self.entries.len());
+        self.entries.push(entry);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&FaiEntry> {
+        self.by_name.get(name).map(|i| &self.entries[*i])
+    }
+
+    /// Index an uncompressed FASTA or FASTQ stream. All the lines of a
+    /// sequence but the last one must have the same length.
+    pub fn build(reader: &mut dyn BufRead) -> Result<Self, SeqtoolsError> {
+        let mut builder = IndexBuilder {
+            index: FastaIndex::default(),
+            current: None,
+            short_line: false,
+        };
+        let mut line = vec![];
+        let (mut offset, mut number) = (0, 0);
+        loop {
+            let width = read_line(reader, &mut line)?;
+            if width == 0 {
+                break;
+            }
+            offset += width;
+            number += 1;
+
+            let fastq = match line.first() {
+                Some(b'>') => {
+                    builder.start(&line, offset, number)?;
+                    false
+                }
+                Some(b'@') if builder.current.is_none() => {
+                    builder.start(&line, offset, number)?;
+                    true
+                }
+                _ => {
+                    builder.add_line(line.len() as u64, width, number)?;
+                    false
+                }
+            };
+
+            // The qualities of a FASTQ record follow its `+` line, with the
+            // same layout as the sequence
+            if fastq {
+                loop {
+                    let width = read_line(reader, &mut line)?;
+                    offset += width;
+                    number += 1;
+                    if width == 0 || line.first() == Some(&b'+') {
+                        break;
+                    }
+                    builder.add_line(line.len() as u64, width, number)?;
+                }
+                let Some(entry) = builder.current.as_mut() else {
+                    continue;
+                };
+                entry.qual_offset = Some(offset);
+                let mut quals = 0;
+                while quals < entry.length {
+                    let width = read_line(reader, &mut line)?;
+                    if width == 0 {
+                        let name = entry.name.clone();
+                        return Err(builder
+                            .invalid(format!("Missing qualities for sequence {name}"), number));
+                    }
+                    offset += width;
+                    number += 1;
+                    quals += line.len() as u64;
+                }
+                builder.finish();
+            }
+        }
+        builder.finish();
+
+        Ok(builder.index)
+    }
+
+    /// Read a `.fai` file
+    pub fn read(path: &Path) -> Result<Self, SeqtoolsError> {
+        let mut index = FastaIndex::default();
+        for (i, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+            let line = line?;
+            let invalid = || {
+                SeqtoolsError::invalid_argument(format!(
+                    "Line {} of {} is not a valid .fai line",
+                    i + 1,
+                    path.display()
+                ))
+            };
+            let fields: Vec<&str> = line.split('\t').collect();
+            if fields.len() != 5 && fields.len() != 6 {
+                return Err(invalid());
+            }
+            let number = |i: usize| fields[i].parse::<u64>().map_err(|_| invalid());
+            index.push(FaiEntry {
+                name: fields[0].to_string(),
+                length: number(1)?,
+                offset: number(2)?,
+                line_bases: number(3)?,
+                line_width: number(4)?,
+                qual_offset: fields.get(5).map(|_| number(5)).transpose()?,
+            });
+        }
+
+        Ok(index)
+    }
+
+    pub fn write(&self, writer: &mut dyn Write) -> io::Result<()> {
+        for entry in &self.entries {
+            write!(
+                writer,
+                "{}\t{}\t{}\t{}\t{}",
+                entry.name, entry.length, entry.offset, entry.line_bases, entry.line_width
+            )?;
+            if let Some(qual_offset) = entry.qual_offset {
+                write!(writer, "\t{qual_offset}")?;
+            }
+            writeln!(writer)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A region of a sequence, with 0-based half-open coordinates
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Region {
+    pub name: String,
+    pub start: u64,
+    /// End of the region, the end of the sequence if not given
+    pub end: Option<u64>,
+}
+
+impl Region {
+    /// Parse a `name`, `name:start` or `name:start-end` region, with 1-based
+    /// inclusive positions. Names that contain `:` are looked up in `index`
+    /// as a whole first.
+    pub fn parse(text: &str, index: &FastaIndex) -> Result<Region, SeqtoolsError> {
+        if index.get(text).is_some() {
+            return Ok(Region {
+                name: text.to_string(),
+                start: 0,
+                end: None,
+            });
+        }
+        Region::parse_coordinates(text)
+    }
+
+    /// Parse a region like [`Region::parse`], taking everything before the
+    /// last `:` as the name
+    pub fn parse_coordinates(text: &str) -> Result<Region, SeqtoolsError> {
+        let invalid = || SeqtoolsError::invalid_argument(format!("Invalid region '{text}'"));
+        let Some((name, range)) = text.rsplit_once(':') else {
+            return Ok(Region {
+                name: text.to_string(),
+                start: 0,
+                end: None,
+            });
+        };
+
+        let position = |value: &str| {
+            value
+                .replace(',', "")
+                .parse::<u64>()
+                .ok()
+                .filter(|p| *p > 0)
+                .ok_or_else(invalid)
+        };
+        let (start, end) = match range.split_once('-') {
+            Some((start, end)) => (position(start)?, Some(position(end)?)),
+            None => (position(range)?, None),
+        };
+        if end.is_some_and(|end| end < start) {
+            return Err(invalid());
+        }
+
+        Ok(Region {
+            name: name.to_string(),
+            start: start - 1,
+            end,
+        })
+    }
+
+    /// Regions of a BED file, with 0-based half-open coordinates. Header,
+    /// track and comment lines are skipped.
+    pub fn read_bed(path: &Path) -> Result<Vec<Region>, SeqtoolsError> {
+        let features = intervals::read_bed(path)?;
+        Ok(features.into_iter().map(|feature| feature.region).collect())
+    }
+}
+
+impl Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.end {
+            Some(end) => write!(f, "{}:{}-{end}", self.name, self.start + 1),
+            None if self.start > 0 => write!(f, "{}:{}", self.name, self.start + 1),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+/// Offsets of the BGZF blocks of a file, as stored in `.gzi` files
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GziIndex {
+    /// `(compressed, uncompressed)` offsets of the start of each block after
+    /// the first one
+    pub blocks: Vec<(u64, u64)>,
+}
+
+/// Whether the first bytes of a file are the header of a BGZF block: a gzip
+/// member with a `BC` extra field
+fn is_bgzf_header(header: &[u8]) -> bool {
+    header.len() >= BGZF_HEADER_LEN
+        && header.starts_with(&GZIP_MAGIC)
+        && header[3] & 4 != 0
+        && &header[12..14] == b"BC"
+}
+
+impl GziIndex {
+    /// Find the blocks of a BGZF file from their headers, without
+    /// decompressing them
+    pub fn build<R: Read + Seek>(file: &mut R) -> Result<Self, SeqtoolsError> {
+        let mut blocks = vec![];
+        let (mut compressed, mut uncompressed) = (0, 0);
+        let mut header = [0; BGZF_HEADER_LEN];
+        loop {
+            file.seek(SeekFrom::Start(compressed))?;
+            let read = file.read(&mut header)?;
+            if read == 0 {
+                break;
+            }
+            file.read_exact(&mut header[read..])?;
+            if !is_bgzf_header(&header) {
+                return Err(SeqtoolsError::Parse {
+                    message: format!("Invalid BGZF block at offset {compressed}"),
+                    location: Location::default(),
+                });
+            }
+
+            // The block ends with the size of its uncompressed data
+            let block_size = u16::from_le_bytes([header[16], header[17]]) as u64 + 1;
+            let mut size = [0; 4];
+            file.seek(SeekFrom::Start(compressed + block_size - 4))?;
+            file.read_exact(&mut size)?;
+
+            if compressed > 0 {
+                blocks.push((compressed, uncompressed));
+            }
+            compressed += block_size;
+            uncompressed += u32::from_le_bytes(size) as u64;
+        }
+
+        Ok(GziIndex { blocks })
+    }
+
+    /// Read a `.gzi` file: the number of blocks followed by their offsets, as
+    /// little-endian 64-bit integers
+    pub fn read(path: &Path) -> Result<Self, SeqtoolsError> {
+        let mut bytes = vec![];
+        File::open(path)?.read_to_end(&mut bytes)?;
+        let numbers: Vec<u64> = bytes
+            .chunks_exact(8)
+            .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap_or_default()))
+            .collect();
+        match numbers.split_first() {
+            Some((count, offsets)) if offsets.len() as u64 == count * 2 => Ok(GziIndex {
+                blocks: offsets
+                    .chunks_exact(2)
+                    .map(|pair| (pair[0], pair[1]))
+                    .collect(),
+            }),
+            _ => Err(SeqtoolsError::invalid_argument(format!(
+                "{} is not a valid .gzi file",
+                path.display()
+            ))),
+        }
+    }
+
+    pub fn write(&self, writer: &mut dyn Write) -> io::Result<()> {
+        writer.write_all(&(self.blocks.len() as u64).to_le_bytes())?;
+        for (compressed, uncompressed) in &self.blocks {
+            writer.write_all(&compressed.to_le_bytes())?;
+            writer.write_all(&uncompressed.to_le_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    /// Offsets of the block that contains the uncompressed `offset`
+    fn block_of(&self, offset: u64) -> (u64, u64) {
+        let i = self.blocks.partition_point(|(_, start)| *start <= offset);
+        match i {
+            0 => (0, 0),
+            i => self.blocks[i - 1],
+        }
+    }
+}
+
+/// Path of an index next to `path`, e.g. `genome.fa.fai`
+pub fn index_path(path: &Path, extension: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(extension);
+    PathBuf::from(name)
+}
+
+/// A file that can be read at any offset of its uncompressed content: an
+/// uncompressed file, or a BGZF compressed file along with its `.gzi` index
+pub struct SeekableFile {
+    file: File,
+    /// Block index of BGZF compressed files
+    gzi: Option<GziIndex>,
+}
+
+impl SeekableFile {
+    /// Open a file, reading its `.gzi` index if it is BGZF compressed. A
+    /// missing index, or any index if `rebuild` is set, is built and written
+    /// next to the file.
+    pub fn open(path: &Path, rebuild: bool) -> Result<Self, SeqtoolsError> {
+        let mut file = File::open(path)?;
+        let mut header = [0; BGZF_HEADER_LEN];
+        let read = file.read(&mut header)?;
+        let header = &header[..read];
+        let compressed = header.starts_with(&GZIP_MAGIC);
+        if compressed && !is_bgzf_header(header) {
+            return Err(SeqtoolsError::invalid_argument(
+                "Indexing needs an uncompressed or BGZF compressed file, compress it with bgzip instead of gzip",
+            ));
+        }
+
+        let gzi = if compressed {
+            let gzi_path = index_path(path, "gzi");
+            Some(if gzi_path.exists() && !rebuild {
+                GziIndex::read(&gzi_path)?
+            } else {
+                let gzi = GziIndex::build(&mut file)?;
+                let mut writer = File::create(gzi_path)?;
+                gzi.write(&mut writer)?;
+                gzi
+            })
+        } else {
+            None
+        };
+
+        Ok(SeekableFile { file, gzi })
+    }
+
+    /// The uncompressed content of the file, from its start
+    pub fn stream(&mut self) -> io::Result<Box<dyn BufRead + '_>> {
+        self.file.seek(SeekFrom::Start(0))?;
+        Ok(match self.gzi {
+            Some(_) => Box::new(BufReader::new(flate2::read::MultiGzDecoder::new(
+                &self.file,
+            ))),
+            None => Box::new(BufReader::new(&self.file)),
+        })
+    }
+
+    /// Read `len` bytes at an offset of the uncompressed content
+    pub fn read_at(&mut self, offset: u64, len: u64) -> io::Result<Vec<u8>> {
+        let mut bytes = vec![0; len as usize];
+        match &self.gzi {
+            None => {
+                self.file.seek(SeekFrom::Start(offset))?;
+                self.file.read_exact(&mut bytes)?;
+            }
+            Some(gzi) => {
+                let (compressed, uncompressed) = gzi.block_of(offset);
+                self.file.seek(SeekFrom::Start(compressed))?;
+                let mut decoder = flate2::read::MultiGzDecoder::new(BufReader::new(&self.file));
+                io::copy(
+                    &mut (&mut decoder).take(offset - uncompressed),
+                    &mut io::sink(),
+                )?;
+                decoder.read_exact(&mut bytes)?;
+            }
+        }
+
+        Ok(bytes)
+    }
+}
+
+/// Reads regions of an indexed FASTA or FASTQ file
+pub struct IndexedReader {
+    file: SeekableFile,
+    index: FastaIndex,
+}
+
+impl IndexedReader {
+    /// Open an uncompressed or BGZF compressed file along with its `.fai`
+    /// index, and its `.gzi` index if compressed. Missing indexes are built
+    /// and written next to the file.
+    pub fn open(path: &Path) -> Result<Self, SeqtoolsError> {
+        Self::open_indexed(path, false)
+    }
+
+    /// Build the indexes of a file and write them next to it, replacing
+    /// existing ones
+    pub fn create(path: &Path) -> Result<Self, SeqtoolsError> {
+        Self::open_indexed(path, true)
+    }
+
+    fn open_indexed(path: &Path, rebuild: bool) -> Result<Self, SeqtoolsError> {
+        let location = Location {
+            file: Some(path.display().to_string()),
+            ..Default::default()
+        };
+        let reader = Self::read_indexes(path, rebuild);
+
+        reader.map_err(|e| e.with_location(location))
+    }
+
+    fn read_indexes(path: &Path, rebuild: bool) -> Result<Self, SeqtoolsError> {
+        let mut file = SeekableFile::open(path, rebuild)?;
+        let fai_path = index_path(path, "fai");
+        let index = if fai_path.exists() && !rebuild {
+            FastaIndex::read(&fai_path)?
+        } else {
+            let index = FastaIndex::build(&mut file.stream()?)?;
+            let mut writer = io::BufWriter::new(File::create(fai_path)?);
+            index.write(&mut writer)?;
+            writer.flush()?;
+            index
+        };
+
+        Ok(IndexedReader { file, index })
+    }
+
+    pub fn index(&self) -> &FastaIndex {
+        &self.index
+    }
+
+    /// Read the characters of a record between two positions, skipping line
+    /// endings
+    fn read_range(
+        &mut self,
+        entry: &FaiEntry,
+        start: u64,
+        from: u64,
+        to: u64,
+    ) -> io::Result<Vec<u8>> {
+        if from >= to {
+            return Ok(vec![]);
+        }
+        let first = entry.position(start, from);
+        let last = entry.position(start, to - 1);
+        let mut bytes = self.file.read_at(first, last - first + 1)?;
+        bytes.retain(|c| *c != b'\n' && *c != b'\r');
+
+        Ok(bytes)
+    }
+
+    /// Sequence of a region, and its qualities for FASTQ files. The end of
+    /// the region is clipped to the end of the sequence.
+    pub fn fetch(&mut self, region: &Region) -> Result<Record, SeqtoolsError> {
+        let entry = self.index.get(&region.name).cloned().ok_or_else(|| {
+            SeqtoolsError::invalid_argument(format!("Unknown sequence '{}'", region.name))
+        })?;
+        let end = region.end.unwrap_or(entry.length).min(entry.length);
+        let start = region.start.min(end);
+
+        let seq = self.read_range(&entry, entry.offset, start, end)?;
+        let qual = match entry.qual_offset {
+            Some(offset) => Some(self.read_range(&entry, offset, start, end)?),
+            None => None,
+        };
+
+        Ok(Record::new(region.to_string().into_bytes(), seq, qual))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use flate2::write::GzEncoder;
+    use flate2::{Compression, GzBuilder};
+
+    use super::*;
+    use crate::testing::TempFile;
+
+    fn entry(name: &str, numbers: [u64; 4], qual_offset: Option<u64>) -> FaiEntry {
+        FaiEntry {
+            name: name.to_string(),
+            length: numbers[0],
+            offset: numbers[1],
+            line_bases: numbers[2],
+            line_width: numbers[3],
+            qual_offset,
+        }
+    }
+
+    /// A BGZF block holding `data`
+    fn bgzf_block(data: &[u8]) -> Vec<u8> {
+        let mut encoder: GzEncoder<Vec<u8>> = GzBuilder::new()
+            .extra(vec![b'B', b'C', 2, 0, 0, 0])
+            .write(vec![], Compression::default());
+        encoder.write_all(data).unwrap();
+        let mut block = encoder.finish().unwrap();
+        let size = (block.len() - 1) as u16;
+        block[16..18].copy_from_slice(&size.to_le_bytes());
+        block
+    }
+
+    #[test]
+    fn indexes_fasta() {
+        let text = ">a desc\nACGTA\nCG\n>b\r\nAAAA\r\n";
+        let index = FastaIndex::build(&mut text.as_bytes()).unwrap();
+        assert_eq!(
+            index.entries,
+            [
+                entry("a", [7, 8, 5, 6], None),
+                entry("b", [4, 21, 4, 6], None)
+            ]
+        );
+        assert_eq!(index.get("b"), Some(&index.entries[1]));
+        assert_eq!(index.get("c"), None);
+
+        let mut out = vec![];
+        index.write(&mut out).unwrap();
+        assert_eq!(out, b"a\t7\t8\t5\t6\nb\t4\t21\t4\t6\n");
+    }
+
+    #[test]
+    fn indexes_fastq() {
+        let text = "@r1\nACGT\n+\nIIII\n@r2\nAC\n+r2\n@!\n";
+        let index = FastaIndex::build(&mut text.as_bytes()).unwrap();
+        assert_eq!(
+            index.entries,
+            [
+                entry("r1", [4, 4, 4, 5], Some(11)),
+                entry("r2", [2, 20, 2, 3], Some(27)),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_irregular_files() {
+        let error_line = |text: &str| match FastaIndex::build(&mut text.as_bytes()).unwrap_err() {
+            SeqtoolsError::Parse { location, .. } => location.line,
+            err => panic!("unexpected error {err:?}"),
+        };
+        assert_eq!(error_line(">a\nAC\nACG\n"), Some(3));
+        assert_eq!(error_line(">a\nACG\nA\nAC\n"), Some(4));
+        assert_eq!(error_line(">a\nA\n>a\nA\n"), Some(3));
+        assert_eq!(error_line("ACGT\n"), Some(1));
+        assert_eq!(error_line("@r\nACGT\n+\nII\n"), Some(4));
+    }
+
+    #[test]
+    fn parses_regions() {
+        let region = |name: &str, start, end| Region {
+            name: name.to_string(),
+            start,
+            end,
+        };
+        let parse = |text| Region::parse_coordinates(text).unwrap();
+        assert_eq!(parse("chr1"), region("chr1", 0, None));
+        assert_eq!(parse("chr1:10"), region("chr1", 9, None));
+        assert_eq!(parse("chr1:1,000-2,000"), region("chr1", 999, Some(2000)));
+        assert_eq!(parse("HLA:A:5-6"), region("HLA:A", 4, Some(6)));
+        for invalid in ["chr1:0-5", "chr1:6-5", "chr1:a-5", "chr1:"] {
+            assert!(Region::parse_coordinates(invalid).is_err(), "{invalid}");
+        }
+
+        let index = FastaIndex::build(&mut ">HLA:A\nACGT\n".as_bytes()).unwrap();
+        assert_eq!(
+            Region::parse("HLA:A", &index).unwrap(),
+            region("HLA:A", 0, None)
+        );
+
+        assert_eq!(
+            region("chr1", 999, Some(2000)).to_string(),
+            "chr1:1000-2000"
+        );
+        assert_eq!(region("chr1", 9, None).to_string(), "chr1:10");
+        assert_eq!(region("chr1", 0, None).to_string(), "chr1");
+    }
+
+    #[test]
+    fn indexes_bgzf_blocks() {
+        let mut file = bgzf_block(b">a\nACGT\n");
+        let second = file.len() as u64;
+        file.extend(bgzf_block(b"ACGT\n>b\n"));
+        let third = file.len() as u64;
+        file.extend(bgzf_block(b"TTTT\n"));
+
+        let gzi = GziIndex::build(&mut Cursor::new(&file)).unwrap();
+        assert_eq!(gzi.blocks, [(second, 8), (third, 16)]);
+        assert_eq!(gzi.block_of(7), (0, 0));
+        assert_eq!(gzi.block_of(8), (second, 8));
+        assert_eq!(gzi.block_of(20), (third, 16));
+
+        let mut plain = vec![];
+        GzEncoder::new(&mut plain, Compression::default())
+            .write_all(b">a\nACGT\n")
+            .unwrap();
+        assert!(GziIndex::build(&mut Cursor::new(&plain)).is_err());
+    }
+
+    #[test]
+    fn fetches_regions_of_bgzf_files() {
+        let mut content = bgzf_block(b">a\nACGT\n");
+        content.extend(bgzf_block(b"ACGT\nAC\n>b\n"));
+        content.extend(bgzf_block(b"TTTT\n"));
+        let file = TempFile::new("fetch.fa.gz", &content);
+
+        let mut reader = IndexedReader::create(&file.0).unwrap();
+        assert_eq!(reader.index().entries.len(), 2);
+        let gzi = GziIndex::read(&index_path(&file.0, "gzi")).unwrap();
+        assert_eq!(gzi.blocks.len(), 2);
+
+        let fetch = |reader: &mut IndexedReader, text| {
+            let region = Region::parse(text, reader.index()).unwrap();
+            reader.fetch(&region).unwrap().seq
+        };
+        assert_eq!(fetch(&mut reader, "a:3-7"), b"GTACG");
+        assert_eq!(fetch(&mut reader, "a:9-20"), b"AC");
+        assert_eq!(fetch(&mut reader, "b"), b"TTTT");
+
+        let mut reader = IndexedReader::open(&file.0).unwrap();
+        assert_eq!(fetch(&mut reader, "a"), b"ACGTACGTAC");
+        let region = Region::parse_coordinates("c").unwrap();
+        assert!(reader.fetch(&region).is_err());
+    }
+
+    #[test]
+    fn fetches_qualities() {
+        let file = TempFile::new("fetch.fq", b"@r1\nACGT\n+\nABCD\n");
+        let mut reader = IndexedReader::create(&file.0).unwrap();
+        let record = reader
+            .fetch(&Region::parse_coordinates("r1:2-3").unwrap())
+            .unwrap();
+        assert_eq!(record.id, b"r1:2-3");
+        assert_eq!(record.seq, b"CG");
+        assert_eq!(record.qual.as_deref(), Some(b"BC".as_slice()));
+    }
+}
@@ -0,0 +1,212 @@
+//! Clustal alignments, as written by Clustal Omega, ClustalW and MUSCLE: a
+//! `CLUSTAL` header followed by blocks of sequences, each one ending with a
+//! line that marks the conserved columns.
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+
+use needletail::parser::LineEnding;
+
+use crate::errors::{Location, SeqtoolsError};
+use crate::record::Record;
+use crate::viewer::Alphabet;
+
+/// Number of alignment columns per block
+const BLOCK_WIDTH: usize = 60;
+/// Spaces between the longest name and the sequences
+const NAME_PADDING: usize = 6;
+
+/// Groups of amino acids with strongly and weakly similar properties, as
+/// used by ClustalW to mark columns with `:` and `.`
+const STRONG_GROUPS: [&[u8]; 9] = [
+    b"STA", b"NEQK", b"NHQK", b"NDEQ", b"QHRK", b"MILV", b"MILF", b"HY", b"FYW",
+];
+const WEAK_GROUPS: [&[u8]; 11] = [
+    b"CSA", b"ATV", b"SAG", b"STNK", b"STPA", b"SGND", b"SNDEQK", b"NDEQHK", b"NEQHRK", b"FVLIM",
+    b"HFY",
+];
+
+/// Whether `line` is the header of a Clustal file. MUSCLE writes its own name
+/// instead of `CLUSTAL`.
+pub fn is_header(line: &str) -> bool {
+    line.starts_with("CLUSTAL") || line.starts_with("MUSCLE")
+}
+
+fn invalid(message: impl Into<String>, line: u64) -> SeqtoolsError {
+    SeqtoolsError::Parse {
+        message: message.into(),
+        location: Location {
+            line: Some(line),
+            ..Default::default()
+        },
+    }
+}
+
+/// Read a Clustal alignment, along with the line where each record starts
+pub fn read(reader: &mut dyn BufRead) -> Result<Vec<(Record, u64)>, SeqtoolsError> {
+    let mut records: Vec<(Record, u64)> = vec![];
+    let mut by_name: HashMap<String, usize> = HashMap::new();
+    let mut header = false;
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        let number = i as u64 + 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if !header {
+            if !is_header(&line) {
+                return Err(invalid("Expected a CLUSTAL header", number));
+            }
+            header = true;
+            continue;
+        }
+        // Lines that mark conserved columns start with spaces
+        if line.starts_with(char::is_whitespace) {
+            continue;
+        }
+
+        // Sequences can be followed by the number of residues so far
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let (name, seq) = match fields[..] {
+            [name, seq] => (name, seq),
+            [name, seq, count] if count.parse::<usize>().is_ok() => (name, seq),
+            _ => return Err(invalid("Expected a sequence name and a sequence", number)),
+        };
+        match by_name.get(name) {
+            Some(i) => records[*i].0.seq.extend(seq.bytes()),
+            None => {
+                by_name.insert(name.into(), records.len());
+                records.push((Record::new(name.into(), seq.into(), None), number));
+            }
+        }
+    }
+
+    let length = records.first().map_or(0, |(record, _)| record.seq.len());
+    if let Some((record, line)) = records.iter().find(|(r, _)| r.seq.len() != length) {
+        return Err(invalid(
+            format!(
+                "Sequence of {} has {} characters but the alignment has {length} columns",
+                String::from_utf8_lossy(&record.id),
+                record.seq.len()
+            ),
+            *line,
+        ));
+    }
+
+    Ok(records)
+}
+
+/// Conservation mark of a column: `*` when all residues are identical, and for
+/// proteins `:` or `.` when they all belong to a strong or weak group
+fn conservation(column: &[u8], alphabet: Alphabet) -> u8 {
+    let column: Vec<u8> = column.iter().map(u8::to_ascii_uppercase).collect();
+    if column.is_empty() || column.iter().any(|c| matches!(c, b'-' | b'.')) {
+        return b' ';
+    }
+    let in_group = |groups: &[&[u8]]| {
+        groups
+            .iter()
+            .any(|group| column.iter().all(|c| group.contains(c)))
+    };
+
+    if column.iter().all(|c| *c == column[0]) {
+        b'*'
+    } else if alphabet == Alphabet::Nucleic {
+        b' '
+    } else if in_group(&STRONG_GROUPS) {
+        b':'
+    } else if in_group(&WEAK_GROUPS) {
+        b'.'
+    } else {
+        b' '
+    }
+}
+
+/// Write records as a Clustal alignment in blocks of 60 columns.
+/// All sequences must have the same length.
+pub fn write(
+    writer: &mut dyn Write,
+    records: &[Record],
+    line_ending: LineEnding,
+) -> Result<(), SeqtoolsError> {
+    let ncol = super::alignment_length(records)?;
+    let names: Vec<String> = records.iter().map(super::name).collect();
+    let width = names.iter().map(|name| name.len()).max().unwrap_or(0) + NAME_PADDING;
+    let alphabet = Alphabet::infer(records.iter().map(|record| record.seq.as_slice()));
+
+    let eol = line_ending.to_bytes();
+    writer.write_all(b"CLUSTAL multiple sequence alignment")?;
+    writer.write_all(&eol)?;
+    for start in (0..ncol).step_by(BLOCK_WIDTH) {
+        let end = ncol.min(start + BLOCK_WIDTH);
+        writer.write_all(&eol)?;
+        for (name, record) in names.iter().zip(records) {
+            write!(writer, "{name:<width$}")?;
+            writer.write_all(&record.seq[start..end])?;
+            writer.write_all(&eol)?;
+        }
+
+        let marks: Vec<u8> = (start..end)
+            .map(|i| {
+                let column: Vec<u8> = records.iter().map(|record| record.seq[i]).collect();
+                conservation(&column, alphabet)
+            })
+            .collect();
+        write!(writer, "{:width$}", "")?;
+        writer.write_all(&marks)?;
+        writer.write_all(&eol)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_blocks() {
+        let text = "CLUSTAL W (1.83) multiple sequence alignment\n\n\
+            a      MKV-L 4\nb      MRVIL 5\n       *:* *\n\n\
+            a      AA 6\nb      AG 7\n       *\n";
+        let records = read(&mut text.as_bytes()).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].0.id, b"a");
+        assert_eq!(records[0].0.seq, b"MKV-LAA");
+        assert_eq!(records[1].0.seq, b"MRVILAG");
+        assert_eq!((records[0].1, records[1].1), (3, 4));
+    }
+
+    #[test]
+    fn rejects_invalid_alignments() {
+        assert!(read(&mut ">a\nACGT\n".as_bytes()).is_err());
+        assert!(read(&mut "CLUSTAL\n\na AC\nb A\n".as_bytes()).is_err());
+        assert!(read(&mut "CLUSTAL\n\na A C G\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn marks_conserved_columns() {
+        assert_eq!(conservation(b"AAA", Alphabet::Nucleic), b'*');
+        assert_eq!(conservation(b"ACA", Alphabet::Nucleic), b' ');
+        assert_eq!(conservation(b"A-A", Alphabet::Nucleic), b' ');
+        assert_eq!(conservation(b"mmm", Alphabet::Protein), b'*');
+        assert_eq!(conservation(b"ILV", Alphabet::Protein), b':');
+        assert_eq!(conservation(b"CSA", Alphabet::Protein), b'.');
+        assert_eq!(conservation(b"WPA", Alphabet::Protein), b' ');
+    }
+
+    #[test]
+    fn round_trips_long_alignments() {
+        let records = vec![
+            Record::new(b"first".to_vec(), b"ACGT".repeat(20), None),
+            Record::new(b"second one".to_vec(), b"ACGA".repeat(20), None),
+        ];
+        let mut out = vec![];
+        write(&mut out, &records, LineEnding::Unix).unwrap();
+
+        let read = read(&mut out.as_slice()).unwrap();
+        assert_eq!(read[0].0.id, b"first");
+        assert_eq!(read[1].0.id, b"second");
+        assert_eq!(read[0].0.seq, records[0].seq);
+        assert_eq!(read[1].0.seq, records[1].seq);
+    }
+}
@@ -0,0 +1,579 @@
+//! GenBank and EMBL flat files. Entries are read one at a time, along with
+//! their feature table, and features can be extracted as records of their own.
+use std::collections::HashMap;
+use std::io::{self, BufRead, Lines};
+
+use crate::errors::{Location, SeqtoolsError};
+use crate::record::Record;
+use crate::sequence;
+
+/// Column where the key of a feature starts, in both formats
+const KEY_START: usize = 5;
+/// Column where the location and qualifiers of a feature start
+const VALUE_START: usize = 21;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    GenBank,
+    Embl,
+}
+
+/// Guess whether an uncompressed stream is a GenBank or EMBL file from its
+/// first line, without consuming it
+pub fn detect(stream: &mut dyn BufRead) -> io::Result<Option<Kind>> {
+    Ok(match super::first_line(stream)? {
+        Some(line) if line.starts_with("LOCUS ") => Some(Kind::GenBank),
+        Some(line) if line.starts_with("ID   ") => Some(Kind::Embl),
+        _ => None,
+    })
+}
+
+/// An entry of the feature table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Feature {
+    /// Feature key, e.g. `CDS` or `gene`
+    pub key: String,
+    /// Location as written in the file, e.g. `complement(join(1..10,20..30))`
+    pub location: String,
+    /// `(name, value)` qualifiers, without quotes. The value is empty for
+    /// qualifiers such as `/pseudo`.
+    pub qualifiers: Vec<(String, String)>,
+}
+
+/// An entry of a flat file: the record of its whole sequence and its features
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub record: Record,
+    pub features: Vec<Feature>,
+}
+
+/// Split a line into its first word and the rest of the line
+fn split_word(text: &str) -> (&str, &str) {
+    let text = text.trim_start();
+    let end = text.find(char::is_whitespace).unwrap_or(text.len());
+    (&text[..end], text[end..].trim())
+}
+
+/// Builds the features of an entry from the lines of its feature table
+#[derive(Default)]
+struct FeatureTable {
+    features: Vec<Feature>,
+    /// Whether the last qualifier has an unterminated quoted value
+    quoted: bool,
+}
+
+impl FeatureTable {
+    fn add_line(&mut self, line: &str) {
+        let key = line.get(KEY_START..VALUE_START).unwrap_or_default().trim();
+        let value = line.get(VALUE_START..).unwrap_or_default().trim();
+        if !key.is_empty() {
+            self.features.push(Feature {
+                key: key.to_string(),
+                location: value.to_string(),
+                qualifiers: vec![],
+            });
+            self.quoted = false;
+            return;
+        }
+
+        let Some(feature) = self.features.last_mut() else {
+            return;
+        };
+        let odd_quotes = value.matches('"').count() % 2 == 1;
+        if let (Some(qualifier), false) = (value.strip_prefix('/'), self.quoted) {
+            let (name, value) = qualifier.split_once('=').unwrap_or((qualifier, ""));
+            feature
+                .qualifiers
+                .push((name.to_string(), value.to_string()));
+            self.quoted = odd_quotes;
+        } else if let Some((name, text)) = feature.qualifiers.last_mut() {
+            // Protein translations are wrapped without spaces
+            if name != "translation" {
+                text.push(' ');
+            }
+            text.push_str(value);
+            self.quoted ^= odd_quotes;
+        } else {
+            feature.location.push_str(value);
+        }
+    }
+
+    fn finish(self) -> Vec<Feature> {
+        let mut features = self.features;
+        for feature in &mut features {
+            for (_, value) in &mut feature.qualifiers {
+                if let Some(quoted) = value.strip_prefix('"') {
+                    *value = quoted
+                        .strip_suffix('"')
+                        .unwrap_or(quoted)
+                        .replace("\"\"", "\"");
+                }
+            }
+        }
+
+        features
+    }
+}
+
+/// Parse the lines of a GenBank entry, without its `//` terminator
+fn parse_genbank(lines: &[String]) -> Entry {
+    let mut section = "";
+    let (mut locus, mut accession, mut version) = (None, None, None);
+    let mut definition = String::new();
+    let mut table = FeatureTable::default();
+    let mut seq = vec![];
+    for line in lines {
+        if !line.starts_with(' ') {
+            let (keyword, rest) = split_word(line);
+            let first = split_word(rest).0.to_string();
+            section = keyword;
+            match keyword {
+                "LOCUS" => locus = Some(first),
+                "ACCESSION" => accession = Some(first),
+                "VERSION" => version = Some(first),
+                "DEFINITION" => definition.push_str(rest),
+                _ => {}
+            }
+            continue;
+        }
+
+        match section {
+            "DEFINITION" => {
+                definition.push(' ');
+                definition.push_str(line.trim());
+            }
+            "FEATURES" => table.add_line(line),
+            "ORIGIN" => seq.extend(line.bytes().filter(u8::is_ascii_alphabetic)),
+            _ => {}
+        }
+    }
+
+    let name = version.or(accession).or(locus).unwrap_or_default();
+    Entry {
+        record: Record::new(identifier(&name, &definition), seq, None),
+        features: table.finish(),
+    }
+}
+
+/// Parse the lines of an EMBL entry, without its `//` terminator
+fn parse_embl(lines: &[String]) -> Entry {
+    let (mut name, mut accession, mut version) = (None, None, None);
+    let mut definition = String::new();
+    let mut table = FeatureTable::default();
+    let mut seq = vec![];
+    let mut in_sequence = false;
+    for line in lines {
+        let content = line.get(KEY_START..).unwrap_or_default().trim();
+        match line.get(..2).unwrap_or_default() {
+            "ID" => {
+                let mut fields = content.split(';').map(str::trim);
+                name = fields.next().map(str::to_string);
+                version = fields.find_map(|field| field.strip_prefix("SV ").map(str::to_string));
+            }
+            "AC" if accession.is_none() => {
+                accession = content.split(';').next().map(|ac| ac.trim().to_string())
+            }
+            "DE" => {
+                if !definition.is_empty() {
+                    definition.push(' ');
+                }
+                definition.push_str(content);
+            }
+            "FT" => table.add_line(line),
+            "SQ" => in_sequence = true,
+            "  " if in_sequence => seq.extend(line.bytes().filter(u8::is_ascii_alphabetic)),
+            _ => {}
+        }
+    }
+
+    let name = match (accession.or(name), version) {
+        (Some(name), Some(version)) => format!("{name}.{version}"),
+        (name, _) => name.unwrap_or_default(),
+    };
+    Entry {
+        record: Record::new(identifier(&name, &definition), seq, None),
+        features: table.finish(),
+    }
+}
+
+/// Record identifier made of the accession and the definition, without its
+/// final period
+fn identifier(name: &str, definition: &str) -> Vec<u8> {
+    let definition = definition.trim();
+    let definition = definition.strip_suffix('.').unwrap_or(definition);
+    if definition.is_empty() {
+        name.into()
+    } else {
+        format!("{name} {definition}").into_bytes()
+    }
+}
+
+/// Reads the entries of a flat file one at a time
+pub struct Reader {
+    lines: Lines<Box<dyn BufRead + Send>>,
+    kind: Kind,
+    /// Number of lines read so far
+    line: u64,
+    finished: bool,
+}
+
+impl Reader {
+    pub fn new(stream: Box<dyn BufRead + Send>, kind: Kind) -> Self {
+        Reader {
+            lines: stream.lines(),
+            kind,
+            line: 0,
+            finished: false,
+        }
+    }
+
+    /// The lines of the next entry and the line where it starts
+    fn next_lines(&mut self) -> Result<Option<(Vec<String>, u64)>, SeqtoolsError> {
+        let mut lines = vec![];
+        let mut start = 0;
+        for line in self.lines.by_ref() {
+            let line = line?;
+            self.line += 1;
+            if line.trim_end() == "//" {
+                return Ok(Some((lines, start)));
+            }
+            if lines.is_empty() {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                start = self.line;
+            }
+            lines.push(line);
+        }
+
+        if lines.is_empty() {
+            Ok(None)
+        } else {
+            Err(SeqtoolsError::Parse {
+                message: "Entry is not terminated by '//'".to_string(),
+                location: Location {
+                    line: Some(start),
+                    ..Default::default()
+                },
+            })
+        }
+    }
+}
+
+impl Iterator for Reader {
+    /// An entry along with the line where it starts
+    type Item = Result<(Entry, u64), SeqtoolsError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        let (lines, start) = match self.next_lines() {
+            Ok(Some(entry)) => entry,
+            Ok(None) => return None,
+            Err(e) => {
+                self.finished = true;
+                return Some(Err(e));
+            }
+        };
+
+        let entry = match self.kind {
+            Kind::GenBank => parse_genbank(&lines),
+            Kind::Embl => parse_embl(&lines),
+        };
+        Some(Ok((entry, start)))
+    }
+}
+
+/// Part of a feature location, with 1-based inclusive bounds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Segment {
+    start: usize,
+    end: usize,
+    /// Whether the segment is on the complementary strand
+    reverse: bool,
+}
+
+/// Arguments of `name(...)` in a location
+fn call<'a>(text: &'a str, name: &str) -> Option<&'a str> {
+    text.strip_prefix(name)?
+        .trim_start()
+        .strip_prefix('(')?
+        .strip_suffix(')')
+}
+
+/// Split the arguments of a `join` on the commas that are not nested
+fn split_arguments(text: &str) -> Vec<&str> {
+    let mut arguments = vec![];
+    let (mut depth, mut start) = (0, 0);
+    for (i, c) in text.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                arguments.push(&text[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    arguments.push(&text[start..]);
+
+    arguments
+}
+
+fn position(text: &str) -> Result<usize, String> {
+    text.trim_start_matches(['<', '>'])
+        .parse()
+        .map_err(|_| format!("Invalid position '{text}'"))
+}
+
+/// Segments of a location in the order they are transcribed. `complement`
+/// reverses the segments of its argument and `join` or `order` concatenate
+/// them.
+fn parse_location(text: &str) -> Result<Vec<Segment>, String> {
+    let text = text.trim();
+    if let Some(inner) = call(text, "complement") {
+        let mut segments = parse_location(inner)?;
+        segments.reverse();
+        for segment in &mut segments {
+            segment.reverse = !segment.reverse;
+        }
+        return Ok(segments);
+    }
+    if let Some(inner) = call(text, "join").or_else(|| call(text, "order")) {
+        let mut segments = vec![];
+        for argument in split_arguments(inner) {
+            segments.extend(parse_location(argument)?);
+        }
+        return Ok(segments);
+    }
+    if text.contains(':') {
+        return Err(format!("Location '{text}' refers to another entry"));
+    }
+    // A site between two bases has no sequence
+    if text.contains('^') {
+        return Ok(vec![]);
+    }
+
+    let (start, end) = text.split_once("..").unwrap_or((text, text));
+    Ok(vec![Segment {
+        start: position(start)?,
+        end: position(end)?,
+        reverse: false,
+    }])
+}
+
+/// Sequence of a feature in an entry
+pub fn feature_sequence(entry: &Entry, feature: &Feature) -> Result<Vec<u8>, SeqtoolsError> {
+    let error = |message: String| SeqtoolsError::record(message, &entry.record.id);
+    let seq = &entry.record.seq;
+    let mut feature_seq = vec![];
+    for segment in parse_location(&feature.location.replace(' ', "")).map_err(error)? {
+        if segment.start == 0 || segment.start > segment.end || segment.end > seq.len() {
+            return Err(error(format!(
+                "Location '{}' of a {} feature is outside of the {} bp sequence",
+                feature.location,
+                feature.key,
+                seq.len()
+            )));
+        }
+        let part = &seq[segment.start - 1..segment.end];
+        if segment.reverse {
+            feature_seq.extend(sequence::reverse_complement(part));
+        } else {
+            feature_seq.extend_from_slice(part);
+        }
+    }
+
+    Ok(feature_seq)
+}
+
+/// Records of the features of an entry whose key is in `keys`, named
+/// `<accession>_<key>_<n>` and with their qualifiers and location in the
+/// description, e.g. `[gene=thrL] [location=190..255]`. `n` numbers the
+/// features of each key in the entry, from 1. Translations are left out of the
+/// description, and features without a sequence, such as sites between two
+/// bases, are skipped but keep their number.
+pub fn feature_records(entry: &Entry, keys: &[String]) -> Result<Vec<Record>, SeqtoolsError> {
+    let name = super::name(&entry.record);
+    let mut records = vec![];
+    let mut numbers: HashMap<&str, usize> = HashMap::new();
+    for feature in entry.features.iter().filter(|f| keys.contains(&f.key)) {
+        let number = numbers.entry(feature.key.as_str()).or_insert(0);
+        *number += 1;
+        let mut id = format!("{name}_{}_{number}", feature.key);
+        for (qualifier, value) in &feature.qualifiers {
+            match (qualifier.as_str(), value.is_empty()) {
+                ("translation", _) => {}
+                (_, true) => id.push_str(&format!(" [{qualifier}]")),
+                (_, false) => id.push_str(&format!(" [{qualifier}={value}]")),
+            }
+        }
+        id.push_str(&format!(" [location={}]", feature.location));
+
+        let seq = feature_sequence(entry, feature)?;
+        if seq.is_empty() {
+            continue;
+        }
+        records.push(Record::new(id.into_bytes(), seq, None));
+    }
+
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GENBANK: &str = "\
+LOCUS       TEST                      20 bp    DNA     linear   SYN 01-JAN-2024
+DEFINITION  Test sequence,
+            two lines.
+ACCESSION   AB000001
+VERSION     AB000001.2
+FEATURES             Location/Qualifiers
+     source          1..20
+     gene            3..8
+                     /gene=\"abc\"
+     CDS             complement(join(3..5,
+                     10..12))
+                     /gene=\"abc\"
+                     /note=\"a \"\"quoted\"\" word
+                     on two lines\"
+                     /pseudo
+                     /translation=\"MK
+                     L\"
+     misc_feature    5^6
+ORIGIN
+        1 acgtaccgta ggttacgtaa
+//
+";
+
+    const EMBL: &str = "\
+ID   X56734; SV 1; linear; mRNA; STD; PLN; 10 BP.
+AC   X56734; S46826;
+DE   Test sequence
+FT   source          1..10
+FT   gene            2..4
+FT                   /gene=\"x\"
+SQ   Sequence 10 BP;
+     acgtacgtac                                                           10
+//
+";
+
+    fn entries(text: &'static str, kind: Kind) -> Vec<Entry> {
+        Reader::new(Box::new(text.as_bytes()), kind)
+            .map(|entry| entry.unwrap().0)
+            .collect()
+    }
+
+    #[test]
+    fn detects_kind() {
+        assert_eq!(
+            detect(&mut GENBANK.as_bytes()).unwrap(),
+            Some(Kind::GenBank)
+        );
+        assert_eq!(detect(&mut EMBL.as_bytes()).unwrap(), Some(Kind::Embl));
+        assert_eq!(detect(&mut ">a\nACGT\n".as_bytes()).unwrap(), None);
+    }
+
+    #[test]
+    fn reads_genbank_entries() {
+        let entries = entries(GENBANK, Kind::GenBank);
+        assert_eq!(entries.len(), 1);
+        let entry = &entries[0];
+        assert_eq!(entry.record.id, b"AB000001.2 Test sequence, two lines");
+        assert_eq!(entry.record.seq, b"acgtaccgtaggttacgtaa");
+
+        let keys: Vec<&str> = entry.features.iter().map(|f| f.key.as_str()).collect();
+        assert_eq!(keys, ["source", "gene", "CDS", "misc_feature"]);
+        let cds = &entry.features[2];
+        assert_eq!(cds.location, "complement(join(3..5,10..12))");
+        assert_eq!(
+            cds.qualifiers,
+            [
+                ("gene".to_string(), "abc".to_string()),
+                (
+                    "note".to_string(),
+                    "a \"quoted\" word on two lines".to_string()
+                ),
+                ("pseudo".to_string(), String::new()),
+                ("translation".to_string(), "MKL".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_embl_entries() {
+        let entries = entries(EMBL, Kind::Embl);
+        let entry = &entries[0];
+        assert_eq!(entry.record.id, b"X56734.1 Test sequence");
+        assert_eq!(entry.record.seq, b"acgtacgtac");
+        assert_eq!(entry.features[1].key, "gene");
+        assert_eq!(entry.features[1].location, "2..4");
+        assert_eq!(
+            entry.features[1].qualifiers,
+            [("gene".to_string(), "x".to_string())]
+        );
+    }
+
+    #[test]
+    fn rejects_unterminated_entries() {
+        let mut reader = Reader::new(
+            Box::new("LOCUS       A\nORIGIN\n".as_bytes()),
+            Kind::GenBank,
+        );
+        assert!(reader.next().unwrap().is_err());
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn parses_locations() {
+        let segment = |start, end, reverse| Segment {
+            start,
+            end,
+            reverse,
+        };
+        assert_eq!(
+            parse_location("complement(join(<1..5,9..>12))").unwrap(),
+            [segment(9, 12, true), segment(1, 5, true)]
+        );
+        assert_eq!(
+            parse_location("order(3,complement(7..8))").unwrap(),
+            [segment(3, 3, false), segment(7, 8, true)]
+        );
+        assert_eq!(parse_location("5^6").unwrap(), []);
+        assert!(parse_location("J00194.1:100..202").is_err());
+        assert!(parse_location("1..x").is_err());
+    }
+
+    #[test]
+    fn extracts_features() {
+        let entry = &entries(GENBANK, Kind::GenBank)[0];
+        let keys = [
+            "gene".to_string(),
+            "CDS".to_string(),
+            "misc_feature".to_string(),
+        ];
+        let records = feature_records(entry, &keys).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(
+            records[0].id,
+            b"AB000001.2_gene_1 [gene=abc] [location=3..8]"
+        );
+        assert_eq!(records[0].seq, b"gtaccg");
+        assert!(records[1].id.starts_with(b"AB000001.2_CDS_1 [gene=abc]"));
+        assert!(records[1]
+            .id
+            .ends_with(b"[pseudo] [location=complement(join(3..5,10..12))]"));
+        assert_eq!(records[1].seq, b"ccttac");
+
+        let outside = Feature {
+            key: "gene".to_string(),
+            location: "15..25".to_string(),
+            qualifiers: vec![],
+        };
+        assert!(feature_sequence(entry, &outside).is_err());
+    }
+}
@@ -0,0 +1,115 @@
+//! Readers and writers for the formats that needletail does not handle.
+//! Alignment formats are read and written as a whole, since their layout
+//! depends on all the records.
+use std::io::{self, BufRead};
+
+use crate::errors::SeqtoolsError;
+use crate::record::Record;
+use crate::Format;
+
+pub mod clustal;
+pub mod flatfile;
+pub mod nexus;
+pub mod phylip;
+pub mod stockholm;
+pub mod tabular;
+
+/// Annotations of a whole alignment rather than of one of its sequences
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Annotations {
+    /// `(tag, text)` annotations of the alignment, such as the `#=GF` lines
+    /// of Stockholm files
+    pub file: Vec<(String, String)>,
+    /// `(tag, text)` annotations with one character per column, such as the
+    /// `#=GC` lines of Stockholm files
+    pub columns: Vec<(String, String)>,
+}
+
+/// All the records of a file in an alignment format
+pub struct Alignment {
+    /// Records along with the line where each one starts
+    pub records: Vec<(Record, u64)>,
+    pub format: Format,
+    pub annotations: Annotations,
+}
+
+/// First word of a record identifier, which is its name in the alignment
+pub fn name(record: &Record) -> String {
+    let id = String::from_utf8_lossy(&record.id);
+    id.split_whitespace().next().unwrap_or_default().to_string()
+}
+
+/// Number of columns of an alignment. All sequences must have the same length.
+pub fn alignment_length(records: &[Record]) -> Result<usize, SeqtoolsError> {
+    let length = records.first().map_or(0, |record| record.seq.len());
+    match records.iter().find(|record| record.seq.len() != length) {
+        Some(record) => Err(SeqtoolsError::record(
+            format!(
+                "Sequence has {} characters but the alignment has {length} columns",
+                record.seq.len()
+            ),
+            &record.id,
+        )),
+        None => Ok(length),
+    }
+}
+
+/// First non-empty line of a stream, without consuming it
+fn first_line(stream: &mut dyn BufRead) -> io::Result<Option<String>> {
+    let start = String::from_utf8_lossy(stream.fill_buf()?);
+    let line = start.lines().find(|line| !line.trim().is_empty());
+
+    Ok(line.map(str::to_string))
+}
+
+/// Guess the format of an uncompressed stream from its first line, without
+/// consuming it. Returns `None` for FASTA and FASTQ, which needletail
+/// recognizes on its own.
+pub fn detect(stream: &mut dyn BufRead) -> io::Result<Option<Format>> {
+    Ok(match first_line(stream)?.as_deref() {
+        Some(line) if phylip::is_header(line) => Some(Format::Phylip),
+        Some(line) if nexus::is_header(line) => Some(Format::Nexus),
+        Some(line) if stockholm::is_header(line) => Some(Format::Stockholm),
+        Some(line) if clustal::is_header(line) => Some(Format::Clustal),
+        Some(line) if tabular::is_jsonl(line) => Some(Format::Jsonl),
+        Some(line) if tabular::is_tsv(line) => Some(Format::Tsv),
+        _ => None,
+    })
+}
+
+/// Read all the records of a stream in the given format. The format of the
+/// alignment is refined from the content, e.g. strict or relaxed PHYLIP.
+pub fn read(format: Format, stream: &mut dyn BufRead) -> Result<Alignment, SeqtoolsError> {
+    let alignment = |records, format| Alignment {
+        records,
+        format,
+        annotations: Annotations::default(),
+    };
+    match format {
+        Format::Phylip | Format::PhylipStrict => {
+            let (records, strict) = phylip::read(stream)?;
+            let format = if strict {
+                Format::PhylipStrict
+            } else {
+                Format::Phylip
+            };
+            Ok(alignment(records, format))
+        }
+        Format::Nexus => Ok(alignment(nexus::read(stream)?, Format::Nexus)),
+        Format::Stockholm => {
+            let (records, annotations) = stockholm::read(stream)?;
+            Ok(Alignment {
+                records,
+                format,
+                annotations,
+            })
+        }
+        Format::Clustal => Ok(alignment(clustal::read(stream)?, Format::Clustal)),
+        Format::Fasta | Format::Fastq => Err(SeqtoolsError::invalid_argument(format!(
+            "{format:?} is read by needletail"
+        ))),
+        Format::Tsv | Format::Jsonl => Err(SeqtoolsError::invalid_argument(format!(
+            "{format:?} is read line by line"
+        ))),
+    }
+}
@@ -0,0 +1,359 @@
+//! Alignments in the MATRIX of a NEXUS DATA or CHARACTERS block, as used by
+//! MrBayes, BEAST or PAUP*.
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, Write};
+
+use needletail::parser::LineEnding;
+
+use crate::errors::{Location, SeqtoolsError};
+use crate::record::Record;
+use crate::viewer::Alphabet;
+
+/// Characters that cannot appear in an unquoted taxon label
+const PUNCTUATION: &str = "()[]{}/\\,;:=*'\"`<>~";
+
+pub fn is_header(line: &str) -> bool {
+    line.trim().eq_ignore_ascii_case("#nexus")
+}
+
+/// Remove `[...]` comments, which can be nested, while keeping line breaks
+/// so that line numbers stay the same
+fn strip_comments(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut depth = 0;
+    let mut quoted = false;
+    for c in text.chars() {
+        match c {
+            '\'' if depth == 0 => {
+                quoted = !quoted;
+                stripped.push(c);
+            }
+            '[' if !quoted => depth += 1,
+            ']' if !quoted && depth > 0 => depth -= 1,
+            '\n' => stripped.push(c),
+            _ if depth == 0 => stripped.push(c),
+            _ => {}
+        }
+    }
+
+    stripped
+}
+
+/// Position of the first occurrence of `word` in `text` that is not part of
+/// a longer word
+fn find_word(text: &str, word: &str, from: usize) -> Option<usize> {
+    let is_word = |c: Option<char>| c.is_some_and(|c| c.is_ascii_alphanumeric() || c == '_');
+    let mut start = from;
+    while let Some(i) = text[start..].find(word).map(|i| i + start) {
+        let before = text[..i].chars().next_back();
+        let after = text[i + word.len()..].chars().next();
+        if !is_word(before) && !is_word(after) {
+            return Some(i);
+        }
+        start = i + word.len();
+    }
+
+    None
+}
+
+/// Value of a `key=value` setting in a lowercase command
+fn setting<'a>(command: &'a str, key: &str) -> Option<&'a str> {
+    let start = find_word(command, key, 0)? + key.len();
+    let rest = command[start..]
+        .trim_start()
+        .strip_prefix('=')?
+        .trim_start();
+    let end = rest
+        .find(|c: char| c.is_whitespace() || c == ';')
+        .unwrap_or(rest.len());
+
+    Some(&rest[..end])
+}
+
+/// Split a matrix line into its taxon label and the rest of the line.
+/// Quoted labels can contain spaces, and `''` stands for a quote.
+fn split_label(line: &str) -> Option<(String, &str)> {
+    let line = line.trim_start();
+    if let Some(quoted) = line.strip_prefix('\'') {
+        let mut label = String::new();
+        let mut chars = quoted.char_indices().peekable();
+        while let Some((i, c)) = chars.next() {
+            if c != '\'' {
+                label.push(c);
+            } else if chars.peek().is_some_and(|(_, c)| *c == '\'') {
+                label.push('\'');
+                chars.next();
+            } else {
+                return Some((label, &quoted[i + 1..]));
+            }
+        }
+        None
+    } else {
+        let end = line.find(char::is_whitespace).unwrap_or(line.len());
+        Some((line[..end].to_string(), &line[end..]))
+    }
+}
+
+fn without_spaces(text: &str) -> Vec<u8> {
+    text.bytes().filter(|c| !c.is_ascii_whitespace()).collect()
+}
+
+/// Read the alignment of the first DATA or CHARACTERS block, in sequential or
+/// interleaved layout. Returns the records along with the line where each
+/// one starts.
+pub fn read(reader: &mut dyn BufRead) -> Result<Vec<(Record, u64)>, SeqtoolsError> {
+    let mut text = String::new();
+    reader.read_to_string(&mut text)?;
+    let text = strip_comments(&text);
+    let lower = text.to_ascii_lowercase();
+
+    let line_of = |offset: usize| text[..offset].matches('\n').count() as u64 + 1;
+    let invalid = |message: &str, offset: Option<usize>| SeqtoolsError::Parse {
+        message: message.to_string(),
+        location: Location {
+            line: offset.map(line_of),
+            ..Default::default()
+        },
+    };
+
+    let mut block = None;
+    let mut from = 0;
+    while let Some(begin) = find_word(&lower, "begin", from) {
+        let name = lower[begin + "begin".len()..].trim_start();
+        if name.starts_with("data") || name.starts_with("characters") {
+            block = Some(begin);
+            break;
+        }
+        from = begin + 1;
+    }
+    let block = block.ok_or_else(|| invalid("No DATA or CHARACTERS block", None))?;
+    let matrix = find_word(&lower, "matrix", block)
+        .ok_or_else(|| invalid("No MATRIX in the DATA block", Some(block)))?;
+
+    let header = &lower[block..matrix];
+    let nchar: Option<usize> = setting(header, "nchar").and_then(|n| n.parse().ok());
+    let interleaved = find_word(header, "interleave", 0).is_some()
+        && !matches!(setting(header, "interleave"), Some("no" | "false"));
+    let matchchar = setting(header, "matchchar").and_then(|c| c.bytes().next());
+
+    // The matrix ends at the first semicolon that is not in a quoted label
+    let start = matrix + "matrix".len();
+    let mut quoted = false;
+    let end = text[start..]
+        .char_indices()
+        .find(|(_, c)| {
+            if *c == '\'' {
+                quoted = !quoted;
+            }
+            *c == ';' && !quoted
+        })
+        .map(|(i, _)| start + i)
+        .ok_or_else(|| invalid("MATRIX is not terminated by ';'", Some(matrix)))?;
+
+    let mut records: Vec<(Record, u64)> = vec![];
+    let mut by_label: HashMap<String, usize> = HashMap::new();
+    let mut offset = start;
+    for line in text[start..end].split('\n') {
+        let line_start = offset;
+        let number = line_of(line_start);
+        offset += line.len() + 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        // In a sequential matrix, sequences can span several lines
+        if let Some((last, _)) = records.last_mut() {
+            if !interleaved && nchar.is_some_and(|n| last.seq.len() < n) {
+                last.seq.extend(without_spaces(line));
+                continue;
+            }
+        }
+
+        let (label, rest) = split_label(line)
+            .ok_or_else(|| invalid("Unterminated quoted taxon label", Some(line_start)))?;
+        match by_label.get(&label) {
+            Some(i) => records[*i].0.seq.extend(without_spaces(rest)),
+            None => {
+                by_label.insert(label.clone(), records.len());
+                let record = Record::new(label.into_bytes(), without_spaces(rest), None);
+                records.push((record, number));
+            }
+        }
+    }
+
+    if let Some(matchchar) = matchchar {
+        let first = records.first().map(|(record, _)| record.seq.clone());
+        for (record, _) in records.iter_mut().skip(1) {
+            for (i, c) in record.seq.iter_mut().enumerate() {
+                if *c == matchchar {
+                    *c = first
+                        .as_ref()
+                        .and_then(|seq| seq.get(i))
+                        .copied()
+                        .unwrap_or(*c);
+                }
+            }
+        }
+    }
+
+    if let Some(nchar) = nchar {
+        if let Some((record, line)) = records.iter().find(|(r, _)| r.seq.len() != nchar) {
+            return Err(SeqtoolsError::Parse {
+                message: format!(
+                    "Sequence of {} has {} characters, expected NCHAR={nchar}",
+                    String::from_utf8_lossy(&record.id),
+                    record.seq.len()
+                ),
+                location: Location {
+                    line: Some(*line),
+                    ..Default::default()
+                },
+            });
+        }
+    }
+
+    Ok(records)
+}
+
+/// Taxon label of a record: its whole identifier, quoted if needed
+fn label(record: &Record) -> String {
+    let id = String::from_utf8_lossy(&record.id);
+    if id.is_empty() || id.contains(|c: char| c.is_whitespace() || PUNCTUATION.contains(c)) {
+        format!("'{}'", id.replace('\'', "''"))
+    } else {
+        id.into_owned()
+    }
+}
+
+/// NEXUS datatype of the sequences, inferred from their alphabet
+fn datatype(records: &[Record]) -> &'static str {
+    match Alphabet::infer(records.iter().map(|record| record.seq.as_slice())) {
+        Alphabet::Protein => "PROTEIN",
+        Alphabet::Nucleic => {
+            let has = |bases: &[u8]| {
+                records
+                    .iter()
+                    .any(|record| record.seq.iter().any(|c| bases.contains(c)))
+            };
+            if has(b"Uu") && !has(b"Tt") {
+                "RNA"
+            } else {
+                "DNA"
+            }
+        }
+    }
+}
+
+/// Write records as a NEXUS file with a single DATA block.
+/// All sequences must have the same length.
+pub fn write(
+    writer: &mut dyn Write,
+    records: &[Record],
+    line_ending: LineEnding,
+) -> Result<(), SeqtoolsError> {
+    let nchar = super::alignment_length(records)?;
+    let labels: Vec<String> = records.iter().map(label).collect();
+    // Rows with the same label would be read back as a single taxon
+    let mut seen = HashSet::new();
+    if let Some(duplicate) = labels.iter().find(|label| !seen.insert(*label)) {
+        return Err(SeqtoolsError::invalid_argument(format!(
+            "Taxon label {duplicate} appears several times, NEXUS labels must be unique"
+        )));
+    }
+    let width = labels.iter().map(|label| label.len()).max().unwrap_or(0);
+
+    let eol = String::from_utf8_lossy(&line_ending.to_bytes()).into_owned();
+    write!(writer, "#NEXUS{eol}{eol}BEGIN DATA;{eol}")?;
+    write!(
+        writer,
+        "    DIMENSIONS NTAX={} NCHAR={nchar};{eol}",
+        records.len()
+    )?;
+    write!(
+        writer,
+        "    FORMAT DATATYPE={} MISSING=? GAP=-;{eol}",
+        datatype(records)
+    )?;
+    write!(writer, "    MATRIX{eol}")?;
+    for (label, record) in labels.iter().zip(records) {
+        write!(writer, "    {label:<width$} ")?;
+        writer.write_all(&record.seq)?;
+        write!(writer, "{eol}")?;
+    }
+    write!(writer, "    ;{eol}END;{eol}")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_text(text: &str) -> Vec<(Record, u64)> {
+        read(&mut text.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn reads_sequential_matrix() {
+        let text = "#NEXUS\nBEGIN TAXA;\n  DIMENSIONS NTAX=2;\nEND;\n\
+            begin data; [a comment]\n  dimensions ntax=2 nchar=8;\n  matrix\n\
+            'taxon one' ACGT\n  ACGT\ntwo ACGTTTTT\n;\nend;\n";
+        let records = read_text(text);
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].0.id, b"taxon one");
+        assert_eq!(records[0].0.seq, b"ACGTACGT");
+        assert_eq!(records[1].0.id, b"two");
+        assert_eq!(records[1].0.seq, b"ACGTTTTT");
+        assert_eq!((records[0].1, records[1].1), (8, 10));
+    }
+
+    #[test]
+    fn reads_interleaved_matrix_with_matchchar() {
+        let text = "#NEXUS\nBEGIN CHARACTERS;\n  DIMENSIONS NCHAR=6;\n\
+            FORMAT INTERLEAVE MATCHCHAR=.;\n  MATRIX\na ACG\nb .T.\n\na TTA\nb ..C\n;\nEND;\n";
+        let records = read_text(text);
+        assert_eq!(records[0].0.seq, b"ACGTTA");
+        assert_eq!(records[1].0.seq, b"ATGTTC");
+    }
+
+    #[test]
+    fn checks_nchar() {
+        let text = "#NEXUS\nBEGIN DATA;\nDIMENSIONS NCHAR=4;\nMATRIX\na ACGT\nb ACG\n;\nEND;\n";
+        match read(&mut text.as_bytes()).unwrap_err() {
+            SeqtoolsError::Parse { location, .. } => assert_eq!(location.line, Some(6)),
+            err => panic!("unexpected error {err:?}"),
+        }
+        assert!(read(&mut "#NEXUS\nBEGIN TREES;\nEND;\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn round_trips_labels() {
+        let records = vec![
+            Record::new(b"plain".to_vec(), b"ACGU".to_vec(), None),
+            Record::new(b"taxon one".to_vec(), b"ACGU".to_vec(), None),
+            Record::new(b"taxon two".to_vec(), b"ACGU".to_vec(), None),
+            Record::new(b"it's;odd".to_vec(), b"ACGU".to_vec(), None),
+            Record::new(vec![], b"AC-U".to_vec(), None),
+        ];
+        let mut out = vec![];
+        write(&mut out, &records, LineEnding::Unix).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.contains("DATATYPE=RNA"));
+        assert!(text.contains("    plain       ACGU\n"));
+        assert!(text.contains("    'taxon one' ACGU\n"));
+        assert!(text.contains("    'it''s;odd' ACGU\n"));
+
+        let read: Vec<Record> = read_text(&text).into_iter().map(|(r, _)| r).collect();
+        assert_eq!(read, records);
+    }
+
+    #[test]
+    fn rejects_duplicate_labels() {
+        let records = vec![
+            Record::new(b"taxon".to_vec(), b"ACGT".to_vec(), None),
+            Record::new(b"taxon".to_vec(), b"ACGA".to_vec(), None),
+        ];
+        let err = write(&mut vec![], &records, LineEnding::Unix).unwrap_err();
+        assert!(matches!(err, SeqtoolsError::InvalidArgument(_)));
+    }
+}
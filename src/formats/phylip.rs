@@ -0,0 +1,330 @@
+//! PHYLIP alignments, with strict (names of exactly 10 characters) or relaxed
+//! (names separated from the sequence by whitespace) taxon names, written
+//! sequentially or in interleaved blocks.
+use std::io::{BufRead, Write};
+
+use needletail::parser::LineEnding;
+
+use crate::errors::{Location, SeqtoolsError};
+use crate::record::Record;
+
+const STRICT_NAME_LEN: usize = 10;
+/// Number of alignment columns per block of interleaved output
+const BLOCK_WIDTH: usize = 60;
+
+/// Whether `line` is a PHYLIP header: the number of taxa and of characters
+pub fn is_header(line: &str) -> bool {
+    header(line).is_some()
+}
+
+fn header(line: &str) -> Option<(usize, usize)> {
+    let mut fields = line.split_whitespace();
+    let ntax = fields.next()?.parse().ok()?;
+    let nchar = fields.next()?.parse().ok()?;
+    Some((ntax, nchar))
+}
+
+/// A non-empty line of the alignment with its line number
+struct Line {
+    text: String,
+    number: u64,
+}
+
+fn without_spaces(text: &str) -> Vec<u8> {
+    text.bytes().filter(|c| !c.is_ascii_whitespace()).collect()
+}
+
+/// Split a line that starts with a taxon name into the name and the sequence
+fn split_name(line: &str, strict: bool) -> (String, Vec<u8>) {
+    if strict {
+        let end = line
+            .char_indices()
+            .nth(STRICT_NAME_LEN)
+            .map_or(line.len(), |(i, _)| i);
+        (line[..end].trim().to_string(), without_spaces(&line[end..]))
+    } else {
+        let line = line.trim_start();
+        let end = line.find(char::is_whitespace).unwrap_or(line.len());
+        (line[..end].to_string(), without_spaces(&line[end..]))
+    }
+}
+
+/// Parse the lines after the header with one of the 4 PHYLIP layouts.
+/// Returns `None` if the lines do not hold `ntax` sequences of `nchar` characters.
+fn parse(
+    lines: &[Line],
+    ntax: usize,
+    nchar: usize,
+    strict: bool,
+    interleaved: bool,
+) -> Option<Vec<(Record, u64)>> {
+    let mut records = vec![];
+    let mut lines = lines.iter();
+
+    if interleaved {
+        for _ in 0..ntax {
+            let line = lines.next()?;
+            let (name, seq) = split_name(&line.text, strict);
+            records.push((Record::new(name.into_bytes(), seq, None), line.number));
+        }
+        for (i, line) in lines.enumerate() {
+            records[i % ntax].0.seq.extend(without_spaces(&line.text));
+        }
+    } else {
+        for _ in 0..ntax {
+            let line = lines.next()?;
+            let (name, mut seq) = split_name(&line.text, strict);
+            while seq.len() < nchar {
+                seq.extend(without_spaces(&lines.next()?.text));
+            }
+            records.push((Record::new(name.into_bytes(), seq, None), line.number));
+        }
+        if lines.next().is_some() {
+            return None;
+        }
+    }
+
+    if records.iter().all(|(record, _)| record.seq.len() == nchar) {
+        Some(records)
+    } else {
+        None
+    }
+}
+
+/// Read a PHYLIP alignment, guessing whether it is strict or relaxed and
+/// sequential or interleaved. Returns the records along with the line where
+/// each one starts, and whether names are strict.
+pub fn read(reader: &mut dyn BufRead) -> Result<(Vec<(Record, u64)>, bool), SeqtoolsError> {
+    let mut lines = vec![];
+    for (i, line) in reader.lines().enumerate() {
+        let text = line?;
+        if !text.trim().is_empty() {
+            lines.push(Line {
+                text,
+                number: i as u64 + 1,
+            });
+        }
+    }
+
+    let invalid = |message: String, line: Option<u64>| SeqtoolsError::Parse {
+        message,
+        location: Location {
+            line,
+            ..Default::default()
+        },
+    };
+    let first = lines
+        .first()
+        .ok_or_else(|| invalid("Empty PHYLIP file".to_string(), None))?;
+    let (ntax, nchar) = header(&first.text).ok_or_else(|| {
+        invalid(
+            "Expected the number of taxa and characters on the first line".to_string(),
+            Some(first.number),
+        )
+    })?;
+    if ntax == 0 {
+        return Err(invalid(
+            "The PHYLIP alignment must have at least one taxon".to_string(),
+            Some(first.number),
+        ));
+    }
+
+    for strict in [false, true] {
+        for interleaved in [false, true] {
+            if let Some(records) = parse(&lines[1..], ntax, nchar, strict, interleaved) {
+                return Ok((records, strict));
+            }
+        }
+    }
+
+    Err(invalid(
+        format!("Expected {ntax} sequences of {nchar} characters in the PHYLIP alignment"),
+        Some(first.number),
+    ))
+}
+
+/// Taxon names to use in the output. In strict mode, names that do not fit
+/// in 10 characters or that are not unique are replaced by short names, along
+/// with a `short name -> identifier` map. Names are measured in characters,
+/// like their padding, so that non-ASCII names keep sequences aligned.
+pub fn names(records: &[Record], strict: bool) -> (Vec<String>, Option<Vec<(String, String)>>) {
+    let names: Vec<String> = records.iter().map(super::name).collect();
+    if !strict {
+        return (names, None);
+    }
+
+    let mut unique: Vec<&String> = names.iter().collect();
+    unique.sort();
+    unique.dedup();
+    if unique.len() == names.len() && names.iter().all(|n| n.chars().count() <= STRICT_NAME_LEN) {
+        return (names, None);
+    }
+
+    let short: Vec<String> = (1..=names.len()).map(|i| format!("seq{i:07}")).collect();
+    let map = short
+        .iter()
+        .cloned()
+        .zip(
+            records
+                .iter()
+                .map(|r| String::from_utf8_lossy(&r.id).into_owned()),
+        )
+        .collect();
+    (short, Some(map))
+}
+
+/// Write records as a PHYLIP alignment with the given taxon names.
+/// All sequences must have the same length.
+pub fn write(
+    writer: &mut dyn Write,
+    names: &[String],
+    records: &[Record],
+    strict: bool,
+    interleaved: bool,
+    line_ending: LineEnding,
+) -> Result<(), SeqtoolsError> {
+    let nchar = super::alignment_length(records)?;
+    let eol = line_ending.to_bytes();
+    write!(writer, "{} {nchar}", records.len())?;
+    writer.write_all(&eol)?;
+
+    let width = if interleaved {
+        BLOCK_WIDTH
+    } else {
+        nchar.max(1)
+    };
+    for start in (0..nchar.max(1)).step_by(width) {
+        if start > 0 {
+            writer.write_all(&eol)?;
+        }
+        for (name, record) in names.iter().zip(records) {
+            if start == 0 {
+                if strict {
+                    write!(writer, "{name:<STRICT_NAME_LEN$}")?;
+                } else {
+                    write!(writer, "{name} ")?;
+                }
+            }
+            let end = (start + width).min(nchar);
+            writer.write_all(&record.seq[start.min(end)..end])?;
+            writer.write_all(&eol)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records() -> Vec<Record> {
+        vec![
+            Record::new(b"seq1 first".to_vec(), b"ACGT-ACGTA".to_vec(), None),
+            Record::new(b"seq2".to_vec(), b"ACGTTACG-A".to_vec(), None),
+        ]
+    }
+
+    fn round_trip(strict: bool, interleaved: bool) -> Vec<Record> {
+        let records = records();
+        let (names, map) = names(&records, strict);
+        assert!(map.is_none());
+        let mut out = vec![];
+        write(
+            &mut out,
+            &names,
+            &records,
+            strict,
+            interleaved,
+            LineEnding::Unix,
+        )
+        .unwrap();
+
+        let (read, _) = read(&mut out.as_slice()).unwrap();
+        read.into_iter().map(|(record, _)| record).collect()
+    }
+
+    #[test]
+    fn round_trips() {
+        for strict in [false, true] {
+            for interleaved in [false, true] {
+                let read = round_trip(strict, interleaved);
+                let ids: Vec<&[u8]> = read.iter().map(|r| r.id.as_slice()).collect();
+                assert_eq!(ids, [b"seq1".as_slice(), b"seq2"]);
+                let seqs: Vec<&[u8]> = read.iter().map(|r| r.seq.as_slice()).collect();
+                assert_eq!(seqs, [b"ACGT-ACGTA".as_slice(), b"ACGTTACG-A"]);
+            }
+        }
+    }
+
+    #[test]
+    fn reads_interleaved_blocks() {
+        let text = "2 8\nfirst ACGT\nsecond TTTT\n\nAC GT\nGGGG\n";
+        let (records, strict) = read(&mut text.as_bytes()).unwrap();
+        assert!(!strict);
+        assert_eq!(records[0].0.seq, b"ACGTACGT");
+        assert_eq!(records[1].0.seq, b"TTTTGGGG");
+        assert_eq!((records[0].1, records[1].1), (2, 3));
+    }
+
+    #[test]
+    fn reads_strict_names_with_spaces() {
+        let text = "1 4\nmy seq    ACGT\n";
+        let (records, strict) = read(&mut text.as_bytes()).unwrap();
+        assert!(strict);
+        assert_eq!(records[0].0.id, b"my seq");
+        assert_eq!(records[0].0.seq, b"ACGT");
+    }
+
+    #[test]
+    fn shortens_long_strict_names() {
+        let records = vec![Record::new(
+            b"a_very_long_name".to_vec(),
+            b"A".to_vec(),
+            None,
+        )];
+        let (names, map) = names(&records, true);
+        assert_eq!(names, ["seq0000001"]);
+        assert_eq!(
+            map.unwrap(),
+            [("seq0000001".to_string(), "a_very_long_name".to_string())]
+        );
+    }
+
+    #[test]
+    fn pads_non_ascii_names_by_characters() {
+        let records = vec![
+            Record::new("Ærø".into(), b"ACGT".to_vec(), None),
+            Record::new("ééééééééé1".into(), b"TTGG".to_vec(), None),
+        ];
+        let (names, map) = names(&records, true);
+        assert!(map.is_none());
+        let mut out = vec![];
+        write(&mut out, &names, &records, true, false, LineEnding::Unix).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert_eq!(text, "2 4\nÆrø       ACGT\nééééééééé1TTGG\n");
+
+        let (read, strict) = read(&mut text.as_bytes()).unwrap();
+        assert!(strict);
+        let read: Vec<Record> = read.into_iter().map(|(record, _)| record).collect();
+        assert_eq!(read, records);
+
+        let long = vec![Record::new("éééééééééé1".into(), b"A".to_vec(), None)];
+        assert!(super::names(&long, true).1.is_some());
+    }
+
+    #[test]
+    fn rejects_zero_taxa() {
+        let err = read(&mut "0 10\nextra line\n".as_bytes()).unwrap_err();
+        match err {
+            SeqtoolsError::Parse { location, .. } => assert_eq!(location.line, Some(1)),
+            err => panic!("unexpected error {err:?}"),
+        }
+    }
+
+    #[test]
+    fn rejects_wrong_lengths() {
+        assert!(read(&mut "2 4\na ACGT\nb ACG\n".as_bytes()).is_err());
+        assert!(read(&mut "".as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,293 @@
+//! Stockholm alignments, as used by Pfam, Rfam and HMMER. `#=GF` and `#=GC`
+//! lines are kept as annotations of the alignment and `#=GS` lines as
+//! annotations of each sequence. Per-residue `#=GR` lines are not kept.
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+
+use needletail::parser::LineEnding;
+
+use super::Annotations;
+use crate::errors::{Location, SeqtoolsError};
+use crate::record::Record;
+
+const HEADER: &str = "# STOCKHOLM";
+const END: &str = "//";
+/// `#=GS` tag of the description of a sequence, which is also added to its
+/// identifier
+const DESCRIPTION: &str = "DE";
+
+pub fn is_header(line: &str) -> bool {
+    line.starts_with(HEADER)
+}
+
+fn invalid(message: impl Into<String>, line: u64) -> SeqtoolsError {
+    SeqtoolsError::Parse {
+        message: message.into(),
+        location: Location {
+            line: Some(line),
+            ..Default::default()
+        },
+    }
+}
+
+/// Split a line into its first word and the rest of the line
+fn split_word(text: &str) -> (&str, &str) {
+    let text = text.trim_start();
+    let end = text.find(char::is_whitespace).unwrap_or(text.len());
+    (&text[..end], text[end..].trim())
+}
+
+/// One alignment of the file, between its header and `//`
+#[derive(Default)]
+struct Block {
+    records: Vec<(Record, u64)>,
+    by_name: HashMap<String, usize>,
+    /// `(name, tag, text, line)` of the `#=GS` lines, which can come before
+    /// the sequence they annotate
+    sequence_annotations: Vec<(String, String, String, u64)>,
+    annotations: Annotations,
+}
+
+impl Block {
+    fn add_line(&mut self, text: &str, number: u64) -> Result<(), SeqtoolsError> {
+        if let Some(rest) = text.strip_prefix("#=GF ") {
+            let (tag, value) = split_word(rest);
+            self.annotations.file.push((tag.into(), value.into()));
+        } else if let Some(rest) = text.strip_prefix("#=GC ") {
+            // Column annotations are split into blocks like the sequences
+            let (tag, value) = split_word(rest);
+            match self.annotations.columns.iter_mut().find(|(t, _)| t == tag) {
+                Some((_, columns)) => columns.push_str(value),
+                None => self.annotations.columns.push((tag.into(), value.into())),
+            }
+        } else if let Some(rest) = text.strip_prefix("#=GS ") {
+            let (name, rest) = split_word(rest);
+            let (tag, value) = split_word(rest);
+            self.sequence_annotations
+                .push((name.into(), tag.into(), value.into(), number));
+        } else if !text.starts_with('#') {
+            let (name, seq) = split_word(text);
+            if seq.is_empty() || seq.contains(char::is_whitespace) {
+                return Err(invalid("Expected a sequence name and a sequence", number));
+            }
+            match self.by_name.get(name) {
+                Some(i) => self.records[*i].0.seq.extend(seq.bytes()),
+                None => {
+                    self.by_name.insert(name.into(), self.records.len());
+                    let record = Record::new(name.into(), seq.into(), None);
+                    self.records.push((record, number));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<(Vec<(Record, u64)>, Annotations), SeqtoolsError> {
+        for (name, tag, text, line) in self.sequence_annotations {
+            let i = self.by_name.get(&name).ok_or_else(|| {
+                invalid(format!("#=GS annotation for unknown sequence {name}"), line)
+            })?;
+            let record = &mut self.records[*i].0;
+            if tag == DESCRIPTION && !text.is_empty() {
+                record.id.push(b' ');
+                record.id.extend(text.bytes());
+            }
+            record.annotations.push((tag, text));
+        }
+
+        let length = self
+            .records
+            .first()
+            .map_or(0, |(record, _)| record.seq.len());
+        if let Some((record, line)) = self.records.iter().find(|(r, _)| r.seq.len() != length) {
+            return Err(invalid(
+                format!(
+                    "Sequence of {} has {} characters but the alignment has {length} columns",
+                    super::name(record),
+                    record.seq.len()
+                ),
+                *line,
+            ));
+        }
+
+        Ok((self.records, self.annotations))
+    }
+}
+
+/// Read the alignments of a Stockholm file, along with the line where each
+/// record starts. The annotations of the alignment are only kept when the file
+/// holds a single alignment.
+pub fn read(reader: &mut dyn BufRead) -> Result<(Vec<(Record, u64)>, Annotations), SeqtoolsError> {
+    let mut records = vec![];
+    let mut annotations = vec![];
+    let mut block: Option<(Block, u64)> = None;
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        let text = line.trim_end();
+        let number = i as u64 + 1;
+        if text.is_empty() {
+            continue;
+        }
+
+        match block.as_mut() {
+            None if is_header(text) => block = Some((Block::default(), number)),
+            None => return Err(invalid(format!("Expected '{HEADER} 1.0'"), number)),
+            Some(_) if text == END => {
+                let (block_records, block_annotations) = block.take().unwrap().0.finish()?;
+                records.extend(block_records);
+                annotations.push(block_annotations);
+            }
+            Some((current, _)) => current.add_line(text, number)?,
+        }
+    }
+    if let Some((_, start)) = block {
+        return Err(invalid(
+            format!("Alignment is not terminated by '{END}'"),
+            start,
+        ));
+    }
+
+    let annotations = match annotations.len() {
+        1 => annotations.pop().unwrap_or_default(),
+        _ => Annotations::default(),
+    };
+    Ok((records, annotations))
+}
+
+/// `#=GS` annotations of a record. The description in its identifier is used
+/// unless the record already has one.
+fn sequence_annotations(record: &Record) -> Vec<(String, String)> {
+    let mut annotations = record.annotations.clone();
+    if !annotations.iter().any(|(tag, _)| tag == DESCRIPTION) {
+        let id = String::from_utf8_lossy(&record.id);
+        if let Some((_, description)) = id.trim().split_once(char::is_whitespace) {
+            annotations.insert(0, (DESCRIPTION.into(), description.trim().into()));
+        }
+    }
+
+    annotations
+}
+
+/// Write records as a Stockholm alignment, one line per sequence.
+/// All sequences must have the same length. Column annotations that do not
+/// match the length of the alignment, e.g. after trimming, are left out.
+pub fn write(
+    writer: &mut dyn Write,
+    records: &[Record],
+    annotations: &Annotations,
+    line_ending: LineEnding,
+) -> Result<(), SeqtoolsError> {
+    let ncol = super::alignment_length(records)?;
+    let names: Vec<String> = records.iter().map(super::name).collect();
+    let columns: Vec<(String, &String)> = annotations
+        .columns
+        .iter()
+        .filter(|(_, text)| text.len() == ncol)
+        .map(|(tag, text)| (format!("#=GC {tag}"), text))
+        .collect();
+    let name_width = names.iter().map(|name| name.len()).max().unwrap_or(0);
+    let width = columns
+        .iter()
+        .map(|(label, _)| label.len())
+        .max()
+        .unwrap_or(0)
+        .max(name_width);
+
+    let eol = String::from_utf8_lossy(&line_ending.to_bytes()).into_owned();
+    write!(writer, "{HEADER} 1.0{eol}")?;
+    let mut header = !annotations.file.is_empty();
+    for (tag, text) in &annotations.file {
+        write!(writer, "#=GF {tag} {text}{eol}")?;
+    }
+    for (name, record) in names.iter().zip(records) {
+        for (tag, text) in sequence_annotations(record) {
+            header = true;
+            write!(writer, "#=GS {name:<name_width$} {tag} {text}{eol}")?;
+        }
+    }
+    if header {
+        write!(writer, "{eol}")?;
+    }
+
+    for (name, record) in names.iter().zip(records) {
+        write!(writer, "{name:<width$} ")?;
+        writer.write_all(&record.seq)?;
+        write!(writer, "{eol}")?;
+    }
+    for (label, text) in columns {
+        write!(writer, "{label:<width$} {text}{eol}")?;
+    }
+    write!(writer, "{END}{eol}")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALIGNMENT: &str = "# STOCKHOLM 1.0\n#=GF ID test\n#=GS a DE first sequence\n\n\
+        a    ACG-\nb    AC-U\n#=GC SS_cons <<..\n\na    UU\nb    UA\n#=GC SS_cons >>\n//\n";
+
+    #[test]
+    fn reads_blocks_and_annotations() {
+        let (records, annotations) = read(&mut ALIGNMENT.as_bytes()).unwrap();
+        assert_eq!(records[0].0.id, b"a first sequence");
+        assert_eq!(records[0].0.seq, b"ACG-UU");
+        assert_eq!(records[1].0.seq, b"AC-UUA");
+        assert_eq!((records[0].1, records[1].1), (5, 6));
+        assert_eq!(
+            records[0].0.annotations,
+            [("DE".to_string(), "first sequence".to_string())]
+        );
+        assert_eq!(annotations.file, [("ID".to_string(), "test".to_string())]);
+        assert_eq!(
+            annotations.columns,
+            [("SS_cons".to_string(), "<<..>>".to_string())]
+        );
+    }
+
+    #[test]
+    fn round_trips() {
+        let (records, annotations) = read(&mut ALIGNMENT.as_bytes()).unwrap();
+        let records: Vec<Record> = records.into_iter().map(|(record, _)| record).collect();
+        let mut out = vec![];
+        write(&mut out, &records, &annotations, LineEnding::Unix).unwrap();
+
+        let (read_records, read_annotations) = read(&mut out.as_slice()).unwrap();
+        let read_records: Vec<Record> = read_records.into_iter().map(|(r, _)| r).collect();
+        assert_eq!(read_records, records);
+        assert_eq!(read_annotations, annotations);
+    }
+
+    #[test]
+    fn keeps_descriptions_of_identifiers() {
+        let records = vec![Record::new(b"a some text".to_vec(), b"AC".to_vec(), None)];
+        let mut out = vec![];
+        write(
+            &mut out,
+            &records,
+            &Annotations::default(),
+            LineEnding::Unix,
+        )
+        .unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert_eq!(text, "# STOCKHOLM 1.0\n#=GS a DE some text\n\na AC\n//\n");
+    }
+
+    #[test]
+    fn rejects_invalid_alignments() {
+        let error_line = |text: &str| match read(&mut text.as_bytes()).unwrap_err() {
+            SeqtoolsError::Parse { location, .. } => location.line,
+            err => panic!("unexpected error {err:?}"),
+        };
+        assert_eq!(error_line("# STOCKHOLM 1.0\na AC\nb A\n//\n"), Some(3));
+        assert_eq!(error_line("# STOCKHOLM 1.0\na AC\n"), Some(1));
+        assert_eq!(
+            error_line("# STOCKHOLM 1.0\n#=GS c DE x\na AC\n//\n"),
+            Some(2)
+        );
+        assert_eq!(error_line(">a\nACGT\n"), Some(1));
+    }
+}
@@ -0,0 +1,253 @@
+//! Formats with one record per line: tab-separated `id\tseq[\tqual]` values
+//! and JSON Lines objects with `id`, `desc`, `seq` and `qual` fields.
+use std::io::{self, Write};
+
+use needletail::parser::LineEnding;
+use serde_json::{json, Value};
+
+use crate::record::Record;
+
+/// Header line that TSV files may start with
+const TSV_HEADERS: [&str; 2] = ["id\tseq", "id\tseq\tqual"];
+
+pub fn is_jsonl(line: &str) -> bool {
+    line.trim_start().starts_with('{')
+}
+
+pub fn is_tsv(line: &str) -> bool {
+    line.contains('\t') && !line.starts_with(['>', '@'])
+}
+
+pub fn is_tsv_header(line: &str) -> bool {
+    TSV_HEADERS.contains(&line.trim_end())
+}
+
+fn check_qual(seq: &[u8], qual: Option<&[u8]>) -> Result<(), String> {
+    match qual {
+        Some(qual) if qual.len() != seq.len() => Err(format!(
+            "Sequence has {} characters but quality has {}",
+            seq.len(),
+            qual.len()
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Parse an `id\tseq[\tqual]` line
+pub fn parse_tsv(line: &str) -> Result<Record, String> {
+    let fields: Vec<&str> = line.trim_end_matches('\r').split('\t').collect();
+    let (id, seq, qual) = match fields[..] {
+        [id, seq] => (id, seq, None),
+        [id, seq, qual] => (id, seq, Some(qual.as_bytes())),
+        _ => {
+            return Err(format!(
+                "Expected 2 or 3 tab-separated fields, found {}",
+                fields.len()
+            ))
+        }
+    };
+    check_qual(seq.as_bytes(), qual)?;
+
+    Ok(Record::new(id.into(), seq.into(), qual.map(<[u8]>::to_vec)))
+}
+
+/// Parse a JSON object with a string `id` and `seq`, and an optional `desc`
+/// and `qual`. The description is added to the identifier.
+pub fn parse_jsonl(line: &str) -> Result<Record, String> {
+    let value: Value = serde_json::from_str(line).map_err(|e| format!("Invalid JSON: {e}"))?;
+    let field = |name: &str| match value.get(name) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(text)) => Ok(Some(text.as_str())),
+        Some(_) => Err(format!("Field '{name}' should be a string")),
+    };
+    let missing = |name: &str| format!("Missing field '{name}'");
+
+    let mut id = field("id")?.ok_or_else(|| missing("id"))?.to_string();
+    let seq = field("seq")?.ok_or_else(|| missing("seq"))?;
+    let qual = field("qual")?.map(str::as_bytes);
+    if let Some(desc) = field("desc")?.filter(|desc| !desc.is_empty()) {
+        id.push(' ');
+        id.push_str(desc);
+    }
+    check_qual(seq.as_bytes(), qual)?;
+
+    Ok(Record::new(id.into(), seq.into(), qual.map(<[u8]>::to_vec)))
+}
+
+/// Write a record as an `id\tseq[\tqual]` line. Identifiers with tabs or
+/// line breaks cannot be written, as they would be read back as other fields
+/// or lines.
+pub fn write_tsv(
+    writer: &mut dyn Write,
+    record: &Record,
+    line_ending: LineEnding,
+) -> io::Result<()> {
+    if record.id.iter().any(|c| matches!(c, b'\t' | b'\n' | b'\r')) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Identifier {:?} contains a tab or a line break, it cannot be written as TSV",
+                String::from_utf8_lossy(&record.id)
+            ),
+        ));
+    }
+    writer.write_all(&record.id)?;
+    writer.write_all(b"\t")?;
+    writer.write_all(&record.seq)?;
+    if let Some(qual) = &record.qual {
+        writer.write_all(b"\t")?;
+        writer.write_all(qual)?;
+    }
+    writer.write_all(&line_ending.to_bytes())
+}
+
+/// Write a record as a JSON object. The first word of the identifier is the
+/// `id` and the rest is the `desc`, `null` when there is none, like `qual`
+/// for records without qualities.
+pub fn write_jsonl(
+    writer: &mut dyn Write,
+    record: &Record,
+    line_ending: LineEnding,
+) -> io::Result<()> {
+    let id = String::from_utf8_lossy(&record.id);
+    let (id, desc) = match id.trim().split_once(char::is_whitespace) {
+        Some((id, desc)) => (id, Some(desc.trim())),
+        None => (id.trim(), None),
+    };
+    let object = json!({
+        "id": id,
+        "desc": desc,
+        "seq": String::from_utf8_lossy(&record.seq),
+        "qual": record.qual.as_deref().map(String::from_utf8_lossy),
+    });
+    serde_json::to_writer(&mut *writer, &object)?;
+    writer.write_all(&line_ending.to_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(id: &str, seq: &str, qual: Option<&str>) -> Record {
+        Record::new(id.into(), seq.into(), qual.map(|q| q.into()))
+    }
+
+    fn written(
+        write: fn(&mut dyn Write, &Record, LineEnding) -> io::Result<()>,
+        record: &Record,
+    ) -> String {
+        let mut out = vec![];
+        write(&mut out, record, LineEnding::Unix).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn detects_formats() {
+        assert!(is_jsonl("  {\"id\": \"a\"}"));
+        assert!(!is_jsonl("id\tseq"));
+        assert!(is_tsv("a\tACGT"));
+        assert!(!is_tsv(">a\tdescription"));
+        assert!(!is_tsv("@a\tdescription"));
+        assert!(is_tsv_header("id\tseq\r\n"));
+        assert!(is_tsv_header("id\tseq\tqual"));
+        assert!(!is_tsv_header("id\tACGT"));
+    }
+
+    #[test]
+    fn parses_tsv_lines() {
+        assert_eq!(parse_tsv("a b\tACGT").unwrap(), record("a b", "ACGT", None));
+        assert_eq!(
+            parse_tsv("a\tACGT\tIIII\r").unwrap(),
+            record("a", "ACGT", Some("IIII"))
+        );
+        assert_eq!(parse_tsv("a\t\t").unwrap(), record("a", "", Some("")));
+    }
+
+    #[test]
+    fn rejects_invalid_tsv_lines() {
+        assert_eq!(
+            parse_tsv("a").unwrap_err(),
+            "Expected 2 or 3 tab-separated fields, found 1"
+        );
+        assert_eq!(
+            parse_tsv("a\tACGT\tIIII\textra").unwrap_err(),
+            "Expected 2 or 3 tab-separated fields, found 4"
+        );
+        assert_eq!(
+            parse_tsv("a\tACGT\tIII").unwrap_err(),
+            "Sequence has 4 characters but quality has 3"
+        );
+    }
+
+    #[test]
+    fn parses_jsonl_lines() {
+        assert_eq!(
+            parse_jsonl(r#"{"id": "a", "seq": "ACGT"}"#).unwrap(),
+            record("a", "ACGT", None)
+        );
+        assert_eq!(
+            parse_jsonl(r#"{"id": "a", "desc": "sample 1", "seq": "ACGT", "qual": "IIII"}"#)
+                .unwrap(),
+            record("a sample 1", "ACGT", Some("IIII"))
+        );
+        assert_eq!(
+            parse_jsonl(r#"{"id": "a", "desc": "", "seq": "ACGT", "qual": null}"#).unwrap(),
+            record("a", "ACGT", None)
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_jsonl_lines() {
+        assert!(parse_jsonl(r#"{"id": "a", "seq": "ACGT""#)
+            .unwrap_err()
+            .starts_with("Invalid JSON: "));
+        assert_eq!(
+            parse_jsonl(r#"{"seq": "ACGT"}"#).unwrap_err(),
+            "Missing field 'id'"
+        );
+        assert_eq!(
+            parse_jsonl(r#"{"id": "a"}"#).unwrap_err(),
+            "Missing field 'seq'"
+        );
+        assert_eq!(
+            parse_jsonl(r#"{"id": 1, "seq": "ACGT"}"#).unwrap_err(),
+            "Field 'id' should be a string"
+        );
+        assert_eq!(
+            parse_jsonl(r#"{"id": "a", "seq": "ACGT", "qual": "II"}"#).unwrap_err(),
+            "Sequence has 4 characters but quality has 2"
+        );
+    }
+
+    #[test]
+    fn writes_tsv_lines() {
+        assert_eq!(
+            written(write_tsv, &record("a b", "ACGT", None)),
+            "a b\tACGT\n"
+        );
+        let fastq = record("a", "ACGT", Some("IIII"));
+        let line = written(write_tsv, &fastq);
+        assert_eq!(line, "a\tACGT\tIIII\n");
+        assert_eq!(parse_tsv(line.trim_end()).unwrap(), fastq);
+
+        let mut out = vec![];
+        let err = write_tsv(&mut out, &record("a\tb", "ACGT", None), LineEnding::Unix);
+        assert_eq!(err.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn writes_jsonl_lines() {
+        assert_eq!(
+            written(write_jsonl, &record("a", "ACGT", None)),
+            "{\"id\":\"a\",\"desc\":null,\"seq\":\"ACGT\",\"qual\":null}\n"
+        );
+        let fastq = record("a sample\t1", "ACGT", Some("II\"I"));
+        let line = written(write_jsonl, &fastq);
+        let value: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(value["id"], "a");
+        assert_eq!(value["desc"], "sample\t1");
+        assert_eq!(value["qual"], "II\"I");
+        assert_eq!(parse_jsonl(&line).unwrap(), fastq);
+    }
+}
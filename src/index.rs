@@ -0,0 +1,466 @@
+//! Persistent index from record identifiers to their position in a FASTA or
+//! FASTQ file, so that a few records can be read from a huge file without
+//! scanning it.
+//!
+//! The index is a text file next to the input, `<file>.idx`, with a header
+//! line holding the size and modification time of the indexed file, so that
+//! an index of a file that changed since is not used, followed by one
+//! `id<TAB>offset<TAB>length` line per record sorted by identifier. Lookups
+//! are binary searches in the index file, which is never loaded in memory.
+//! Uncompressed and BGZF compressed files can be indexed.
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Cursor, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::errors::{Location, SeqtoolsError};
+use crate::faidx::{index_path, SeekableFile};
+use crate::record::Record;
+
+const HEADER: &str = "#seqtools-index";
+/// Number of entries sorted in memory at once while building an index
+const CHUNK_LEN: usize = 1_000_000;
+/// Size of the part of the index that is scanned once the binary search has
+/// narrowed it down
+const SCAN_LEN: u64 = 64 * 1024;
+
+/// Position of a record in the uncompressed file
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct Entry {
+    id: Vec<u8>,
+    offset: u64,
+    length: u64,
+}
+
+impl Entry {
+    fn write(&self, writer: &mut dyn Write) -> io::Result<()> {
+        writer.write_all(&self.id)?;
+        writeln!(writer, "\t{}\t{}", self.offset, self.length)
+    }
+
+    /// Parse an index line. Identifiers can contain tabs, so the numbers are
+    /// taken from the end of the line.
+    fn parse(line: &[u8]) -> Option<Entry> {
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        let mut fields = line.rsplitn(3, |c| *c == b'\t');
+        let number = |field: Option<&[u8]>| std::str::from_utf8(field?).ok()?.parse().ok();
+        let length = number(fields.next())?;
+        let offset = number(fields.next())?;
+        let id = fields.next()?.to_vec();
+
+        Some(Entry { id, offset, length })
+    }
+}
+
+/// Path of the id index of a file
+pub fn id_index_path(path: &Path) -> PathBuf {
+    index_path(path, "idx")
+}
+
+fn read_line(reader: &mut dyn BufRead, line: &mut Vec<u8>) -> io::Result<u64> {
+    line.clear();
+    Ok(reader.read_until(b'\n', line)? as u64)
+}
+
+/// Size and modification time, in nanoseconds since the Unix epoch, of a
+/// file. Files rewritten in place with the same size get a new modification
+/// time.
+fn stamp(path: &Path) -> io::Result<(u64, u128)> {
+    let metadata = fs::metadata(path)?;
+    let modified = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_nanos());
+
+    Ok((metadata.len(), modified))
+}
+
+/// Header of a record without its marker and line ending
+fn header_id(line: &[u8]) -> Vec<u8> {
+    let mut id = line[1..].to_vec();
+    while id.last().is_some_and(|c| *c == b'\n' || *c == b'\r') {
+        id.pop();
+    }
+    id
+}
+
+/// Find the records of an uncompressed FASTA or FASTQ stream. FASTQ records
+/// span 4 lines.
+fn scan(
+    stream: &mut dyn BufRead,
+    mut add: impl FnMut(Entry) -> io::Result<()>,
+) -> Result<(), SeqtoolsError> {
+    let mut line = vec![];
+    let mut offset = 0;
+    let mut current: Option<Entry> = None;
+    loop {
+        let width = read_line(stream, &mut line)?;
+        if width == 0 {
+            break;
+        }
+        match line.first() {
+            Some(b'>') => {
+                if let Some(mut entry) = current.take() {
+                    entry.length = offset - entry.offset;
+                    add(entry)?;
+                }
+                current = Some(Entry {
+                    id: header_id(&line),
+                    offset,
+                    length: 0,
+                });
+            }
+            Some(b'@') if current.is_none() => {
+                let id = header_id(&line);
+                let mut length = width;
+                for _ in 0..3 {
+                    length += read_line(stream, &mut line)?;
+                }
+                add(Entry { id, offset, length })?;
+                offset += length;
+                continue;
+            }
+            Some(b'\n' | b'\r') => {}
+            _ if current.is_none() => {
+                return Err(SeqtoolsError::Parse {
+                    message: "Only FASTA and FASTQ files can be indexed".to_string(),
+                    location: Location::default(),
+                })
+            }
+            _ => {}
+        }
+        offset += width;
+    }
+    if let Some(mut entry) = current.take() {
+        entry.length = offset - entry.offset;
+        add(entry)?;
+    }
+
+    Ok(())
+}
+
+/// Sort a chunk of entries and write it to a temporary file
+fn write_chunk(entries: &mut Vec<Entry>, path: PathBuf) -> io::Result<PathBuf> {
+    entries.sort();
+    let mut writer = BufWriter::new(File::create(&path)?);
+    for entry in entries.drain(..) {
+        entry.write(&mut writer)?;
+    }
+    writer.flush()?;
+
+    Ok(path)
+}
+
+/// Build the id index of a file and write it next to the file. Entries are
+/// sorted in chunks that are then merged, so that the index of a file with
+/// more records than fit in memory can be built. Returns the number of
+/// records.
+pub fn build(path: &Path) -> Result<usize, SeqtoolsError> {
+    let location = Location {
+        file: Some(path.display().to_string()),
+        ..Default::default()
+    };
+    let built = build_index(path, CHUNK_LEN);
+
+    built.map_err(|e| e.with_location(location))
+}
+
+fn build_index(path: &Path, chunk_len: usize) -> Result<usize, SeqtoolsError> {
+    let index = id_index_path(path);
+    // Taken before reading the file, so that changes made while it is read
+    // make the index out of date
+    let (size, modified) = stamp(path)?;
+    let mut file = SeekableFile::open(path, false)?;
+    let mut entries = vec![];
+    let mut chunks = vec![];
+    let mut count = 0;
+    scan(&mut file.stream()?, |entry| {
+        count += 1;
+        entries.push(entry);
+        if entries.len() == chunk_len {
+            let chunk = index_path(&index, &format!("part{}", chunks.len()));
+            chunks.push(write_chunk(&mut entries, chunk)?);
+        }
+        Ok(())
+    })?;
+
+    let mut writer = BufWriter::new(File::create(&index)?);
+    writeln!(writer, "{HEADER}\t{size}\t{modified}")?;
+    if chunks.is_empty() {
+        entries.sort();
+        for entry in &entries {
+            entry.write(&mut writer)?;
+        }
+    } else {
+        if !entries.is_empty() {
+            let chunk = index_path(&index, &format!("part{}", chunks.len()));
+            chunks.push(write_chunk(&mut entries, chunk)?);
+        }
+        merge(&chunks, &mut writer)?;
+        for chunk in chunks {
+            fs::remove_file(chunk)?;
+        }
+    }
+    writer.flush()?;
+
+    Ok(count)
+}
+
+/// Merge sorted chunk files into the index
+fn merge(chunks: &[PathBuf], writer: &mut dyn Write) -> Result<(), SeqtoolsError> {
+    let mut readers = vec![];
+    for chunk in chunks {
+        readers.push(BufReader::new(File::open(chunk)?));
+    }
+    let next = |reader: &mut BufReader<File>| -> Result<Option<Entry>, SeqtoolsError> {
+        let mut line = vec![];
+        if read_line(reader, &mut line)? == 0 {
+            return Ok(None);
+        }
+        Entry::parse(&line)
+            .map(Some)
+            .ok_or_else(|| SeqtoolsError::Parse {
+                message: "Invalid line in a temporary index file".to_string(),
+                location: Location::default(),
+            })
+    };
+
+    let mut heap = BinaryHeap::new();
+    for (i, reader) in readers.iter_mut().enumerate() {
+        if let Some(entry) = next(reader)? {
+            heap.push(Reverse((entry, i)));
+        }
+    }
+    while let Some(Reverse((entry, i))) = heap.pop() {
+        entry.write(writer)?;
+        if let Some(entry) = next(&mut readers[i])? {
+            heap.push(Reverse((entry, i)));
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads records of a file through its id index
+pub struct IdIndex {
+    index: BufReader<File>,
+    /// Offset of the first entry of the index, after its header
+    start: u64,
+    /// Size of the index file
+    len: u64,
+    file: SeekableFile,
+    name: String,
+}
+
+impl IdIndex {
+    /// Open the id index of a file, if it has one
+    pub fn open(path: &Path) -> Result<Option<Self>, SeqtoolsError> {
+        let index_path = id_index_path(path);
+        if !index_path.exists() {
+            return Ok(None);
+        }
+        let location = Location {
+            file: Some(path.display().to_string()),
+            ..Default::default()
+        };
+        let index = Self::open_index(path, &index_path);
+
+        index.map(Some).map_err(|e| e.with_location(location))
+    }
+
+    fn open_index(path: &Path, index_path: &Path) -> Result<Self, SeqtoolsError> {
+        let mut index = BufReader::new(File::open(index_path)?);
+        let mut header = String::new();
+        let start = index.read_line(&mut header)? as u64;
+        let indexed = header.trim_end().strip_prefix(HEADER).and_then(|stamp| {
+            let mut fields = stamp.split_whitespace();
+            let size = fields.next()?.parse::<u64>().ok()?;
+            let modified = fields.next()?.parse::<u128>().ok()?;
+            Some((size, modified))
+        });
+        if indexed != Some(stamp(path)?) {
+            return Err(SeqtoolsError::invalid_argument(format!(
+                "{} is missing or out of date, rebuild it with `seqtools index`",
+                index_path.display()
+            )));
+        }
+
+        Ok(IdIndex {
+            len: fs::metadata(index_path)?.len(),
+            index,
+            start,
+            file: SeekableFile::open(path, false)?,
+            name: path.display().to_string(),
+        })
+    }
+
+    fn next_entry(&mut self, line: &mut Vec<u8>) -> Result<Option<Entry>, SeqtoolsError> {
+        if read_line(&mut self.index, line)? == 0 {
+            return Ok(None);
+        }
+        Entry::parse(line)
+            .map(Some)
+            .ok_or_else(|| SeqtoolsError::Parse {
+                message: format!("Invalid line in the index of {}", self.name),
+                location: Location::default(),
+            })
+    }
+
+    /// Entries of the records with identifier `id`, in the order of the file
+    fn lookup(&mut self, id: &[u8]) -> Result<Vec<Entry>, SeqtoolsError> {
+        // `lo` is always the start of a line whose identifier is smaller than
+        // `id`, or of the first line, and `hi` the start of a line whose
+        // identifier is not smaller, or the end of the file
+        let (mut lo, mut hi) = (self.start, self.len);
+        let mut line = vec![];
+        while hi - lo > SCAN_LEN {
+            let mid = lo + (hi - lo) / 2;
+            self.index.seek(SeekFrom::Start(mid))?;
+            let line_start = mid + read_line(&mut self.index, &mut line)?;
+            if line_start >= hi {
+                break;
+            }
+            match self.next_entry(&mut line)? {
+                Some(entry) if entry.id.as_slice() < id => lo = line_start,
+                _ => hi = line_start,
+            }
+        }
+
+        self.index.seek(SeekFrom::Start(lo))?;
+        let mut found = vec![];
+        while let Some(entry) = self.next_entry(&mut line)? {
+            match entry.id.as_slice().cmp(id) {
+                Ordering::Less => continue,
+                Ordering::Equal => found.push(entry),
+                Ordering::Greater => break,
+            }
+        }
+
+        Ok(found)
+    }
+
+    /// The records with identifier `id`, none if it is not in the file
+    pub fn fetch(&mut self, id: &str) -> Result<Vec<Record>, SeqtoolsError> {
+        let mut records = vec![];
+        for entry in self.lookup(id.as_bytes())? {
+            let bytes = self.file.read_at(entry.offset, entry.length)?;
+            let location = Location {
+                file: Some(self.name.clone()),
+                ..Default::default()
+            };
+            let mut reader = needletail::parse_fastx_reader(Cursor::new(bytes))
+                .map_err(|e| SeqtoolsError::from(e).with_location(location.clone()))?;
+            let record = match reader.next() {
+                Some(Ok(record)) => Record::from(&record),
+                Some(Err(e)) => return Err(SeqtoolsError::from(e).with_location(location)),
+                None => Record::new(vec![], vec![], None),
+            };
+            if record.id != entry.id {
+                return Err(SeqtoolsError::invalid_argument(format!(
+                    "The index of {} is out of date, rebuild it with `seqtools index`",
+                    self.name
+                )));
+            }
+            records.push(record);
+        }
+
+        Ok(records)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::FileTimes;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::testing::TempFile;
+
+    fn seqs(index: &mut IdIndex, id: &str) -> Vec<Vec<u8>> {
+        let records = index.fetch(id).unwrap();
+        assert!(records.iter().all(|record| record.id == id.as_bytes()));
+        records.into_iter().map(|record| record.seq).collect()
+    }
+
+    #[test]
+    fn fetches_fasta_records() {
+        let file = TempFile::new("index.fa", b">b\nGG\nCC\n>a\nAC\n\n>c d\nT\n>a\nTT\n");
+        assert!(IdIndex::open(&file.0).unwrap().is_none());
+        assert_eq!(build(&file.0).unwrap(), 4);
+
+        let mut index = IdIndex::open(&file.0).unwrap().unwrap();
+        assert_eq!(seqs(&mut index, "a"), [b"AC".to_vec(), b"TT".to_vec()]);
+        assert_eq!(seqs(&mut index, "b"), [b"GGCC".to_vec()]);
+        assert_eq!(seqs(&mut index, "c d"), [b"T".to_vec()]);
+        for missing in ["", "0", "aa", "c", "z"] {
+            assert!(seqs(&mut index, missing).is_empty(), "{missing}");
+        }
+    }
+
+    #[test]
+    fn fetches_fastq_records() {
+        let file = TempFile::new("index.fq", b"@r2\nAC\n+\nII\n@r1\nG\n+r1\n#\n");
+        build(&file.0).unwrap();
+
+        let mut index = IdIndex::open(&file.0).unwrap().unwrap();
+        let records = index.fetch("r1").unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].seq, b"G");
+        assert_eq!(records[0].qual.as_deref(), Some(b"#".as_slice()));
+    }
+
+    #[test]
+    fn searches_large_indexes() {
+        // Identifiers in shuffled order, with a repeated one at the end
+        let n = 10_000;
+        let ids: Vec<String> = (0..n).map(|i| format!("r{:05}", i * 7919 % n)).collect();
+        let mut text: String = ids.iter().map(|id| format!(">{id}\nACGT\n")).collect();
+        text.push_str(">r05000\nTTTT\n");
+        let file = TempFile::new("large.fa", text.as_bytes());
+
+        // Small chunks so that the index is built by merging sorted chunks
+        assert_eq!(build_index(&file.0, 1_000).unwrap(), n + 1);
+        let index = id_index_path(&file.0);
+        assert!(fs::metadata(&index).unwrap().len() > 2 * SCAN_LEN);
+        assert!(!index_path(&index, "part0").exists());
+
+        let mut index = IdIndex::open(&file.0).unwrap().unwrap();
+        for id in ["r00000", "r00001", "r04999", "r05001", "r09999"] {
+            assert_eq!(seqs(&mut index, id), [b"ACGT".to_vec()], "{id}");
+        }
+        assert_eq!(
+            seqs(&mut index, "r05000"),
+            [b"ACGT".to_vec(), b"TTTT".to_vec()]
+        );
+        for missing in ["r", "r000000", "r04999a", "r10000", "s"] {
+            assert!(seqs(&mut index, missing).is_empty(), "{missing}");
+        }
+    }
+
+    #[test]
+    fn rejects_out_of_date_indexes() {
+        let file = TempFile::new("stale.fa", b">a\nACGT\n>b\nACGT\n");
+        build(&file.0).unwrap();
+        assert!(IdIndex::open(&file.0).is_ok());
+
+        // Same size but records at other offsets
+        fs::write(&file.0, b">aa\nACG\n>b\nACGT\n").unwrap();
+        let later = fs::metadata(&file.0).unwrap().modified().unwrap() + Duration::from_secs(1);
+        File::options()
+            .write(true)
+            .open(&file.0)
+            .unwrap()
+            .set_times(FileTimes::new().set_modified(later))
+            .unwrap();
+        assert!(matches!(
+            IdIndex::open(&file.0),
+            Err(SeqtoolsError::InvalidArgument(_))
+        ));
+
+        build(&file.0).unwrap();
+        let mut index = IdIndex::open(&file.0).unwrap().unwrap();
+        assert_eq!(seqs(&mut index, "aa"), [b"ACG".to_vec()]);
+    }
+}
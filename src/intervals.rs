@@ -0,0 +1,146 @@
+//! Features located on sequences, given as coordinates on the command line or
+//! read from BED and GFF files.
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use crate::errors::SeqtoolsError;
+use crate::faidx::Region;
+
+/// A region of a sequence, on either strand
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Feature {
+    pub region: Region,
+    /// Name of the feature, from the name column of BED files or the `Name`
+    /// or `ID` attribute of GFF files
+    pub name: Option<String>,
+    /// Whether the feature is on the minus strand
+    pub reverse: bool,
+}
+
+impl Feature {
+    /// Parse a `name:start-end` region like [`Region::parse_coordinates`],
+    /// optionally followed by `:+` or `:-` for its strand
+    pub fn parse(text: &str) -> Result<Feature, SeqtoolsError> {
+        let (coordinates, reverse) = match text.strip_suffix(":-") {
+            Some(coordinates) => (coordinates, true),
+            None => (text.strip_suffix(":+").unwrap_or(text), false),
+        };
+
+        Ok(Feature {
+            region: Region::parse_coordinates(coordinates)?,
+            name: None,
+            reverse,
+        })
+    }
+
+    /// Identifier of the extracted sequence: the name of the feature, or its
+    /// region followed by `(-)` on the minus strand
+    pub fn id(&self) -> String {
+        match (&self.name, self.reverse) {
+            (Some(name), _) => name.clone(),
+            (None, false) => self.region.to_string(),
+            (None, true) => format!("{}(-)", self.region),
+        }
+    }
+}
+
+/// Lines of a feature file that hold features, with their line number.
+/// Blank, comment, track and browser lines are skipped.
+fn feature_lines(path: &Path) -> Result<Vec<(usize, String)>, SeqtoolsError> {
+    let mut lines = vec![];
+    for (i, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        if line.starts_with("##FASTA") {
+            break;
+        }
+        if line.trim().is_empty()
+            || line.starts_with('#')
+            || line.starts_with("track")
+            || line.starts_with("browser")
+        {
+            continue;
+        }
+        lines.push((i + 1, line));
+    }
+
+    Ok(lines)
+}
+
+fn invalid_line(path: &Path, line: usize, kind: &str) -> SeqtoolsError {
+    SeqtoolsError::invalid_argument(format!(
+        "Line {line} of {} is not a valid {kind} line",
+        path.display()
+    ))
+}
+
+/// Features of a BED file, with their name and strand when the file has those
+/// columns
+pub fn read_bed(path: &Path) -> Result<Vec<Feature>, SeqtoolsError> {
+    let mut features = vec![];
+    for (i, line) in feature_lines(path)? {
+        let fields: Vec<&str> = line.split('\t').collect();
+        let position = |i: usize| fields.get(i).and_then(|p| p.trim().parse::<u64>().ok());
+        let (Some(start), Some(end)) = (position(1), position(2)) else {
+            return Err(invalid_line(path, i, "BED"));
+        };
+        if start > end {
+            return Err(invalid_line(path, i, "BED"));
+        }
+
+        features.push(Feature {
+            region: Region {
+                name: fields[0].to_string(),
+                start,
+                end: Some(end),
+            },
+            name: fields
+                .get(3)
+                .map(|name| name.trim())
+                .filter(|name| !name.is_empty() && *name != ".")
+                .map(String::from),
+            reverse: fields.get(5).is_some_and(|strand| strand.trim() == "-"),
+        });
+    }
+
+    Ok(features)
+}
+
+/// Features of a GFF3 file, named after their `Name` attribute, or their `ID`
+/// if they have no name. Positions are converted from 1-based inclusive to
+/// 0-based half-open coordinates.
+pub fn read_gff(path: &Path) -> Result<Vec<Feature>, SeqtoolsError> {
+    let mut features = vec![];
+    for (i, line) in feature_lines(path)? {
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 9 {
+            return Err(invalid_line(path, i, "GFF"));
+        }
+        let position = |i: usize| fields[i].trim().parse::<u64>().ok().filter(|p| *p > 0);
+        let (Some(start), Some(end)) = (position(3), position(4)) else {
+            return Err(invalid_line(path, i, "GFF"));
+        };
+        if start > end {
+            return Err(invalid_line(path, i, "GFF"));
+        }
+
+        let attribute = |key: &str| {
+            fields[8]
+                .split(';')
+                .filter_map(|attribute| attribute.trim().split_once('='))
+                .find(|(name, _)| *name == key)
+                .map(|(_, value)| value.to_string())
+        };
+        features.push(Feature {
+            region: Region {
+                name: fields[0].to_string(),
+                start: start - 1,
+                end: Some(end),
+            },
+            name: attribute("Name").or_else(|| attribute("ID")),
+            reverse: fields[6] == "-",
+        });
+    }
+
+    Ok(features)
+}
@@ -0,0 +1,205 @@
+//! Counting of the k-mers of nucleotide sequences.
+//!
+//! K-mers are stored 2 bits per base in a `u64`, which bounds `k` to 31 and
+//! keeps each distinct k-mer at a fixed size whatever `k` is. K-mers with
+//! bases other than A, C, G and T (or U) are skipped. K-mers are written with
+//! U instead of T when the counted sequences are RNA, that is when they have
+//! uracils and no thymines.
+use std::collections::{BTreeMap, HashMap};
+
+use crate::errors::SeqtoolsError;
+
+/// Largest k-mer size that fits in the 2-bit encoding
+pub const MAX_K: usize = 31;
+
+fn encode(base: u8) -> Option<u64> {
+    match base.to_ascii_uppercase() {
+        b'A' => Some(0),
+        b'C' => Some(1),
+        b'G' => Some(2),
+        b'T' | b'U' => Some(3),
+        _ => None,
+    }
+}
+
+/// Number of occurrences of each k-mer in one or several sequences.
+/// Canonical counts add up each k-mer with its reverse complement, under the
+/// smallest of the two.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KmerCounts {
+    k: usize,
+    canonical: bool,
+    counts: HashMap<u64, u64>,
+    thymines: bool,
+    uracils: bool,
+}
+
+impl KmerCounts {
+    /// Counts of k-mers of size `k`, which must be between 1 and [`MAX_K`]
+    pub fn new(k: usize, canonical: bool) -> Result<Self, SeqtoolsError> {
+        if !(1..=MAX_K).contains(&k) {
+            return Err(SeqtoolsError::invalid_argument(format!(
+                "The k-mer size must be between 1 and {MAX_K}, not {k}"
+            )));
+        }
+
+        Ok(KmerCounts {
+            k,
+            canonical,
+            counts: HashMap::new(),
+            thymines: false,
+            uracils: false,
+        })
+    }
+
+    pub fn add(&mut self, seq: &[u8]) {
+        let mask = (1 << (2 * self.k)) - 1;
+        let shift = 2 * (self.k - 1);
+        let (mut forward, mut reverse, mut len) = (0, 0, 0);
+        for base in seq {
+            let Some(code) = encode(*base) else {
+                len = 0;
+                continue;
+            };
+            if code == 3 {
+                if base.eq_ignore_ascii_case(&b'U') {
+                    self.uracils = true;
+                } else {
+                    self.thymines = true;
+                }
+            }
+            forward = ((forward << 2) | code) & mask;
+            reverse = (reverse >> 2) | ((3 - code) << shift);
+            len += 1;
+            if len >= self.k {
+                let kmer = if self.canonical {
+                    forward.min(reverse)
+                } else {
+                    forward
+                };
+                *self.counts.entry(kmer).or_insert(0) += 1;
+            }
+        }
+    }
+
+    /// Add the counts of `other` to these
+    pub fn merge(&mut self, other: KmerCounts) {
+        self.thymines |= other.thymines;
+        self.uracils |= other.uracils;
+        if self.counts.is_empty() {
+            self.counts = other.counts;
+            return;
+        }
+        for (kmer, count) in other.counts {
+            *self.counts.entry(kmer).or_insert(0) += count;
+        }
+    }
+
+    /// Number of distinct k-mers
+    pub fn distinct(&self) -> usize {
+        self.counts.len()
+    }
+
+    fn decode(&self, kmer: u64) -> String {
+        let bases = if self.uracils && !self.thymines {
+            [b'A', b'C', b'G', b'U']
+        } else {
+            [b'A', b'C', b'G', b'T']
+        };
+        (0..self.k)
+            .rev()
+            .map(|i| bases[((kmer >> (2 * i)) & 3) as usize] as char)
+            .collect()
+    }
+
+    /// K-mers and their counts, in alphabetical order
+    pub fn sorted(&self) -> Vec<(String, u64)> {
+        let mut counts: Vec<(u64, u64)> = self.counts.iter().map(|(k, c)| (*k, *c)).collect();
+        counts.sort_unstable();
+        counts
+            .into_iter()
+            .map(|(kmer, count)| (self.decode(kmer), count))
+            .collect()
+    }
+
+    /// K-mer spectrum: the number of distinct k-mers seen each number of times
+    pub fn spectrum(&self) -> BTreeMap<u64, u64> {
+        let mut spectrum = BTreeMap::new();
+        for count in self.counts.values() {
+            *spectrum.entry(*count).or_insert(0) += 1;
+        }
+        spectrum
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counts(k: usize, canonical: bool, seqs: &[&[u8]]) -> KmerCounts {
+        let mut counts = KmerCounts::new(k, canonical).unwrap();
+        for seq in seqs {
+            counts.add(seq);
+        }
+        counts
+    }
+
+    fn kmers(expected: &[(&str, u64)]) -> Vec<(String, u64)> {
+        expected
+            .iter()
+            .map(|(kmer, count)| (kmer.to_string(), *count))
+            .collect()
+    }
+
+    #[test]
+    fn counts_stranded_kmers() {
+        let counts = counts(2, false, &[b"ACGTac"]);
+        assert_eq!(
+            counts.sorted(),
+            kmers(&[("AC", 2), ("CG", 1), ("GT", 1), ("TA", 1)])
+        );
+        assert_eq!(counts.distinct(), 4);
+    }
+
+    #[test]
+    fn counts_canonical_kmers() {
+        let counts = counts(3, true, &[b"AAAT", b"TTT"]);
+        // AAT and its reverse complement ATT are counted under AAT
+        assert_eq!(counts.sorted(), kmers(&[("AAA", 2), ("AAT", 1)]));
+    }
+
+    #[test]
+    fn restarts_after_other_bases() {
+        let counts = counts(3, false, &[b"ACGNACGTA"]);
+        assert_eq!(
+            counts.sorted(),
+            kmers(&[("ACG", 2), ("CGT", 1), ("GTA", 1)])
+        );
+    }
+
+    #[test]
+    fn encodes_the_largest_kmers() {
+        let seq = b"ACGTTGCAACGTTGCAACGTTGCAACGTTGCA";
+        let kmers = counts(MAX_K, true, &[seq]).sorted();
+        assert_eq!(kmers.len(), 2);
+        assert_eq!(kmers[0].0.len(), MAX_K);
+        assert!(KmerCounts::new(MAX_K + 1, true).is_err());
+        assert!(KmerCounts::new(0, true).is_err());
+    }
+
+    #[test]
+    fn merges_counts() {
+        let mut merged = counts(2, false, &[b"ACGU"]);
+        merged.merge(counts(2, false, &[b"ACA"]));
+        assert_eq!(
+            merged.sorted(),
+            kmers(&[("AC", 2), ("CA", 1), ("CG", 1), ("GU", 1)])
+        );
+        let spectrum: Vec<(u64, u64)> = merged.spectrum().into_iter().collect();
+        assert_eq!(spectrum, [(1, 3), (2, 1)]);
+
+        // K-mers are written with T as soon as a sequence has thymines
+        merged.merge(counts(2, false, &[b"TT"]));
+        assert_eq!(merged.sorted()[3], ("GT".to_string(), 1));
+    }
+}
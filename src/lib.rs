@@ -0,0 +1,414 @@
+//! Seqtools is a simple library to work with FASTX files.
+//!
+//! The `seqtools` command line utility is a thin layer over this crate: every
+//! report command is backed by a function in [`stats`] that takes an input
+//! reader and returns a plain result struct that the caller is free to print,
+//! serialize or process further. Commands that rewrite records are
+//! [`transform::RecordTransform`]s, listed in [`ops`]. Errors are
+//! [`errors::SeqtoolsError`]s that point at the file and record involved.
+use std::fmt::{self, Display};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use needletail::errors::ParseError;
+use needletail::FastxReader;
+
+use crate::errors::{Location, SeqtoolsError};
+use crate::formats::flatfile;
+use crate::reader::{ErrorHandler, OnError, RecordReader};
+
+pub mod errors;
+pub mod faidx;
+pub mod formats;
+pub mod index;
+pub mod intervals;
+pub mod kmers;
+pub mod locate;
+pub mod ops;
+pub mod pairs;
+pub mod reader;
+pub mod record;
+pub mod report;
+pub mod sequence;
+pub mod stats;
+#[cfg(test)]
+mod testing;
+pub mod transform;
+pub mod translation;
+pub mod viewer;
+pub mod writer;
+
+#[derive(Copy, Clone, ValueEnum, Debug, PartialEq, Eq)]
+pub enum Format {
+    Fasta,
+    Fastq,
+    /// Relaxed PHYLIP: taxon names are separated from sequences by whitespace
+    Phylip,
+    /// Strict PHYLIP: taxon names are padded or cut to 10 characters
+    PhylipStrict,
+    /// NEXUS file with a DATA block
+    Nexus,
+    /// Stockholm alignment, as used by Pfam, Rfam and HMMER
+    Stockholm,
+    /// Clustal alignment, as written by Clustal Omega and MUSCLE
+    Clustal,
+    /// Tab-separated `id<TAB>seq[<TAB>qual]` lines
+    Tsv,
+    /// JSON Lines: one `{"id", "desc", "seq", "qual"}` object per line
+    Jsonl,
+}
+
+#[derive(Copy, Clone, ValueEnum, Debug)]
+pub enum Molecule {
+    DNA,
+    RNA,
+    Protein,
+}
+
+#[derive(Copy, Clone, ValueEnum, Debug, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Bzip2,
+    Xz,
+    Zstd,
+}
+
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+const BZIP2_MAGIC: [u8; 3] = *b"BZh";
+const XZ_MAGIC: [u8; 6] = [0xFD, b'7', b'z', b'X', b'Z', 0x00];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+
+impl Compression {
+    /// Guess the compression format from the extension of a file
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("gz") => Compression::Gzip,
+            Some("bz2") => Compression::Bzip2,
+            Some("xz") => Compression::Xz,
+            Some("zst") => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+
+    /// Wrap a writer so that everything written to it is compressed
+    pub fn encoder(self, writer: Box<dyn Write>) -> io::Result<Box<dyn Write>> {
+        Ok(match self {
+            Compression::None => writer,
+            Compression::Gzip => Box::new(flate2::write::GzEncoder::new(
+                writer,
+                flate2::Compression::default(),
+            )),
+            Compression::Bzip2 => Box::new(bzip2::write::BzEncoder::new(
+                writer,
+                bzip2::Compression::default(),
+            )),
+            Compression::Xz => Box::new(xz2::write::XzEncoder::new(writer, 6)),
+            Compression::Zstd => Box::new(zstd::Encoder::new(writer, 0)?.auto_finish()),
+        })
+    }
+}
+
+/// Detect the compression of a stream from its first bytes and decompress it
+fn decompress<R: Read + Send + 'static>(reader: R) -> io::Result<Box<dyn BufRead + Send>> {
+    let mut reader = BufReader::new(reader);
+    let start = reader.fill_buf()?;
+
+    Ok(if start.starts_with(&GZIP_MAGIC) {
+        Box::new(BufReader::new(flate2::read::MultiGzDecoder::new(reader)))
+    } else if start.starts_with(&BZIP2_MAGIC) {
+        Box::new(BufReader::new(bzip2::read::MultiBzDecoder::new(reader)))
+    } else if start.starts_with(&XZ_MAGIC) {
+        Box::new(BufReader::new(xz2::read::XzDecoder::new_multi_decoder(
+            reader,
+        )))
+    } else if start.starts_with(&ZSTD_MAGIC) {
+        Box::new(BufReader::new(zstd::Decoder::with_buffer(reader)?))
+    } else {
+        Box::new(reader)
+    })
+}
+
+fn parse_fastx<R: Read + Send + 'static>(reader: R) -> Result<Box<dyn FastxReader>, ParseError> {
+    needletail::parse_fastx_reader(decompress(reader)?)
+}
+
+/// Open a FASTX reader on a file, or on stdin if no path is given.
+/// Compressed inputs are transparently decompressed.
+pub fn init_reader(input: Option<PathBuf>) -> Result<Box<dyn FastxReader>, ParseError> {
+    match input {
+        Some(path) => parse_fastx(File::open(path)?),
+        None => parse_fastx(io::stdin()),
+    }
+}
+
+/// A source of records: a file or stdin
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Input {
+    File(PathBuf),
+    Stdin,
+}
+
+impl Input {
+    /// Read from the given files one after the other, or from stdin if there are none
+    pub fn from_paths(paths: Vec<PathBuf>) -> Vec<Input> {
+        if paths.is_empty() {
+            vec![Input::Stdin]
+        } else {
+            paths.into_iter().map(Input::File).collect()
+        }
+    }
+
+    /// Open a reader on the records of the input
+    pub fn open(&self) -> Result<RecordReader, SeqtoolsError> {
+        self.open_with(&ErrorHandler::default())
+    }
+
+    /// Open a reader that hands malformed records over to `handler`
+    pub fn open_with(&self, handler: &ErrorHandler) -> Result<RecordReader, SeqtoolsError> {
+        let location = Location {
+            file: Some(self.to_string()),
+            ..Default::default()
+        };
+        let reader = self.reader(handler);
+
+        reader.map_err(|e| e.with_location(location))
+    }
+
+    fn reader(&self, handler: &ErrorHandler) -> Result<RecordReader, SeqtoolsError> {
+        let name = self.to_string();
+        let mut stream = self.stream()?;
+        if let Some(format @ (Format::Tsv | Format::Jsonl)) = formats::detect(&mut stream)? {
+            return Ok(RecordReader::lines(stream, format, name, handler.clone()));
+        }
+        if let Some(format) = formats::detect(&mut stream)? {
+            let alignment = formats::read(format, &mut stream)?;
+            return Ok(RecordReader::from_alignment(alignment, name));
+        }
+        if let Some(kind) = flatfile::detect(&mut stream)? {
+            return Ok(RecordReader::flatfile(
+                flatfile::Reader::new(stream, kind),
+                name,
+            ));
+        }
+
+        Ok(match handler.on_error() {
+            OnError::Fail => RecordReader::new(parse_fastx(stream)?, name),
+            OnError::Skip | OnError::Warn => RecordReader::lenient(stream, name, handler.clone()),
+        })
+    }
+
+    /// Open a reader on the entries of a GenBank or EMBL input, along with
+    /// their features
+    pub fn open_flatfile(&self) -> Result<flatfile::Reader, SeqtoolsError> {
+        let location = Location {
+            file: Some(self.to_string()),
+            ..Default::default()
+        };
+        let reader = self.flatfile_reader();
+
+        reader.map_err(|e| e.with_location(location))
+    }
+
+    fn flatfile_reader(&self) -> Result<flatfile::Reader, SeqtoolsError> {
+        let mut stream = self.stream()?;
+        match flatfile::detect(&mut stream)? {
+            Some(kind) => Ok(flatfile::Reader::new(stream, kind)),
+            None => Err(SeqtoolsError::Parse {
+                message: "Expected a GenBank or EMBL file".to_string(),
+                location: Location::default(),
+            }),
+        }
+    }
+
+    /// The decompressed bytes of the input
+    fn stream(&self) -> Result<Box<dyn BufRead + Send>, SeqtoolsError> {
+        Ok(match self {
+            Input::File(path) => decompress(File::open(path)?)?,
+            Input::Stdin => decompress(io::stdin())?,
+        })
+    }
+}
+
+impl Display for Input {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Input::File(path) => write!(f, "{}", path.display()),
+            Input::Stdin => write!(f, "stdin"),
+        }
+    }
+}
+
+/// Expand the glob patterns in a list of input paths. Paths without any glob
+/// special character are kept as is, patterns that match no file are an error.
+pub fn expand_inputs(patterns: &[String]) -> Result<Vec<PathBuf>, SeqtoolsError> {
+    let mut paths = vec![];
+    for pattern in patterns {
+        if !pattern.contains(['*', '?', '[']) {
+            paths.push(PathBuf::from(pattern));
+            continue;
+        }
+
+        let mut matched = glob::glob(pattern)
+            .map_err(|e| SeqtoolsError::invalid_argument(format!("Bad pattern '{pattern}': {e}")))?
+            .peekable();
+        if matched.peek().is_none() {
+            return Err(SeqtoolsError::invalid_argument(format!(
+                "No file matches '{pattern}'"
+            )));
+        }
+        for path in matched {
+            paths.push(path.map_err(io::Error::from)?);
+        }
+    }
+
+    Ok(paths)
+}
+
+/// Open a writer on a file, or on stdout if no path is given.
+/// If `compression` is not specified, files are compressed according to
+/// their extension (.gz, .bz2, .xz or .zst) and stdout is not compressed.
+pub fn init_writer(
+    out: Option<PathBuf>,
+    compression: Option<Compression>,
+) -> io::Result<Box<dyn Write>> {
+    let (writer, guessed): (Box<dyn Write>, _) = match out {
+        Some(path) => {
+            let guessed = Compression::from_path(&path);
+            (Box::new(File::create(path)?), guessed)
+        }
+        None => (Box::new(io::stdout()), Compression::None),
+    };
+    let writer = compression.unwrap_or(guessed).encoder(writer)?;
+
+    Ok(Box::new(BufWriter::new(writer)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::testing::TempFile;
+
+    const FASTQ: &[u8] = b"@a\nACGT\n+\nIIII\n@b\nGG\n+\n!!\n";
+
+    fn read_ids(input: &Input) -> Vec<String> {
+        input
+            .open_with(&ErrorHandler::default())
+            .unwrap()
+            .map(|record| String::from_utf8(record.unwrap().id).unwrap())
+            .collect()
+    }
+
+    fn write(path: &Path, compression: Option<Compression>) {
+        let mut writer = init_writer(Some(path.to_path_buf()), compression).unwrap();
+        writer.write_all(FASTQ).unwrap();
+        writer.flush().unwrap();
+    }
+
+    #[test]
+    fn guesses_compression_from_extensions() {
+        for (name, compression) in [
+            ("reads.fq.gz", Compression::Gzip),
+            ("reads.fq.bz2", Compression::Bzip2),
+            ("reads.fq.xz", Compression::Xz),
+            ("reads.fq.zst", Compression::Zstd),
+            ("reads.fq", Compression::None),
+            ("reads", Compression::None),
+        ] {
+            assert_eq!(Compression::from_path(Path::new(name)), compression);
+        }
+    }
+
+    #[test]
+    fn reads_compressed_outputs() {
+        for (name, magic) in [
+            ("out.fq.gz", &GZIP_MAGIC[..]),
+            ("out.fq.bz2", &BZIP2_MAGIC),
+            ("out.fq.xz", &XZ_MAGIC),
+            ("out.fq.zst", &ZSTD_MAGIC),
+            ("out.fq", b"@a"),
+        ] {
+            let file = TempFile::new(name, b"");
+            write(&file.0, None);
+            assert!(fs::read(&file.0).unwrap().starts_with(magic), "{name}");
+            assert_eq!(read_ids(&Input::File(file.0.clone())), ["a", "b"], "{name}");
+        }
+    }
+
+    #[test]
+    fn detects_compression_without_extensions() {
+        for compression in [Compression::Gzip, Compression::Xz, Compression::None] {
+            let file = TempFile::new("detected.fq", b"");
+            write(&file.0, Some(compression));
+            assert_eq!(read_ids(&Input::File(file.0.clone())), ["a", "b"]);
+        }
+
+        let file = TempFile::new("uncompressed.fq.gz", b"");
+        write(&file.0, Some(Compression::None));
+        assert_eq!(fs::read(&file.0).unwrap(), FASTQ);
+        assert_eq!(read_ids(&Input::File(file.0.clone())), ["a", "b"]);
+    }
+
+    #[test]
+    fn reads_concatenated_gzip_members() {
+        let file = TempFile::new("members.fq.gz", b"");
+        let mut content = vec![];
+        for member in [&b"@a\nAC\n+\nII\n"[..], b"@b\nGT\n+\nII\n"] {
+            let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+            encoder.write_all(member).unwrap();
+            content.extend(encoder.finish().unwrap());
+        }
+        fs::write(&file.0, content).unwrap();
+        assert_eq!(read_ids(&Input::File(file.0.clone())), ["a", "b"]);
+    }
+
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("seqtools-{}-{name}", std::process::id()));
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn expands_globs() {
+        let files: Vec<TempFile> = ["glob-b.fa", "glob-a.fa", "glob-c.fq"]
+            .iter()
+            .map(|name| TempFile::new(name, b">a\nA\n"))
+            .collect();
+        let (a, b, c) = (files[1].0.clone(), files[0].0.clone(), files[2].0.clone());
+
+        let patterns = [temp_path("glob-*.fa"), temp_path("glob-c.f?")];
+        assert_eq!(expand_inputs(&patterns).unwrap(), [a, b.clone(), c.clone()]);
+        let patterns = [temp_path("glob-[bc].f[aq]"), "missing.fa".to_string()];
+        assert_eq!(
+            expand_inputs(&patterns).unwrap(),
+            [b, c, PathBuf::from("missing.fa")]
+        );
+        assert!(expand_inputs(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_globs_without_matches() {
+        for pattern in [temp_path("no-match-*.fa"), temp_path("glob-[.fa")] {
+            match expand_inputs(&[pattern]).unwrap_err() {
+                SeqtoolsError::InvalidArgument(_) => {}
+                err => panic!("unexpected error {err:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn reads_stdin_without_input_files() {
+        assert_eq!(Input::from_paths(vec![]), [Input::Stdin]);
+        let paths = vec![PathBuf::from("a.fa"), PathBuf::from("b.fa")];
+        let inputs = Input::from_paths(paths);
+        assert_eq!(
+            inputs,
+            [Input::File("a.fa".into()), Input::File("b.fa".into())]
+        );
+        assert_eq!(inputs[0].to_string(), "a.fa");
+        assert_eq!(Input::Stdin.to_string(), "stdin");
+    }
+}
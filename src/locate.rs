@@ -0,0 +1,391 @@
+//! Search of patterns in sequences, on one or both strands.
+//!
+//! Patterns are literal strings, nucleotides with IUPAC ambiguity codes or
+//! regular expressions, all matched regardless of case. Literal and IUPAC
+//! patterns can match with a number of mismatches (Hamming distance), or of
+//! mismatches, insertions and deletions (edit distance).
+use clap::ValueEnum;
+use regex::bytes::{Regex, RegexBuilder};
+
+use crate::errors::SeqtoolsError;
+use crate::sequence;
+
+/// Strands of the sequences to search
+#[derive(Copy, Clone, ValueEnum, Debug, Default, PartialEq, Eq)]
+pub enum Strand {
+    #[default]
+    Both,
+    /// The sequences as they are
+    Forward,
+    /// The reverse complement of the sequences
+    Reverse,
+}
+
+#[derive(Debug, Clone)]
+enum Kind {
+    Literal(Vec<u8>),
+    /// Bit masks of the bases each position stands for, see [`base_mask`]
+    Degenerate(Vec<u8>),
+    Regex(Regex),
+}
+
+/// A pattern to search for
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    /// The pattern as given
+    pub text: String,
+    kind: Kind,
+}
+
+/// Bit mask of the bases that a nucleotide or IUPAC code stands for, 0 for
+/// other characters
+fn base_mask(code: u8) -> u8 {
+    sequence::bases(code)
+        .iter()
+        .map(|base| match base {
+            b'A' => 1,
+            b'C' => 2,
+            b'G' => 4,
+            _ => 8,
+        })
+        .fold(0, |mask, bit| mask | bit)
+}
+
+/// A match of a pattern, with 0-based half-open coordinates on the forward
+/// strand
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hit {
+    /// Index of the pattern in the list of searched patterns
+    pub pattern: usize,
+    pub start: usize,
+    pub end: usize,
+    /// Whether the pattern matched the reverse complement
+    pub reverse: bool,
+    /// Number of mismatches, or of edits, between the pattern and the match
+    pub distance: usize,
+    /// The matching part of the sequence, on the strand of the pattern
+    pub matched: Vec<u8>,
+}
+
+impl Pattern {
+    /// A pattern matching exactly its characters
+    pub fn literal(text: &str) -> Self {
+        Pattern {
+            text: text.to_string(),
+            kind: Kind::Literal(text.to_ascii_uppercase().into_bytes()),
+        }
+    }
+
+    /// A nucleotide pattern whose IUPAC codes match any of the bases they
+    /// stand for. A sequence position matches when all the bases it stands
+    /// for are allowed, so an `N` in the sequence only matches an `N`.
+    pub fn degenerate(text: &str) -> Result<Self, SeqtoolsError> {
+        let masks: Vec<u8> = text.bytes().map(base_mask).collect();
+        if masks.contains(&0) {
+            return Err(SeqtoolsError::invalid_argument(format!(
+                "'{text}' is not made of nucleotides and IUPAC codes"
+            )));
+        }
+
+        Ok(Pattern {
+            text: text.to_string(),
+            kind: Kind::Degenerate(masks),
+        })
+    }
+
+    pub fn regex(text: &str) -> Result<Self, SeqtoolsError> {
+        let regex = RegexBuilder::new(text)
+            .case_insensitive(true)
+            .build()
+            .map_err(|e| SeqtoolsError::invalid_argument(format!("Invalid regex: {e}")))?;
+
+        Ok(Pattern {
+            text: text.to_string(),
+            kind: Kind::Regex(regex),
+        })
+    }
+
+    fn len(&self) -> Option<usize> {
+        match &self.kind {
+            Kind::Literal(bases) | Kind::Degenerate(bases) => Some(bases.len()),
+            Kind::Regex(_) => None,
+        }
+    }
+
+    fn matches(&self, i: usize, base: u8) -> bool {
+        match &self.kind {
+            Kind::Literal(bases) => bases[i] == base.to_ascii_uppercase(),
+            Kind::Degenerate(masks) => {
+                let mask = base_mask(base);
+                mask != 0 && mask & !masks[i] == 0
+            }
+            Kind::Regex(_) => unreachable!("regexes are not matched base by base"),
+        }
+    }
+
+    /// `(start, end, distance)` of the matches in `seq`
+    fn find(&self, seq: &[u8], max_distance: usize, edit: bool) -> Vec<(usize, usize, usize)> {
+        match &self.kind {
+            Kind::Regex(regex) => regex
+                .find_iter(seq)
+                .map(|found| (found.start(), found.end(), 0))
+                .collect(),
+            _ if edit => self.find_edits(seq, max_distance),
+            _ => self.find_mismatches(seq, max_distance),
+        }
+    }
+
+    /// Matches with at most `max_distance` mismatches, overlapping ones
+    /// included
+    fn find_mismatches(&self, seq: &[u8], max_distance: usize) -> Vec<(usize, usize, usize)> {
+        let len = self.len().unwrap_or_default();
+        if len == 0 || len > seq.len() {
+            return vec![];
+        }
+
+        let mut hits = vec![];
+        for (start, window) in seq.windows(len).enumerate() {
+            let mut distance = 0;
+            for (i, base) in window.iter().enumerate() {
+                if !self.matches(i, *base) {
+                    distance += 1;
+                    if distance > max_distance {
+                        break;
+                    }
+                }
+            }
+            if distance <= max_distance {
+                hits.push((start, start + len, distance));
+            }
+        }
+
+        hits
+    }
+
+    /// Matches with at most `max_distance` edits. Matches ending at
+    /// consecutive positions are variations of the same match, only the
+    /// closest one of each run is kept.
+    fn find_edits(&self, seq: &[u8], max_distance: usize) -> Vec<(usize, usize, usize)> {
+        let len = self.len().unwrap_or_default();
+        // Edit distance between the first i characters of the pattern and the
+        // best matching sequence ending at the current position, and the
+        // start of that sequence
+        let mut distances: Vec<usize> = (0..=len).collect();
+        let mut starts = vec![0; len + 1];
+        let mut hits = vec![];
+        let mut run: Option<(usize, usize, usize)> = None;
+
+        for (j, base) in seq.iter().enumerate() {
+            let mut diagonal = (distances[0], starts[0]);
+            distances[0] = 0;
+            starts[0] = j + 1;
+            for i in 1..=len {
+                let substitution = diagonal.0 + usize::from(!self.matches(i - 1, *base));
+                let mut best = (substitution, diagonal.1);
+                if distances[i - 1] + 1 < best.0 {
+                    best = (distances[i - 1] + 1, starts[i - 1]);
+                }
+                if distances[i] + 1 < best.0 {
+                    best = (distances[i] + 1, starts[i]);
+                }
+                diagonal = (distances[i], starts[i]);
+                (distances[i], starts[i]) = best;
+            }
+
+            let end = j + 1;
+            if distances[len] <= max_distance {
+                run = match run {
+                    Some(best) if best.2 <= distances[len] => Some(best),
+                    _ => Some((starts[len], end, distances[len])),
+                };
+            } else if let Some(best) = run.take() {
+                hits.push(best);
+            }
+        }
+        hits.extend(run);
+
+        hits
+    }
+}
+
+/// Options of a search
+#[derive(Debug, Clone, Default)]
+pub struct Search {
+    pub patterns: Vec<Pattern>,
+    pub strand: Strand,
+    /// Number of differences allowed between patterns and their matches
+    pub max_distance: usize,
+    /// Count insertions and deletions as differences, besides mismatches
+    pub edit: bool,
+}
+
+impl Search {
+    /// Check that the options make sense for the patterns: regexes have no
+    /// distance, and patterns must be longer than the distance so that they
+    /// do not match everywhere
+    pub fn check(&self) -> Result<(), SeqtoolsError> {
+        for pattern in self.patterns.iter() {
+            match pattern.len() {
+                None if self.max_distance > 0 => {
+                    return Err(SeqtoolsError::invalid_argument(
+                        "Regular expressions cannot match with a distance",
+                    ))
+                }
+                Some(len) if len <= self.max_distance => {
+                    return Err(SeqtoolsError::invalid_argument(format!(
+                        "Pattern '{}' must be longer than the maximum distance",
+                        pattern.text
+                    )))
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Matches of all the patterns in `seq`, ordered by position
+    pub fn find(&self, seq: &[u8]) -> Vec<Hit> {
+        let mut hits = vec![];
+        if self.strand != Strand::Reverse {
+            for (index, pattern) in self.patterns.iter().enumerate() {
+                for (start, end, distance) in pattern.find(seq, self.max_distance, self.edit) {
+                    hits.push(Hit {
+                        pattern: index,
+                        start,
+                        end,
+                        reverse: false,
+                        distance,
+                        matched: seq[start..end].to_vec(),
+                    });
+                }
+            }
+        }
+        if self.strand != Strand::Forward {
+            let reverse = sequence::reverse_complement(seq);
+            for (index, pattern) in self.patterns.iter().enumerate() {
+                for (start, end, distance) in pattern.find(&reverse, self.max_distance, self.edit) {
+                    hits.push(Hit {
+                        pattern: index,
+                        start: seq.len() - end,
+                        end: seq.len() - start,
+                        reverse: true,
+                        distance,
+                        matched: reverse[start..end].to_vec(),
+                    });
+                }
+            }
+        }
+
+        hits.sort_by_key(|hit| (hit.start, hit.end, hit.pattern, hit.reverse));
+        hits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn search(patterns: Vec<Pattern>, strand: Strand, max_distance: usize, edit: bool) -> Search {
+        Search {
+            patterns,
+            strand,
+            max_distance,
+            edit,
+        }
+    }
+
+    /// `(start, end, reverse, distance)` of the hits of a search
+    fn hits(search: &Search, seq: &[u8]) -> Vec<(usize, usize, bool, usize)> {
+        search
+            .find(seq)
+            .into_iter()
+            .map(|hit| (hit.start, hit.end, hit.reverse, hit.distance))
+            .collect()
+    }
+
+    #[test]
+    fn finds_literal_patterns() {
+        let search = search(vec![Pattern::literal("aca")], Strand::Forward, 0, false);
+        assert_eq!(
+            hits(&search, b"ACACAgaca"),
+            [(0, 3, false, 0), (2, 5, false, 0), (6, 9, false, 0)]
+        );
+        assert_eq!(hits(&search, b"AC"), []);
+    }
+
+    #[test]
+    fn finds_reverse_complements() {
+        let search = search(vec![Pattern::literal("AAC")], Strand::Both, 0, false);
+        let found = search.find(b"GGAACTTGTT");
+        assert_eq!(found.len(), 2);
+        assert_eq!(
+            (found[0].start, found[0].end, found[0].reverse),
+            (2, 5, false)
+        );
+        assert_eq!(
+            (found[1].start, found[1].end, found[1].reverse),
+            (7, 10, true)
+        );
+        assert_eq!(found[1].matched, b"AAC");
+
+        let reverse = Search {
+            strand: Strand::Reverse,
+            ..search
+        };
+        assert_eq!(hits(&reverse, b"GGAACTTGTT"), [(7, 10, true, 0)]);
+    }
+
+    #[test]
+    fn finds_mismatches() {
+        let search = search(vec![Pattern::literal("ACGT")], Strand::Forward, 1, false);
+        assert_eq!(
+            hits(&search, b"ACTTACGT"),
+            [(0, 4, false, 1), (4, 8, false, 0)]
+        );
+    }
+
+    #[test]
+    fn finds_edits() {
+        let search = search(vec![Pattern::literal("ACGTACGT")], Strand::Forward, 1, true);
+        // Deletion of the G
+        assert_eq!(hits(&search, b"TTACTACGTTT"), [(2, 9, false, 1)]);
+        // Insertion of a C
+        assert_eq!(hits(&search, b"TTACGTCACGTTT"), [(2, 11, false, 1)]);
+        // Exact match, whose neighbours with one edit are not reported
+        assert_eq!(hits(&search, b"GGACGTACGTGG"), [(2, 10, false, 0)]);
+        assert_eq!(hits(&search, b"ACGAAAGT"), []);
+    }
+
+    #[test]
+    fn finds_degenerate_patterns() {
+        let pattern = Pattern::degenerate("GAWTC").unwrap();
+        let search = search(vec![pattern], Strand::Forward, 0, false);
+        assert_eq!(
+            hits(&search, b"GAATCGATTCGACTCGANTC"),
+            [(0, 5, false, 0), (5, 10, false, 0)]
+        );
+        assert!(Pattern::degenerate("ACGX").is_err());
+    }
+
+    #[test]
+    fn finds_regexes() {
+        let pattern = Pattern::regex("GA+T").unwrap();
+        let search = search(vec![pattern], Strand::Both, 0, false);
+        assert_eq!(
+            hits(&search, b"cgaaatc"),
+            [(1, 6, false, 0), (4, 7, true, 0)]
+        );
+        assert!(Pattern::regex("(").is_err());
+    }
+
+    #[test]
+    fn checks_options() {
+        let regex = search(vec![Pattern::regex("A+").unwrap()], Strand::Both, 1, false);
+        assert!(regex.check().is_err());
+        let short = search(vec![Pattern::literal("AC")], Strand::Both, 2, true);
+        assert!(short.check().is_err());
+        let valid = search(vec![Pattern::literal("ACG")], Strand::Both, 2, true);
+        assert!(valid.check().is_ok());
+    }
+}
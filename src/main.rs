@@ -1,17 +1,70 @@
-use clap::{Parser, Subcommand, ValueEnum};
+use clap::{Parser, Subcommand};
 use std::error::Error;
 use std::path::PathBuf;
 
+use seqtools::kmers;
+use seqtools::locate::Strand;
+use seqtools::pairs::Mates;
+use seqtools::reader::{ErrorHandler, OnError};
+use seqtools::report::OutputFormat;
+use seqtools::translation::{self, Frame, GeneticCode};
+use seqtools::{errors, expand_inputs, init_writer, Compression, Format, Input, Molecule};
+
 mod commands;
-mod errors;
 #[derive(Parser, Debug)]
 #[clap(author, version, verbatim_doc_comment)]
 /// Seqtools is a simple utility to work with FASTX files from the command line.
-/// It seamlessly handles compressed files (.gz, .xz or bz2 formats).
+/// It seamlessly handles compressed files (.gz, .xz, .bz2 or .zst formats).
 pub struct Cli {
-    /// Path to an input FASTX file. [default: stdin]
+    /// Path to an input FASTX file. Can be repeated or be a quoted glob pattern
+    /// (e.g. 'samples/*.fq.gz') to read several files. [default: stdin]
     #[arg(short, long = "in", value_name = "FILE", global = true)]
-    input: Option<PathBuf>,
+    input: Vec<String>,
+
+    /// Path to the file holding the mates 2 of the paired-end reads of --in.
+    /// Repeated like --in, in the same order
+    #[arg(long = "in2", value_name = "FILE", global = true)]
+    input2: Vec<String>,
+
+    /// The input files hold paired-end reads, each mate 1 followed by its mate 2
+    #[arg(long, global = true, conflicts_with = "input2")]
+    interleaved: bool,
+
+    /// Write the mates 2 of paired-end reads to this file instead of after
+    /// their mate 1
+    #[arg(long, value_name = "FILE", global = true)]
+    out2: Option<PathBuf>,
+
+    /// Compression of the output. [default: guessed from the output file extension, none for stdout]
+    #[arg(short = 'z', long = "compress", value_enum, global = true)]
+    compression: Option<Compression>,
+
+    /// Output format of report commands (count, length, freqs, kmers, duplicates and locate)
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text, global = true)]
+    output_format: OutputFormat,
+
+    /// What to do with malformed input records. A summary of dropped records is
+    /// printed at the end.
+    #[arg(long, value_enum, default_value_t = OnError::Fail, global = true)]
+    on_error: OnError,
+
+    /// Write the malformed records dropped with --on-error skip or warn to this file
+    #[arg(long, value_name = "FILE", global = true)]
+    rejects: Option<PathBuf>,
+
+    /// Number of characters per sequence line in FASTA output, 0 to write each
+    /// sequence on a single line
+    #[arg(long, value_name = "N", default_value_t = 0, global = true)]
+    line_width: usize,
+
+    /// Write PHYLIP alignments in interleaved blocks of 60 columns instead of one
+    /// line per sequence
+    #[arg(long, global = true)]
+    phylip_interleaved: bool,
+
+    /// Number of threads used by count, length, freqs, kmers and duplicates
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..), global = true)]
+    threads: u16,
 
     #[command(subcommand)]
     command: Commands,
@@ -39,6 +92,35 @@ pub enum Commands {
         #[arg(short = 's', long = "per-sequence")]
         per_sequence: bool,
     },
+    #[clap(verbatim_doc_comment)]
+    /// Count the k-mers of nucleotide sequences
+    ///
+    /// K-mers are counted across all the input files, or per sequence, and
+    /// printed in alphabetical order. Each k-mer is counted together with its
+    /// reverse complement, under the first of the two, unless --stranded is
+    /// given. K-mers with bases other than A, C, G and T (or U) are skipped,
+    /// and k-mers of RNA sequences, with uracils and no thymines, are written
+    /// with U.
+    ///
+    /// With --spectrum, prints how many distinct k-mers are seen each number
+    /// of times instead, and draws it on stderr.
+    ///
+    /// ## Example
+    /// `$ seqtools -i reads.fq.gz kmers -k 21 --spectrum`
+    Kmers {
+        /// Size of the k-mers
+        #[arg(short, long = "kmer-size", value_name = "N", value_parser = clap::value_parser!(u8).range(1..=kmers::MAX_K as i64))]
+        k: u8,
+        /// Count k-mers as they are instead of with their reverse complement
+        #[arg(long)]
+        stranded: bool,
+        /// Count k-mers per sequence instead of globally
+        #[arg(short = 's', long = "per-sequence", conflicts_with = "spectrum")]
+        per_sequence: bool,
+        /// Print the k-mer spectrum instead of the counts
+        #[arg(long)]
+        spectrum: bool,
+    },
     /// Generate random sequences with normally distributed lengths
     Random {
         /// number of sequences to generate
@@ -72,6 +154,54 @@ pub enum Commands {
         out: Option<PathBuf>,
     },
     #[clap(verbatim_doc_comment)]
+    /// Extract the sequences of features from GenBank or EMBL files
+    ///
+    /// Each feature is named `<accession>_<key>_<n>`, followed by its qualifiers
+    /// and location, e.g. `[gene=thrL] [locus_tag=b0001] [location=190..255]`.
+    /// Sequences on the complementary strand are reverse complemented and the
+    /// parts of `join` locations are concatenated.
+    Features {
+        /// Feature keys to extract. Can be repeated
+        #[arg(short, long = "key", value_name = "KEY", default_values_t = ["CDS".to_string(), "gene".to_string()])]
+        keys: Vec<String>,
+        /// Path to output file [default: stdout]
+        #[arg(short, long, value_name = "FILE")]
+        out: Option<PathBuf>,
+        /// Format of output sequences
+        #[arg(short, long, value_enum, default_value_t=Format::Fasta)]
+        to: Format,
+    },
+    #[clap(verbatim_doc_comment)]
+    /// Index a FASTA or FASTQ file and extract regions of its sequences
+    ///
+    /// Without regions, writes a samtools compatible `<file>.fai` index, and a
+    /// `<file>.gzi` index for files compressed with bgzip. With regions, reads
+    /// them from the file through its index, which is built first if missing.
+    ///
+    /// ## Example
+    /// `$ seqtools -i genome.fa faidx chr1:1000-2000 chr2`
+    Faidx {
+        /// Regions to extract, as `name`, `name:start` or `name:start-end` with
+        /// 1-based inclusive positions
+        regions: Vec<String>,
+        /// BED file of regions to extract
+        #[arg(short, long, value_name = "FILE")]
+        bed: Option<PathBuf>,
+        /// Path to output file [default: stdout]
+        #[arg(short, long, value_name = "FILE")]
+        out: Option<PathBuf>,
+    },
+    #[clap(verbatim_doc_comment)]
+    /// Index the identifiers of FASTA or FASTQ files for select
+    ///
+    /// Writes a `<file>.idx` index next to each input file. When all its
+    /// inputs have one, `select` reads the requested records directly instead
+    /// of scanning the files. Compressed files must be compressed with bgzip.
+    ///
+    /// ## Example
+    /// `$ seqtools -i reads.fq.gz index`
+    Index,
+    #[clap(verbatim_doc_comment)]
     /// Select sequences from file by identifier or index
     ///
     /// ## Examples
@@ -128,6 +258,13 @@ pub enum Commands {
     /// >Seq5
     /// ATATATATA
     /// ```
+    ///
+    /// Records are written in the order of the input files. If every input
+    /// file was indexed with `seqtools index`, the records are instead read
+    /// through the index and written in the order of the requested
+    /// identifiers, file by file. Only the requested records are then read,
+    /// so --on-error does not apply: a malformed requested record is an
+    /// error, and other records are not checked.
     Select {
         /// List of sequence identifiers
         ids: Option<Vec<String>>,
@@ -140,6 +277,9 @@ pub enum Commands {
         /// Path to output file [default: stdout]
         #[arg(short, long, value_name = "FILE")]
         out: Option<PathBuf>,
+        /// Format of output sequences [default: same as input]
+        #[arg(short, long, value_enum)]
+        to: Option<Format>,
     },
     #[clap(verbatim_doc_comment)]
     /// Rename sequences in a fasta file
@@ -149,10 +289,10 @@ pub enum Commands {
     ///    - Numbers: replace sequence header with its index
     ///
     ///    - File: You can define new names by writing them in a tab-separated
-    ///            file with the following format on each line:
-    ///            <old_name>\t<new_name>
-    ///            Sequences whose name isn't specified in this file will not
-    ///            be renamed.
+    ///      file with the following format on each line:
+    ///      <old_name>\t<new_name>
+    ///      Sequences whose name isn't specified in this file will not
+    ///      be renamed.
     Rename {
         /// Rename the sequences with their index
         #[arg(short, long, group = "method")]
@@ -163,6 +303,9 @@ pub enum Commands {
         /// Path to output file [default: stdout]
         #[arg(short, long, value_name = "FILE")]
         out: Option<PathBuf>,
+        /// Format of output sequences [default: same as input]
+        #[arg(short, long, value_enum)]
+        to: Option<Format>,
     },
     #[clap(verbatim_doc_comment)]
     /// Add a common string to as a prefix or suffix to each sequence header
@@ -179,6 +322,9 @@ pub enum Commands {
         /// Path to output file [default: stdout]
         #[arg(short, long, value_name = "FILE")]
         out: Option<PathBuf>,
+        /// Format of output sequences [default: same as input]
+        #[arg(short, long, value_enum)]
+        to: Option<Format>,
     },
     /// Remove a certain number of characters from the beginning or end of each sequence
     Trim {
@@ -190,6 +336,91 @@ pub enum Commands {
         /// Path to output file [default: stdout]
         #[arg(short, long, value_name = "FILE")]
         out: Option<PathBuf>,
+        /// Format of output sequences [default: same as input]
+        #[arg(short, long, value_enum)]
+        to: Option<Format>,
+    },
+    #[clap(verbatim_doc_comment)]
+    /// Reverse complement DNA or RNA sequences
+    ///
+    /// All IUPAC ambiguity codes are complemented, the case of each base is
+    /// kept and FASTQ qualities are reversed. Sequences with uracils and no
+    /// thymines are complemented as RNA. Only the sequences whose identifier is
+    /// given, on the command line or in a file, are reverse complemented if
+    /// there are any.
+    ///
+    /// ## Example
+    /// `$ seqtools -i reads.fq revcomp -f minus_strand.txt`
+    Revcomp {
+        /// Identifiers of the sequences to reverse complement [default: all]
+        ids: Option<Vec<String>>,
+        /// Path to a file containing sequence identifiers (1 per line)
+        #[arg(short = 'f', long, value_name = "FILE")]
+        ids_file: Option<PathBuf>,
+        /// Path to output file [default: stdout]
+        #[arg(short, long, value_name = "FILE")]
+        out: Option<PathBuf>,
+        /// Format of output sequences [default: same as input]
+        #[arg(short, long, value_enum)]
+        to: Option<Format>,
+    },
+    #[clap(verbatim_doc_comment)]
+    /// Translate nucleotide sequences into proteins
+    ///
+    /// Codons with IUPAC ambiguity codes are translated when all the codons
+    /// they stand for give the same amino acid, and are X otherwise. In aligned
+    /// sequences, codons made of gaps are translated to a gap. Stop codons are
+    /// written as *. With several frames, the identifier of each translation
+    /// gets a _frame<N> suffix.
+    ///
+    /// Genetic codes (NCBI translation tables):
+    ///    1  Standard
+    ///    2  Vertebrate Mitochondrial
+    ///    3  Yeast Mitochondrial
+    ///    4  Mold, Protozoan, and Coelenterate Mitochondrial and Mycoplasma/Spiroplasma
+    ///    5  Invertebrate Mitochondrial
+    ///    6  Ciliate, Dasycladacean and Hexamita Nuclear
+    ///    9  Echinoderm and Flatworm Mitochondrial
+    ///   10  Euplotid Nuclear
+    ///   11  Bacterial, Archaeal and Plant Plastid
+    ///   12  Alternative Yeast Nuclear
+    ///   13  Ascidian Mitochondrial
+    ///   14  Alternative Flatworm Mitochondrial
+    ///   15  Blepharisma Nuclear
+    ///   16  Chlorophycean Mitochondrial
+    ///   21  Trematode Mitochondrial
+    ///   22  Scenedesmus obliquus Mitochondrial
+    ///   23  Thraustochytrium Mitochondrial
+    ///   24  Rhabdopleuridae Mitochondrial
+    ///   25  Candidate Division SR1 and Gracilibacteria
+    ///   26  Pachysolen tannophilus Nuclear
+    ///   27  Karyorelict Nuclear
+    ///   28  Condylostoma Nuclear
+    ///   29  Mesodinium Nuclear
+    ///   30  Peritrich Nuclear
+    ///   31  Blastocrithidia Nuclear
+    ///   32  Balanophoraceae Plastid
+    ///   33  Cephalodiscidae Mitochondrial
+    ///
+    /// ## Example
+    /// `$ seqtools -i genes.fa translate -g 11 --to-stop`
+    Translate {
+        /// Genetic code to translate with, by NCBI table number
+        #[arg(short = 'g', long, value_name = "ID", default_value = "1", value_parser = parse_genetic_code)]
+        table: &'static GeneticCode,
+        /// Reading frame to translate: 1 to 3 on the forward strand, -1 to -3
+        /// on the reverse complement, or all for the six of them
+        #[arg(long, value_enum, default_value = "1", allow_hyphen_values = true)]
+        frame: Frame,
+        /// End each protein before its first stop codon
+        #[arg(long)]
+        to_stop: bool,
+        /// Path to output file [default: stdout]
+        #[arg(short, long, value_name = "FILE")]
+        out: Option<PathBuf>,
+        /// Format of output sequences [default: same as input]
+        #[arg(short, long, value_enum)]
+        to: Option<Format>,
     },
     /// Clip all sequences in the alignment to a maximum length
     Clip {
@@ -198,6 +429,84 @@ pub enum Commands {
         /// Path to output file [default: stdout]
         #[arg(short, long, value_name = "FILE")]
         out: Option<PathBuf>,
+        /// Format of output sequences [default: same as input]
+        #[arg(short, long, value_enum)]
+        to: Option<Format>,
+    },
+    #[clap(verbatim_doc_comment)]
+    /// Extract parts of sequences by coordinates or from a BED or GFF file
+    ///
+    /// Features are matched to records by the first word of their identifier
+    /// and written in the order of the input, each record giving all its
+    /// features. Features on the minus strand are reverse complemented.
+    /// Extracted sequences are named after the name column of BED files or
+    /// the Name or ID attribute of GFF files, and after their region
+    /// otherwise, with a (-) suffix on the minus strand.
+    ///
+    /// ## Example
+    /// `$ seqtools -i genome.fa subseq chr1:101-200 chr2:51-80:- --flank 10`
+    Subseq {
+        /// Regions to extract, as `name:start-end` or `name:start` with 1-based
+        /// inclusive positions, followed by `:-` for the minus strand
+        regions: Vec<String>,
+        /// BED file of regions to extract
+        #[arg(short, long, value_name = "FILE")]
+        bed: Option<PathBuf>,
+        /// GFF3 file of features to extract
+        #[arg(short, long, value_name = "FILE")]
+        gff: Option<PathBuf>,
+        /// Number of bases to add on both sides of each region
+        #[arg(long, value_name = "N", default_value_t = 0)]
+        flank: u64,
+        /// Path to output file [default: stdout]
+        #[arg(short, long, value_name = "FILE")]
+        out: Option<PathBuf>,
+        /// Format of output sequences [default: same as input]
+        #[arg(short, long, value_enum)]
+        to: Option<Format>,
+    },
+    #[clap(verbatim_doc_comment)]
+    /// Find the positions of patterns in sequences
+    ///
+    /// Patterns are matched regardless of case, on both strands unless told
+    /// otherwise, and are literal unless --degenerate or --regex is given.
+    /// Matches are printed as BED lines with --bed, and otherwise as
+    /// `id, pattern, strand, start, end, distance, match` rows with 1-based
+    /// inclusive positions, in the format set by --output-format.
+    ///
+    /// With a maximum distance, patterns match with up to that many
+    /// mismatches, or edits with --edit. Overlapping matches are all reported,
+    /// except with --edit where only the closest of the matches ending at
+    /// consecutive positions is.
+    ///
+    /// ## Example
+    /// `$ seqtools -i genome.fa locate --degenerate GAATTC GGNNCC --bed`
+    Locate {
+        /// Patterns to search for
+        patterns: Vec<String>,
+        /// Path to a file containing patterns (1 per line)
+        #[arg(short = 'f', long, value_name = "FILE")]
+        patterns_file: Option<PathBuf>,
+        /// Match IUPAC ambiguity codes of the patterns with the bases they
+        /// stand for
+        #[arg(short, long, conflicts_with = "regex")]
+        degenerate: bool,
+        /// Patterns are regular expressions
+        #[arg(short, long)]
+        regex: bool,
+        /// Strands to search
+        #[arg(short, long, value_enum, default_value_t = Strand::Both)]
+        strand: Strand,
+        /// Maximum number of differences between a pattern and its matches
+        #[arg(short = 'm', long, value_name = "N", default_value_t = 0)]
+        max_distance: usize,
+        /// Count insertions and deletions as differences, not only mismatches
+        #[arg(short, long)]
+        edit: bool,
+        /// Print the matches as BED lines: id, start, end, pattern, distance
+        /// and strand
+        #[arg(short, long)]
+        bed: bool,
     },
     /// Check if alignment has duplicate sequences
     Duplicates {
@@ -216,47 +525,265 @@ pub enum Commands {
         verbose: u8,
     },
     #[clap(verbatim_doc_comment)]
+    /// Interleave the mates of paired-end reads into a single file
+    ///
+    /// Mates 1 are read from --in and mates 2 from --in2, and each mate 1 is
+    /// written followed by its mate 2.
+    ///
+    /// ## Example
+    /// `$ seqtools -i R1.fq.gz --in2 R2.fq.gz interleave -o reads.fq.gz`
+    Interleave {
+        /// Path to output file [default: stdout]
+        #[arg(short, long, value_name = "FILE")]
+        out: Option<PathBuf>,
+        /// Format of output sequences [default: same as input]
+        #[arg(short, long, value_enum)]
+        to: Option<Format>,
+    },
+    #[clap(verbatim_doc_comment)]
+    /// Split interleaved paired-end reads into two files
+    ///
+    /// Mates 1 are written to --out and mates 2 to --out2.
+    ///
+    /// ## Example
+    /// `$ seqtools -i reads.fq.gz deinterleave -o R1.fq.gz --out2 R2.fq.gz`
+    Deinterleave {
+        /// Path to the output file of mates 1 [default: stdout]
+        #[arg(short, long, value_name = "FILE")]
+        out: Option<PathBuf>,
+        /// Format of output sequences [default: same as input]
+        #[arg(short, long, value_enum)]
+        to: Option<Format>,
+    },
+    #[clap(verbatim_doc_comment)]
+    /// Pair up the mates of paired-end reads that are out of sync
+    ///
+    /// Mates are matched by read name, the identifier without its `/1` or `/2`
+    /// suffix, e.g. after mates 1 and 2 were filtered separately. They are read
+    /// from --in and --in2, or from a single file where the first read of a
+    /// name is mate 1. Pairs are written interleaved, or to --out and --out2,
+    /// and the reads whose mate is missing to the singletons file.
+    ///
+    /// ## Example
+    /// `$ seqtools -i R1.fq --in2 R2.fq repair -o R1.fixed.fq --out2 R2.fixed.fq -s singles.fq`
+    Repair {
+        /// Path to output file [default: stdout]
+        #[arg(short, long, value_name = "FILE")]
+        out: Option<PathBuf>,
+        /// Path to the output file of reads without a mate [default: drop them]
+        #[arg(short, long, value_name = "FILE")]
+        singletons: Option<PathBuf>,
+        /// Format of output sequences [default: same as input]
+        #[arg(short, long, value_enum)]
+        to: Option<Format>,
+    },
+    #[clap(verbatim_doc_comment)]
     /// Get an interactive view of the alignment (like Seaview)
     ///
     /// This command does not support reading an alignment from standard input,
     /// you must specify an alignment file using the --in flag.
     View,
+    #[clap(verbatim_doc_comment)]
+    /// Run several rewriting commands one after the other in a single pass
+    ///
+    /// Steps are written like the corresponding commands and separated by commas,
+    /// either on their own or at the end of an argument. Commas inside an
+    /// argument, as in `subseq chr1:1,000-2,000`, are part of it. Steps behave
+    /// exactly like the standalone commands, but the input is only parsed once
+    /// and the output only written once. Options of pipe itself must
+    /// come before the first step.
+    ///
+    /// ## Example
+    /// `$ seqtools -i reads.fq pipe -o out.fq select -f ids.txt, trim 5 --from-start, add-id _x`
+    ///
+    /// is equivalent to:
+    ///
+    /// `$ seqtools -i reads.fq select -f ids.txt | seqtools trim 5 --from-start | seqtools add-id _x > out.fq`
+    ///
+    /// Commands that can be used as steps are: convert, select, rename, add-id,
+    /// trim, clip, revcomp, translate, subseq and de-duplicate. Subseq cannot
+    /// be used with paired-end input.
+    Pipe {
+        /// Comma separated steps to run
+        #[arg(
+            required = true,
+            num_args = 1..,
+            trailing_var_arg = true,
+            allow_hyphen_values = true
+        )]
+        steps: Vec<String>,
+        /// Path to output file [default: stdout]
+        #[arg(short, long, value_name = "FILE")]
+        out: Option<PathBuf>,
+        /// Format of output sequences [default: same as input]
+        #[arg(short, long, value_enum)]
+        to: Option<Format>,
+    },
 }
 
-#[derive(Copy, Clone, ValueEnum, Debug)]
-pub enum Format {
-    Fasta,
-    Fastq,
+/// Parser for a single step of the pipe command
+#[derive(Parser, Debug)]
+#[command(no_binary_name = true)]
+struct PipeStep {
+    #[command(subcommand)]
+    command: Commands,
 }
 
-#[derive(Copy, Clone, ValueEnum, Debug)]
-pub enum Molecule {
-    DNA,
-    RNA,
-    Protein,
+/// Look up a genetic code by its NCBI number
+fn parse_genetic_code(value: &str) -> Result<&'static GeneticCode, String> {
+    let id = value.parse().map_err(|e| format!("{e}"))?;
+    translation::genetic_code(id).ok_or_else(|| format!("no NCBI genetic code has number {id}"))
 }
 
-pub mod viewer;
+/// Split the arguments of the pipe command into steps and parse each step.
+/// Steps end at a comma that stands alone or ends an argument, and start
+/// after one that starts an argument. Commas inside arguments are kept, as in
+/// `chr1:1,000-2,000`.
+fn parse_steps(args: Vec<String>) -> Result<Vec<Commands>, clap::Error> {
+    let mut steps = vec![vec![]];
+    for arg in args {
+        let mut arg = arg.as_str();
+        if let Some(rest) = arg.strip_prefix(',') {
+            steps.push(vec![]);
+            arg = rest;
+        }
+        let ends_step = arg.ends_with(',');
+        let arg = arg.strip_suffix(',').unwrap_or(arg);
+        if !arg.is_empty() {
+            steps.last_mut().unwrap().push(arg.to_string());
+        }
+        if ends_step {
+            steps.push(vec![]);
+        }
+    }
+
+    steps
+        .into_iter()
+        .filter(|step| !step.is_empty())
+        .map(|step| PipeStep::try_parse_from(step).map(|parsed| parsed.command))
+        .collect()
+}
 
-fn main() -> Result<(), Box<dyn Error>> {
+fn main() {
     let cli = Cli::parse();
 
+    if let Err(e) = run(cli) {
+        eprintln!("Error: {e}");
+        std::process::exit(errors::exit_code(e.as_ref()));
+    }
+}
+
+fn run(cli: Cli) -> Result<(), Box<dyn Error>> {
     let line_ending = match std::env::consts::OS {
         "linux" | "macos" | "freebsd" | "netbsd" | "openbsd" => {
             needletail::parser::LineEnding::Unix
         }
         "windows" => needletail::parser::LineEnding::Windows,
-        _ => return Err(errors::MainError::new("Windows is not supported..").into()),
+        os => return Err(format!("Unsupported operating system: {os}").into()),
+    };
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(cli.threads as usize)
+        .build_global()?;
+
+    let output = commands::Output {
+        compression: cli.compression,
+        line_ending,
+        line_width: cli.line_width,
+        phylip_interleaved: cli.phylip_interleaved,
+        out2: cli.out2,
+    };
+
+    let rejects = match (cli.rejects, cli.on_error) {
+        (Some(_), OnError::Fail) => {
+            return Err(errors::SeqtoolsError::invalid_argument(
+                "--rejects requires --on-error skip or warn",
+            )
+            .into())
+        }
+        (Some(path), _) => Some(init_writer(Some(path), None)?),
+        (None, _) => None,
+    };
+    let files = Input::from_paths(expand_inputs(&cli.input)?);
+    let mates = if cli.interleaved {
+        Mates::Interleaved
+    } else if !cli.input2.is_empty() {
+        let mate_files: Vec<Input> = expand_inputs(&cli.input2)?
+            .into_iter()
+            .map(Input::File)
+            .collect();
+        if mate_files.len() != files.len() {
+            return Err(errors::SeqtoolsError::invalid_argument(format!(
+                "Found {} --in2 files for {} --in files",
+                mate_files.len(),
+                files.len()
+            ))
+            .into());
+        }
+        Mates::Files(mate_files)
+    } else {
+        Mates::None
+    };
+    let splits_pairs = matches!(
+        cli.command,
+        Commands::Deinterleave { .. } | Commands::Repair { .. }
+    );
+    if mates == Mates::None && output.out2.is_some() && !splits_pairs {
+        return Err(errors::SeqtoolsError::invalid_argument(
+            "--out2 requires paired-end input, given with --in2 or --interleaved",
+        )
+        .into());
+    }
+    let pair_aware = matches!(
+        cli.command,
+        Commands::Convert { .. }
+            | Commands::Select { .. }
+            | Commands::Rename { .. }
+            | Commands::AddId { .. }
+            | Commands::Trim { .. }
+            | Commands::Clip { .. }
+            | Commands::Revcomp { .. }
+            | Commands::Translate { .. }
+            | Commands::DeDuplicate { .. }
+            | Commands::Pipe { .. }
+            | Commands::Interleave { .. }
+            | Commands::Deinterleave { .. }
+            | Commands::Repair { .. }
+    );
+    if mates != Mates::None && !pair_aware {
+        return Err(errors::SeqtoolsError::invalid_argument(
+            "Paired-end input is only supported by convert, select, rename, add-id, trim, clip, revcomp, translate, de-duplicate, pipe, interleave, deinterleave and repair",
+        )
+        .into());
+    }
+    let inputs = commands::Inputs {
+        files,
+        mates,
+        errors: ErrorHandler::new(cli.on_error, rejects),
     };
 
     match cli.command {
-        Commands::Count => commands::count(cli.input),
+        Commands::Count => commands::count(&inputs, cli.output_format),
         Commands::Length {
             summary,
             histogram,
             tabular,
-        } => commands::length(cli.input, summary, histogram, tabular),
-        Commands::Freqs { per_sequence } => commands::frequencies(cli.input, per_sequence),
+        } => commands::length(&inputs, summary, histogram, tabular, cli.output_format),
+        Commands::Freqs { per_sequence } => {
+            commands::frequencies(&inputs, per_sequence, cli.output_format)
+        }
+        Commands::Kmers {
+            k,
+            stranded,
+            per_sequence,
+            spectrum,
+        } => commands::kmers(
+            &inputs,
+            k.into(),
+            !stranded,
+            per_sequence,
+            spectrum,
+            cli.output_format,
+        ),
         Commands::Random {
             num,
             len,
@@ -264,49 +791,90 @@ fn main() -> Result<(), Box<dyn Error>> {
             sequence_type,
             out,
             format,
-        } => commands::generate_random(num, len, std, sequence_type, out, format, line_ending),
-        Commands::Ids => commands::ids(cli.input),
-        Commands::Convert { to, out } => commands::convert(cli.input, to, out, line_ending),
-        Commands::Select {
-            ids,
-            use_indices,
-            ids_file,
-            out,
-        } => {
-            if use_indices {
-                commands::select_by_index(cli.input, ids, ids_file, out, line_ending)
-            } else {
-                commands::select_by_ids(cli.input, ids, ids_file, out, line_ending)
-            }
+        } => commands::generate_random(num, len, std, sequence_type, out, format, &output),
+        Commands::Ids => commands::ids(&inputs),
+        command @ (Commands::Convert { .. }
+        | Commands::Select { .. }
+        | Commands::Rename { .. }
+        | Commands::AddId { .. }
+        | Commands::Trim { .. }
+        | Commands::Clip { .. }
+        | Commands::Revcomp { .. }
+        | Commands::Translate { .. }
+        | Commands::Subseq { .. }
+        | Commands::DeDuplicate { .. }) => commands::rewrite(&inputs, command, &output),
+        Commands::Pipe { steps, out, to } => {
+            let steps = parse_steps(steps).unwrap_or_else(|e| e.exit());
+            commands::pipe(&inputs, steps, out, to, &output)
         }
-        Commands::Rename {
-            number,
-            map_file,
-            out,
+        Commands::Locate {
+            patterns,
+            patterns_file,
+            degenerate,
+            regex,
+            strand,
+            max_distance,
+            edit,
+            bed,
         } => {
-            if number {
-                commands::index_rename_sequences(cli.input, out, line_ending)
-            } else {
-                commands::map_rename_sequences(cli.input, map_file, out, line_ending)
-            }
+            let search = commands::build_search(
+                patterns,
+                patterns_file,
+                degenerate,
+                regex,
+                strand,
+                max_distance,
+                edit,
+            )?;
+            commands::locate(&inputs, &search, bed, cli.output_format)
         }
-        Commands::AddId {
-            to_add,
-            as_prefix,
-            out,
-        } => commands::add_id(cli.input, to_add, as_prefix, out, line_ending),
-        Commands::Trim {
-            n_char,
-            from_start,
-            out,
-        } => commands::trim(cli.input, n_char, from_start, out, line_ending),
-        Commands::Clip { max_len, out } => commands::clip(cli.input, max_len, out, line_ending),
-        Commands::Duplicates { show_names } => commands::check_duplicates(cli.input, show_names),
-        Commands::DeDuplicate { out, verbose } => {
-            commands::remove_duplicates(cli.input, out, verbose, line_ending)
+        Commands::Duplicates { show_names } => {
+            commands::check_duplicates(&inputs, show_names, cli.output_format)
+        }
+        Commands::View => commands::view_alignment(&inputs),
+        Commands::Faidx { regions, bed, out } => {
+            commands::faidx(&inputs, regions, bed, out, &output)
         }
-        Commands::View => commands::view_alignment(cli.input),
+        Commands::Index => commands::index(&inputs),
+        Commands::Interleave { out, to } => commands::interleave(&inputs, out, to, &output),
+        Commands::Deinterleave { out, to } => commands::deinterleave(&inputs, out, to, &output),
+        Commands::Repair {
+            out,
+            singletons,
+            to,
+        } => commands::repair(&inputs, out, singletons, to, &output),
+        Commands::Features { keys, out, to } => commands::features(&inputs, keys, out, to, &output),
     }?;
 
+    if let Some(summary) = inputs.errors.finish()? {
+        eprintln!("{summary}");
+    }
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn steps(args: &[&str]) -> Vec<Commands> {
+        parse_steps(args.iter().map(|arg| arg.to_string()).collect()).unwrap()
+    }
+
+    #[test]
+    fn splits_pipe_steps_on_commas() {
+        for args in [
+            &["subseq", "chr1:1,000-1,010", ",", "revcomp"][..],
+            &["subseq", "chr1:1,000-1,010,", "revcomp"],
+            &["subseq", "chr1:1,000-1,010", ",revcomp"],
+        ] {
+            let steps = steps(args);
+            assert_eq!(steps.len(), 2, "{args:?}");
+            match &steps[0] {
+                Commands::Subseq { regions, .. } => assert_eq!(regions, &["chr1:1,000-1,010"]),
+                step => panic!("unexpected step {step:?}"),
+            }
+            assert!(matches!(steps[1], Commands::Revcomp { .. }));
+        }
+    }
+}
@@ -0,0 +1,455 @@
+//! Record transformations behind the commands that rewrite FASTX files.
+//! Run them with [`transform::process`](crate::transform::process), on their
+//! own or combined in a [`Chain`](crate::transform::Chain).
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error::Error;
+
+use rand::Rng;
+use rand_distr::{Distribution, Normal};
+
+use crate::intervals::Feature;
+use crate::pairs::{self, Pair};
+use crate::record::Record;
+use crate::transform::{self, RecordTransform};
+use crate::translation::GeneticCode;
+use crate::writer::RecordWriter;
+use crate::Molecule;
+
+const DNA: &[u8] = b"ACGT";
+const RNA: &[u8] = b"ACGU";
+const PROTEIN: &[u8] = b"ACDEFGHIKLMNPQRSTVWY";
+
+/// Write `num` random sequences with normally distributed lengths
+pub fn generate_random(
+    writer: &mut RecordWriter,
+    num: i32,
+    len: f64,
+    std: f64,
+    sequence_type: Molecule,
+) -> Result<(), Box<dyn Error>> {
+    let charset = match sequence_type {
+        Molecule::DNA => DNA,
+        Molecule::RNA => RNA,
+        Molecule::Protein => PROTEIN,
+    };
+
+    let mut rng = rand::thread_rng();
+    let normal = Normal::new(len, std)?;
+
+    for i in 0..num {
+        let id = format!("S{i}").into_bytes();
+
+        let x: u64 = normal.sample(&mut rng) as u64;
+        let seq: Vec<u8> = (0..x)
+            .map(|_| {
+                let idx = rng.gen_range(0..charset.len());
+                charset[idx]
+            })
+            .collect();
+
+        writer.write(Record::new(id, seq, None))?;
+    }
+    writer.finish()?;
+
+    Ok(())
+}
+
+/// Keep the records whose identifier is in `to_select`. A read pair is kept
+/// if the identifier of either mate, or their common name, is selected.
+pub fn select_by_ids(to_select: HashSet<String>) -> impl RecordTransform {
+    SelectIds(to_select)
+}
+
+struct SelectIds(HashSet<String>);
+
+impl SelectIds {
+    fn contains(&self, id: &[u8]) -> bool {
+        std::str::from_utf8(id).is_ok_and(|id| self.0.contains(id))
+    }
+}
+
+impl RecordTransform for SelectIds {
+    fn apply(&mut self, record: Record, out: &mut Vec<Record>) -> Result<(), Box<dyn Error>> {
+        if self.0.contains(record.id_str()?) {
+            out.push(record);
+        }
+        Ok(())
+    }
+
+    fn apply_pair(&mut self, pair: Pair, out: &mut Vec<Pair>) -> Result<(), Box<dyn Error>> {
+        if self.contains(&pair.0.id)
+            || self.contains(&pair.1.id)
+            || self.contains(pairs::mate_name(&pair.0.id))
+        {
+            out.push(pair);
+        }
+        Ok(())
+    }
+}
+
+/// Keep the records, or the read pairs, whose 0-based index is in `to_select`
+pub fn select_by_index(to_select: HashSet<usize>) -> impl RecordTransform {
+    SelectIndices {
+        to_select,
+        cursor: 0,
+    }
+}
+
+struct SelectIndices {
+    to_select: HashSet<usize>,
+    cursor: usize,
+}
+
+impl SelectIndices {
+    fn next_is_selected(&mut self) -> bool {
+        let keep = self.to_select.contains(&self.cursor);
+        self.cursor += 1;
+        keep
+    }
+}
+
+impl RecordTransform for SelectIndices {
+    fn apply(&mut self, record: Record, out: &mut Vec<Record>) -> Result<(), Box<dyn Error>> {
+        if self.next_is_selected() {
+            out.push(record);
+        }
+        Ok(())
+    }
+
+    fn apply_pair(&mut self, pair: Pair, out: &mut Vec<Pair>) -> Result<(), Box<dyn Error>> {
+        if self.next_is_selected() {
+            out.push(pair);
+        }
+        Ok(())
+    }
+}
+
+/// Rename records according to an `old id -> new id` map.
+/// Records that are not in the map keep their identifier.
+pub fn map_rename(new_names: HashMap<String, String>) -> impl RecordTransform {
+    transform::map(move |mut record| {
+        if let Some(new) = new_names.get(record.id_str()?) {
+            record.id = new.as_bytes().to_vec();
+        }
+        Ok(record)
+    })
+}
+
+/// Replace each record identifier with its 0-based index. Both mates of a
+/// read pair get the index of the pair.
+pub fn index_rename() -> impl RecordTransform {
+    IndexRename { cursor: 0 }
+}
+
+struct IndexRename {
+    cursor: usize,
+}
+
+impl IndexRename {
+    fn next_id(&mut self) -> Vec<u8> {
+        let id = format!("{}", self.cursor).into_bytes();
+        self.cursor += 1;
+        id
+    }
+}
+
+impl RecordTransform for IndexRename {
+    fn apply(&mut self, mut record: Record, out: &mut Vec<Record>) -> Result<(), Box<dyn Error>> {
+        record.id = self.next_id();
+        out.push(record);
+        Ok(())
+    }
+
+    fn apply_pair(&mut self, pair: Pair, out: &mut Vec<Pair>) -> Result<(), Box<dyn Error>> {
+        let (mut first, mut second) = pair;
+        first.id = self.next_id();
+        second.id = first.id.clone();
+        out.push((first, second));
+        Ok(())
+    }
+}
+
+pub fn add_id(to_add: String, as_prefix: bool) -> impl RecordTransform {
+    transform::map(move |mut record| {
+        let new_id = if as_prefix {
+            format!("{to_add}{}", record.id_str()?)
+        } else {
+            format!("{}{to_add}", record.id_str()?)
+        };
+        record.id = new_id.into_bytes();
+        Ok(record)
+    })
+}
+
+/// Remove `to_trim` characters from the start or the end of each sequence
+pub fn trim(to_trim: usize, from_start: bool) -> impl RecordTransform {
+    transform::map(move |mut record| {
+        let len = record.seq.len();
+        let to_trim = to_trim.min(len);
+        if from_start {
+            record.slice(to_trim..len);
+        } else {
+            record.slice(0..len - to_trim);
+        }
+        Ok(record)
+    })
+}
+
+/// Truncate each sequence to at most `max_len` characters
+pub fn clip(max_len: usize) -> impl RecordTransform {
+    transform::map(move |mut record| {
+        let len = record.seq.len().min(max_len);
+        record.slice(0..len);
+        Ok(record)
+    })
+}
+
+/// Reverse complement DNA and RNA sequences, only those whose identifier is
+/// in `only` if given
+pub fn reverse_complement(only: Option<HashSet<String>>) -> impl RecordTransform {
+    transform::map(move |mut record| {
+        if only
+            .as_ref()
+            .map_or(Ok(true), |ids| record.id_str().map(|id| ids.contains(id)))?
+        {
+            record.reverse_complement();
+        }
+        Ok(record)
+    })
+}
+
+/// Translate nucleotide sequences into proteins with genetic code `code`, in
+/// each of the reading `frames`. With several frames, each translation is
+/// named after its record with a `_frame<N>` suffix, `N` being the frame. With
+/// `to_stop`, proteins end before their first stop codon.
+pub fn translate(
+    code: &'static GeneticCode,
+    frames: Vec<i8>,
+    to_stop: bool,
+) -> impl RecordTransform {
+    transform::flat_map(move |record| {
+        let name_len = record
+            .id
+            .iter()
+            .position(|c| c.is_ascii_whitespace())
+            .unwrap_or(record.id.len());
+        let (name, description) = record.id.split_at(name_len);
+
+        let mut proteins = Vec::with_capacity(frames.len());
+        for &frame in &frames {
+            let mut protein = code.translate(&record.seq, frame);
+            if to_stop {
+                if let Some(stop) = protein.iter().position(|aa| *aa == b'*') {
+                    protein.truncate(stop);
+                }
+            }
+            let id = if frames.len() > 1 {
+                [name, format!("_frame{frame}").as_bytes(), description].concat()
+            } else {
+                record.id.clone()
+            };
+            let mut translated = Record::new(id, protein, None);
+            translated.annotations = record.annotations.clone();
+            proteins.push(translated);
+        }
+        Ok(proteins)
+    })
+}
+
+/// Extract the `features` of each record, named after the feature. Features
+/// are matched to records by the first word of their identifier, extended by
+/// `flank` bases on both sides and clipped to the sequence. Features on the
+/// minus strand are reverse complemented.
+pub fn subseq(features: Vec<Feature>, flank: u64) -> impl RecordTransform {
+    let mut by_sequence: HashMap<Vec<u8>, Vec<Feature>> = HashMap::new();
+    for feature in features {
+        by_sequence
+            .entry(feature.region.name.as_bytes().to_vec())
+            .or_default()
+            .push(feature);
+    }
+
+    transform::flat_map(move |record| {
+        let name = record
+            .id
+            .split(|c| c.is_ascii_whitespace())
+            .next()
+            .unwrap_or_default();
+        let Some(features) = by_sequence.get(name) else {
+            return Ok(vec![]);
+        };
+
+        let len = record.seq.len() as u64;
+        let mut extracted = Vec::with_capacity(features.len());
+        for feature in features {
+            let end = feature
+                .region
+                .end
+                .unwrap_or(len)
+                .saturating_add(flank)
+                .min(len);
+            let start = feature.region.start.saturating_sub(flank).min(end);
+            let range = start as usize..end as usize;
+            let mut part = Record::new(
+                feature.id().into_bytes(),
+                record.seq[range.clone()].to_vec(),
+                record.qual.as_ref().map(|qual| qual[range].to_vec()),
+            );
+            if feature.reverse {
+                part.reverse_complement();
+            }
+            extracted.push(part);
+        }
+        Ok(extracted)
+    })
+}
+
+/// Keep one record per distinct sequence, the one with the smallest identifier.
+/// Records are buffered until the end of the input and written out ordered by
+/// sequence. Read pairs are duplicates when both their mates are.
+#[derive(Default)]
+pub struct Deduplicate {
+    groups: BTreeMap<Vec<u8>, Vec<Record>>,
+    pair_groups: BTreeMap<(Vec<u8>, Vec<u8>), Vec<Pair>>,
+    removed: Vec<String>,
+}
+
+impl Deduplicate {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Identifiers of the records that were removed, or of the mates 1 of the
+    /// removed pairs, available after `finish`
+    pub fn removed(&self) -> &[String] {
+        &self.removed
+    }
+}
+
+impl RecordTransform for Deduplicate {
+    fn apply(&mut self, mut record: Record, _out: &mut Vec<Record>) -> Result<(), Box<dyn Error>> {
+        record.id = record.id_str()?.trim().as_bytes().to_vec();
+        self.groups
+            .entry(record.seq.clone())
+            .or_default()
+            .push(record);
+
+        Ok(())
+    }
+
+    fn finish(&mut self, out: &mut Vec<Record>) -> Result<(), Box<dyn Error>> {
+        for (_, mut records) in std::mem::take(&mut self.groups) {
+            records.sort_by(|a, b| a.id.cmp(&b.id));
+            let mut records = records.into_iter();
+            if let Some(kept) = records.next() {
+                out.push(kept);
+            }
+            for record in records {
+                self.removed.push(record.id_str()?.to_string());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn apply_pair(&mut self, pair: Pair, _out: &mut Vec<Pair>) -> Result<(), Box<dyn Error>> {
+        let (mut first, mut second) = pair;
+        first.id = first.id_str()?.trim().as_bytes().to_vec();
+        second.id = second.id_str()?.trim().as_bytes().to_vec();
+        self.pair_groups
+            .entry((first.seq.clone(), second.seq.clone()))
+            .or_default()
+            .push((first, second));
+
+        Ok(())
+    }
+
+    fn finish_pairs(&mut self, out: &mut Vec<Pair>) -> Result<(), Box<dyn Error>> {
+        for (_, mut pairs) in std::mem::take(&mut self.pair_groups) {
+            pairs.sort_by(|a, b| a.0.id.cmp(&b.0.id));
+            let mut pairs = pairs.into_iter();
+            if let Some(kept) = pairs.next() {
+                out.push(kept);
+            }
+            for (first, _) in pairs {
+                self.removed.push(first.id_str()?.to_string());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::reader::RecordReader;
+    use crate::testing::SharedBuffer;
+    use crate::writer::WriteOptions;
+    use crate::Format;
+
+    const FASTQ: &str = "@a\nACGTAC\n+\nABCDEF\n@b\nTTGG\n+\nabcd\n@c\nCC\n+\n!!\n";
+
+    /// Output of `transform` on `text`, in the format of the input unless `to` is given
+    fn run(text: &str, to: Option<Format>, mut transform: impl RecordTransform) -> String {
+        let parser = needletail::parse_fastx_reader(Cursor::new(text.to_string())).unwrap();
+        let mut reader = RecordReader::new(parser, "test");
+        let out = SharedBuffer::default();
+        let mut writer = RecordWriter::new(Box::new(out.clone()), to, WriteOptions::default());
+        transform::process(&mut reader, &mut writer, &mut transform).unwrap();
+        out.text()
+    }
+
+    #[test]
+    fn keeps_qualities() {
+        let selected = HashSet::from(["a".to_string(), "c".to_string()]);
+        assert_eq!(
+            run(FASTQ, None, select_by_ids(selected)),
+            "@a\nACGTAC\n+\nABCDEF\n@c\nCC\n+\n!!\n"
+        );
+        assert_eq!(
+            run(FASTQ, None, select_by_index(HashSet::from([1]))),
+            "@b\nTTGG\n+\nabcd\n"
+        );
+        let names = HashMap::from([("b".to_string(), "renamed".to_string())]);
+        assert_eq!(
+            run(FASTQ, None, map_rename(names)),
+            "@a\nACGTAC\n+\nABCDEF\n@renamed\nTTGG\n+\nabcd\n@c\nCC\n+\n!!\n"
+        );
+        assert_eq!(
+            run(FASTQ, None, index_rename()),
+            "@0\nACGTAC\n+\nABCDEF\n@1\nTTGG\n+\nabcd\n@2\nCC\n+\n!!\n"
+        );
+        assert_eq!(
+            run(FASTQ, None, add_id("x_".to_string(), true)),
+            "@x_a\nACGTAC\n+\nABCDEF\n@x_b\nTTGG\n+\nabcd\n@x_c\nCC\n+\n!!\n"
+        );
+    }
+
+    #[test]
+    fn trims_and_clips_qualities_with_sequences() {
+        assert_eq!(
+            run(FASTQ, None, trim(3, true)),
+            "@a\nTAC\n+\nDEF\n@b\nG\n+\nd\n@c\n\n+\n\n"
+        );
+        assert_eq!(
+            run(FASTQ, None, trim(3, false)),
+            "@a\nACG\n+\nABC\n@b\nT\n+\na\n@c\n\n+\n\n"
+        );
+        assert_eq!(
+            run(FASTQ, None, clip(3)),
+            "@a\nACG\n+\nABC\n@b\nTTG\n+\nabc\n@c\nCC\n+\n!!\n"
+        );
+    }
+
+    #[test]
+    fn drops_qualities_on_request() {
+        assert_eq!(
+            run(FASTQ, Some(Format::Fasta), clip(2)),
+            ">a\nAC\n>b\nTT\n>c\nCC\n"
+        );
+        assert_eq!(run(">a\nACGT\n", None, clip(2)), ">a\nAC\n");
+    }
+}
@@ -0,0 +1,471 @@
+//! Paired-end reads.
+//!
+//! Mates are either in two parallel files, where the n-th record of the second
+//! file is the mate of the n-th record of the first one, or interleaved in a
+//! single file, each mate 1 followed by its mate 2. A [`PairReader`] reads
+//! them in step and checks that the mates of each pair belong together, and a
+//! [`Repair`] pairs up mates that are out of step.
+use std::collections::HashMap;
+
+use crate::errors::{Location, SeqtoolsError};
+use crate::reader::RecordReader;
+use crate::record::Record;
+use crate::writer::RecordWriter;
+use crate::{Format, Input};
+
+/// Mate 1 and mate 2 of a read pair
+pub type Pair = (Record, Record);
+
+/// Where the mates of the input records are
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Mates {
+    /// Records are not paired
+    #[default]
+    None,
+    /// The mates of each input file are in the file at the same position
+    Files(Vec<Input>),
+    /// Each record of the input files is followed by its mate
+    Interleaved,
+}
+
+/// Name shared by the mates of a pair: the first word of the identifier,
+/// without its `/1` or `/2` suffix
+pub fn mate_name(id: &[u8]) -> &[u8] {
+    let name = id
+        .split(|c| c.is_ascii_whitespace())
+        .next()
+        .unwrap_or_default();
+
+    name.strip_suffix(b"/1")
+        .or_else(|| name.strip_suffix(b"/2"))
+        .unwrap_or(name)
+}
+
+/// Reads the pairs of two parallel readers, or of an interleaved one
+pub struct PairReader {
+    first: RecordReader,
+    second: Option<RecordReader>,
+}
+
+impl PairReader {
+    /// Read mates 1 from `first` and mates 2 from `second`
+    pub fn new(first: RecordReader, second: RecordReader) -> Self {
+        PairReader {
+            first,
+            second: Some(second),
+        }
+    }
+
+    /// Read pairs of consecutive records
+    pub fn interleaved(reader: RecordReader) -> Self {
+        PairReader {
+            first: reader,
+            second: None,
+        }
+    }
+
+    /// Format of the input, see [`RecordReader::format`]
+    pub fn format(&self) -> Option<Format> {
+        self.first.format()
+    }
+
+    /// Location of the mate 1 of the last pair returned by `next`
+    pub fn location(&self) -> Location {
+        self.first.location()
+    }
+
+    fn next_mate(&mut self) -> Option<Result<Record, SeqtoolsError>> {
+        match &mut self.second {
+            Some(second) => second.next(),
+            None => self.first.next(),
+        }
+    }
+
+    /// Location of the last mate 2 returned by `next_mate`
+    fn mate_location(&self) -> Location {
+        match &self.second {
+            Some(second) => second.location(),
+            None => self.first.location(),
+        }
+    }
+}
+
+impl Iterator for PairReader {
+    type Item = Result<Pair, SeqtoolsError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let first = match self.first.next() {
+            Some(Ok(record)) => record,
+            Some(Err(e)) => return Some(Err(e)),
+            None => {
+                return match self.second.as_mut().and_then(Iterator::next) {
+                    Some(Ok(mate)) => Some(Err(SeqtoolsError::record(
+                        "Mate 2 has no mate 1, the second file has more records",
+                        &mate.id,
+                    )
+                    .with_location(self.mate_location()))),
+                    Some(Err(e)) => Some(Err(e)),
+                    None => None,
+                }
+            }
+        };
+        let location = self.location();
+        let second = match self.next_mate() {
+            Some(Ok(record)) => record,
+            Some(Err(e)) => return Some(Err(e)),
+            None => {
+                return Some(Err(SeqtoolsError::record(
+                    "Mate 1 has no mate 2, the input ends before it",
+                    &first.id,
+                )
+                .with_location(location)))
+            }
+        };
+
+        if mate_name(&first.id) != mate_name(&second.id) {
+            let message = format!(
+                "Mates do not match: mate 2 is '{}'",
+                String::from_utf8_lossy(&second.id)
+            );
+            return Some(Err(
+                SeqtoolsError::record(message, &first.id).with_location(location)
+            ));
+        }
+
+        Some(Ok((first, second)))
+    }
+}
+
+/// Writes read pairs to two parallel writers, or interleaved to a single one
+pub struct PairWriter {
+    first: RecordWriter,
+    second: Option<RecordWriter>,
+}
+
+impl PairWriter {
+    /// Write mates 1 to `first` and mates 2 to `second`, or to `first` right
+    /// after their mate 1 if there is no `second`
+    pub fn new(first: RecordWriter, second: Option<RecordWriter>) -> Self {
+        PairWriter { first, second }
+    }
+
+    /// See [`RecordWriter::set_input_format`]
+    pub fn set_input_format(&mut self, format: Option<Format>) {
+        self.first.set_input_format(format);
+        if let Some(second) = &mut self.second {
+            second.set_input_format(format);
+        }
+    }
+
+    pub fn write(&mut self, (first, second): Pair) -> Result<(), SeqtoolsError> {
+        self.first.write(first)?;
+        match &mut self.second {
+            Some(writer) => writer.write(second),
+            None => self.first.write(second),
+        }
+    }
+
+    pub fn finish(&mut self) -> Result<(), SeqtoolsError> {
+        self.first.finish()?;
+        match &mut self.second {
+            Some(writer) => writer.finish(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Pairs up mates by read name, whatever the order they come in. Reads whose
+/// mate has not been seen yet wait in memory, so mates that are far apart
+/// take as much memory as the records between them.
+#[derive(Default)]
+pub struct Repair {
+    /// Mates waiting for their mate, by read name, along with the order they
+    /// were read in
+    firsts: HashMap<Vec<u8>, (usize, Record)>,
+    seconds: HashMap<Vec<u8>, (usize, Record)>,
+    /// Reads replaced by another read of the same name before their mate came
+    orphans: Vec<(usize, Record)>,
+    read: usize,
+}
+
+impl Repair {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a mate 1, returning its pair if its mate 2 was already added
+    pub fn push_first(&mut self, record: Record) -> Option<Pair> {
+        let name = mate_name(&record.id).to_vec();
+        if let Some((_, second)) = self.seconds.remove(&name) {
+            return Some((record, second));
+        }
+        self.wait(name, record, true);
+        None
+    }
+
+    /// Add a mate 2, returning its pair if its mate 1 was already added
+    pub fn push_second(&mut self, record: Record) -> Option<Pair> {
+        let name = mate_name(&record.id).to_vec();
+        if let Some((_, first)) = self.firsts.remove(&name) {
+            return Some((first, record));
+        }
+        self.wait(name, record, false);
+        None
+    }
+
+    /// Add a read that may be either mate: the first read of a name is mate 1
+    /// and the next one its mate 2
+    pub fn push(&mut self, record: Record) -> Option<Pair> {
+        if self.firsts.contains_key(mate_name(&record.id)) {
+            self.push_second(record)
+        } else {
+            self.push_first(record)
+        }
+    }
+
+    fn wait(&mut self, name: Vec<u8>, record: Record, first: bool) {
+        let waiting = if first {
+            &mut self.firsts
+        } else {
+            &mut self.seconds
+        };
+        if let Some(replaced) = waiting.insert(name, (self.read, record)) {
+            self.orphans.push(replaced);
+        }
+        self.read += 1;
+    }
+
+    /// Reads whose mate never came, in the order they were added
+    pub fn orphans(self) -> Vec<Record> {
+        let mut orphans = self.orphans;
+        orphans.extend(self.firsts.into_values());
+        orphans.extend(self.seconds.into_values());
+        orphans.sort_by_key(|(read, _)| *read);
+
+        orphans.into_iter().map(|(_, record)| record).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::reader::ErrorHandler;
+    use crate::testing::{SharedBuffer, TempFile};
+    use crate::transform::{self, Chain};
+    use crate::writer::WriteOptions;
+
+    fn reader(text: &'static str) -> RecordReader {
+        RecordReader::new(
+            needletail::parse_fastx_reader(Cursor::new(text)).unwrap(),
+            "test",
+        )
+    }
+
+    fn ids(pairs: &[Pair]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(first, second)| {
+                let id = |record: &Record| String::from_utf8(record.id.clone()).unwrap();
+                (id(first), id(second))
+            })
+            .collect()
+    }
+
+    /// The message and record index of a record error
+    fn record_error(err: SeqtoolsError) -> (String, Option<usize>) {
+        match err {
+            SeqtoolsError::Record {
+                message, location, ..
+            } => (message, location.record),
+            err => panic!("unexpected error {err:?}"),
+        }
+    }
+
+    #[test]
+    fn finds_mate_names() {
+        assert_eq!(mate_name(b"read1/1"), b"read1");
+        assert_eq!(mate_name(b"read1/2 some description"), b"read1");
+        assert_eq!(
+            mate_name(b"M00123:1:000:1:1:15:1 1:N:0:ATCACG"),
+            b"M00123:1:000:1:1:15:1"
+        );
+        assert_eq!(mate_name(b"read1/3"), b"read1/3");
+        assert_eq!(mate_name(b"read1"), b"read1");
+        assert_eq!(mate_name(b""), b"");
+    }
+
+    #[test]
+    fn reads_matching_mates() {
+        let first = reader(">a/1\nAC\n>b 1:N:0:1\nGG\n>c\nT\n");
+        let second = reader(">a/2\nGT\n>b 2:N:0:1\nCC\n>c\nA\n");
+        let pairs: Vec<Pair> = PairReader::new(first, second)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            ids(&pairs),
+            [
+                ("a/1".into(), "a/2".into()),
+                ("b 1:N:0:1".into(), "b 2:N:0:1".into()),
+                ("c".into(), "c".into())
+            ]
+        );
+
+        let interleaved = reader(">a/1\nAC\n>a/2\nGT\n>b/1\nG\n>b/2\nC\n");
+        let pairs: Vec<Pair> = PairReader::interleaved(interleaved)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(pairs.len(), 2);
+        assert_eq!(pairs[1].1.seq, b"C");
+    }
+
+    #[test]
+    fn rejects_mismatched_mates() {
+        let first = reader(">a/1\nAC\n>b/1\nGG\n");
+        let second = reader(">a/2\nGT\n>c/2\nCC\n");
+        let mut pairs = PairReader::new(first, second);
+        assert!(pairs.next().unwrap().is_ok());
+        let (message, record) = record_error(pairs.next().unwrap().unwrap_err());
+        assert_eq!(message, "Mates do not match: mate 2 is 'c/2'");
+        assert_eq!(record, Some(1));
+    }
+
+    #[test]
+    fn rejects_missing_mates() {
+        let mut pairs = PairReader::new(reader(">a\nA\n>b\nC\n"), reader(">a\nT\n"));
+        assert!(pairs.next().unwrap().is_ok());
+        let (message, record) = record_error(pairs.next().unwrap().unwrap_err());
+        assert_eq!(message, "Mate 1 has no mate 2, the input ends before it");
+        assert_eq!(record, Some(1));
+
+        let mut pairs = PairReader::new(reader(">a\nA\n"), reader(">a\nT\n>b\nG\n"));
+        assert!(pairs.next().unwrap().is_ok());
+        let (message, record) = record_error(pairs.next().unwrap().unwrap_err());
+        assert_eq!(
+            message,
+            "Mate 2 has no mate 1, the second file has more records"
+        );
+        assert_eq!(record, Some(1));
+
+        let mut pairs = PairReader::interleaved(reader(">a/1\nA\n>a/2\nT\n>b/1\nG\n"));
+        assert!(pairs.next().unwrap().is_ok());
+        assert!(pairs.next().unwrap().is_err());
+        assert!(pairs.next().is_none());
+    }
+
+    #[test]
+    fn processes_pairs_of_files() {
+        let first = TempFile::new("pairs_1.fq", b"@a/1\nAC\n+\nII\n@b/1\nG\n+\nI\n");
+        let second = TempFile::new("pairs_2.fq", b"@a/2\nGT\n+\nII\n@b/2\nC\n+\nI\n");
+        let inputs = [Input::File(first.0.clone())];
+        let mates = Mates::Files(vec![Input::File(second.0.clone())]);
+
+        let (out, out2) = (SharedBuffer::default(), SharedBuffer::default());
+        let mut writer = PairWriter::new(
+            RecordWriter::new(Box::new(out.clone()), None, WriteOptions::default()),
+            Some(RecordWriter::new(
+                Box::new(out2.clone()),
+                None,
+                WriteOptions::default(),
+            )),
+        );
+        transform::process_pairs(
+            &inputs,
+            &mates,
+            &ErrorHandler::default(),
+            &mut writer,
+            &mut Chain::default(),
+        )
+        .unwrap();
+        assert_eq!(out.text(), "@a/1\nAC\n+\nII\n@b/1\nG\n+\nI\n");
+        assert_eq!(out2.text(), "@a/2\nGT\n+\nII\n@b/2\nC\n+\nI\n");
+
+        let interleaved = SharedBuffer::default();
+        let mut writer = PairWriter::new(
+            RecordWriter::new(Box::new(interleaved.clone()), None, WriteOptions::default()),
+            None,
+        );
+        let unpaired = transform::process_pairs(
+            &inputs,
+            &Mates::None,
+            &ErrorHandler::default(),
+            &mut writer,
+            &mut Chain::default(),
+        );
+        assert!(unpaired.is_err());
+        transform::process_pairs(
+            &inputs,
+            &mates,
+            &ErrorHandler::default(),
+            &mut writer,
+            &mut Chain::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            interleaved.text(),
+            "@a/1\nAC\n+\nII\n@a/2\nGT\n+\nII\n@b/1\nG\n+\nI\n@b/2\nC\n+\nI\n"
+        );
+    }
+
+    fn record(id: &str) -> Record {
+        Record::new(id.as_bytes().to_vec(), b"A".to_vec(), None)
+    }
+
+    fn orphan_ids(repair: Repair) -> Vec<String> {
+        repair
+            .orphans()
+            .into_iter()
+            .map(|record| String::from_utf8(record.id).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn repairs_shuffled_mates() {
+        let mut repair = Repair::new();
+        let mut pairs = vec![];
+        for (first, second) in [
+            ("a/1", "c/2"),
+            ("b/1", "a/2"),
+            ("c/1", "e/2"),
+            ("d/1", "b/2"),
+        ] {
+            pairs.extend(repair.push_first(record(first)));
+            pairs.extend(repair.push_second(record(second)));
+        }
+        assert_eq!(
+            ids(&pairs),
+            [
+                ("a/1".into(), "a/2".into()),
+                ("c/1".into(), "c/2".into()),
+                ("b/1".into(), "b/2".into())
+            ]
+        );
+        assert_eq!(orphan_ids(repair), ["e/2", "d/1"]);
+    }
+
+    #[test]
+    fn keeps_replaced_mates_as_orphans() {
+        let mut repair = Repair::new();
+        assert!(repair.push_first(record("a/1 first")).is_none());
+        assert!(repair.push_first(record("a/1 again")).is_none());
+        let pair = repair.push_second(record("a/2")).unwrap();
+        assert_eq!(pair.0.id, b"a/1 again");
+        assert_eq!(orphan_ids(repair), ["a/1 first"]);
+    }
+
+    #[test]
+    fn repairs_interleaved_reads() {
+        let mut repair = Repair::new();
+        let pairs: Vec<Pair> = ["a/1", "b/1", "a/2", "c/1", "b/2", "d/2"]
+            .into_iter()
+            .filter_map(|id| repair.push(record(id)))
+            .collect();
+        assert_eq!(
+            ids(&pairs),
+            [("a/1".into(), "a/2".into()), ("b/1".into(), "b/2".into())]
+        );
+        // Without the other mate, a lone read counts as a mate 1
+        assert_eq!(orphan_ids(repair), ["c/1", "d/2"]);
+    }
+}
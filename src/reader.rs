@@ -0,0 +1,689 @@
+//! Reading owned records while keeping track of where they are in the input,
+//! so that errors can point at the offending record.
+//!
+//! By default the first malformed record is an error. With [`OnError::Skip`]
+//! or [`OnError::Warn`], inputs are read with a lenient parser that drops
+//! malformed records and resumes at the next record that looks valid.
+use std::cell::RefCell;
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
+
+use clap::ValueEnum;
+use needletail::FastxReader;
+
+use crate::errors::{Location, SeqtoolsError};
+use crate::formats::{flatfile, tabular, Alignment, Annotations};
+use crate::record::Record;
+use crate::Format;
+
+#[derive(Copy, Clone, ValueEnum, Debug, Default, PartialEq, Eq)]
+pub enum OnError {
+    /// Stop at the first malformed record
+    #[default]
+    Fail,
+    /// Drop malformed records silently
+    Skip,
+    /// Drop malformed records with a warning on stderr
+    Warn,
+}
+
+#[derive(Default)]
+struct Dropped {
+    reasons: BTreeMap<&'static str, usize>,
+    rejects: Option<Box<dyn Write>>,
+}
+
+/// Decides what happens to malformed records and keeps track of the ones
+/// that were dropped. Clones share the same counts and rejects file, so that
+/// a single summary covers all the inputs of a command.
+#[derive(Clone, Default)]
+pub struct ErrorHandler {
+    on_error: OnError,
+    dropped: Rc<RefCell<Dropped>>,
+}
+
+impl ErrorHandler {
+    /// The raw text of dropped records is written to `rejects` if given
+    pub fn new(on_error: OnError, rejects: Option<Box<dyn Write>>) -> Self {
+        ErrorHandler {
+            on_error,
+            dropped: Rc::new(RefCell::new(Dropped {
+                reasons: BTreeMap::new(),
+                rejects,
+            })),
+        }
+    }
+
+    pub fn on_error(&self) -> OnError {
+        self.on_error
+    }
+
+    fn drop_record(&self, malformed: &Malformed, err: &SeqtoolsError) -> io::Result<()> {
+        if self.on_error == OnError::Warn {
+            eprintln!("Warning: dropping record. {err}");
+        }
+
+        let mut dropped = self.dropped.borrow_mut();
+        *dropped.reasons.entry(malformed.reason).or_insert(0) += 1;
+        if let Some(rejects) = dropped.rejects.as_mut() {
+            for line in malformed.raw.iter() {
+                rejects.write_all(&line.text)?;
+                rejects.write_all(b"\n")?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Number of dropped records for each reason
+    pub fn dropped(&self) -> BTreeMap<&'static str, usize> {
+        self.dropped.borrow().reasons.clone()
+    }
+
+    /// Flush the rejects file and return a summary of the dropped records,
+    /// if there were any
+    pub fn finish(&self) -> io::Result<Option<String>> {
+        let mut dropped = self.dropped.borrow_mut();
+        if let Some(rejects) = dropped.rejects.as_mut() {
+            rejects.flush()?;
+        }
+
+        let total: usize = dropped.reasons.values().sum();
+        if total == 0 {
+            return Ok(None);
+        }
+        let reasons: Vec<String> = dropped
+            .reasons
+            .iter()
+            .map(|(reason, count)| format!("{count} {reason}"))
+            .collect();
+
+        Ok(Some(format!(
+            "Dropped {total} malformed record{}: {}",
+            if total > 1 { "s" } else { "" },
+            reasons.join(", ")
+        )))
+    }
+}
+
+/// A line of the input, without its line ending
+struct Line {
+    text: Vec<u8>,
+    number: u64,
+}
+
+/// A record that could not be parsed, with the lines it spans
+struct Malformed {
+    reason: &'static str,
+    message: String,
+    raw: Vec<Line>,
+}
+
+/// Read a line without its line ending
+fn read_line(reader: &mut dyn BufRead) -> io::Result<Option<Vec<u8>>> {
+    let mut text = vec![];
+    if reader.read_until(b'\n', &mut text)? == 0 {
+        return Ok(None);
+    }
+    while text.last().is_some_and(|c| *c == b'\n' || *c == b'\r') {
+        text.pop();
+    }
+
+    Ok(Some(text))
+}
+
+#[derive(Copy, Clone)]
+enum Fastx {
+    Fasta,
+    Fastq,
+}
+
+/// A FASTA/FASTQ parser that can resume after a malformed record. Like
+/// needletail, it expects FASTQ records of 4 lines: a FASTQ record wrapped
+/// over more lines is dropped as malformed.
+struct LenientParser {
+    reader: Box<dyn BufRead + Send>,
+    lookahead: VecDeque<Line>,
+    read: u64,
+    format: Option<Fastx>,
+}
+
+impl LenientParser {
+    fn new(reader: Box<dyn BufRead + Send>) -> Self {
+        LenientParser {
+            reader,
+            lookahead: VecDeque::new(),
+            read: 0,
+            format: None,
+        }
+    }
+
+    /// Look at the `n`-th next line without consuming it
+    fn peek(&mut self, n: usize) -> io::Result<Option<&Line>> {
+        while self.lookahead.len() <= n {
+            let Some(text) = read_line(&mut self.reader)? else {
+                return Ok(None);
+            };
+            self.read += 1;
+            self.lookahead.push_back(Line {
+                text,
+                number: self.read,
+            });
+        }
+
+        Ok(self.lookahead.get(n))
+    }
+
+    fn starts_with(&mut self, n: usize, c: u8) -> io::Result<bool> {
+        Ok(self
+            .peek(n)?
+            .is_some_and(|line| line.text.first() == Some(&c)))
+    }
+
+    fn pop(&mut self) -> io::Result<Option<Line>> {
+        self.peek(0)?;
+        Ok(self.lookahead.pop_front())
+    }
+
+    /// Whether the next line is the header of a well-formed FASTQ record
+    fn at_fastq_header(&mut self) -> io::Result<bool> {
+        Ok(self.starts_with(0, b'@')? && self.starts_with(2, b'+')?)
+    }
+
+    /// Move the lines up to the next record that looks valid to `raw`
+    fn resync(&mut self, raw: &mut Vec<Line>) -> io::Result<()> {
+        loop {
+            let at_record = match self.format {
+                Some(Fastx::Fastq) => self.at_fastq_header()?,
+                Some(Fastx::Fasta) => self.starts_with(0, b'>')?,
+                None => self.starts_with(0, b'>')? || self.starts_with(0, b'@')?,
+            };
+            if at_record {
+                return Ok(());
+            }
+            match self.pop()? {
+                Some(line) => raw.push(line),
+                None => return Ok(()),
+            }
+        }
+    }
+
+    fn malformed(
+        &mut self,
+        reason: &'static str,
+        message: String,
+        mut raw: Vec<Line>,
+    ) -> io::Result<Malformed> {
+        // The lines after the header may be the start of the next record
+        let mut rest = raw.split_off(1.min(raw.len()));
+        while let Some(line) = rest.pop() {
+            self.lookahead.push_front(line);
+        }
+        self.resync(&mut raw)?;
+
+        Ok(Malformed {
+            reason,
+            message,
+            raw,
+        })
+    }
+
+    fn next_record(&mut self) -> io::Result<Option<Result<(Record, u64), Malformed>>> {
+        while self.peek(0)?.is_some_and(|line| line.text.is_empty()) {
+            self.pop()?;
+        }
+        let header = match self.pop()? {
+            Some(header) => header,
+            None => return Ok(None),
+        };
+
+        let format = match (self.format, header.text.first()) {
+            (Some(format), _) => format,
+            (None, Some(b'>')) => Fastx::Fasta,
+            (None, Some(b'@')) => Fastx::Fastq,
+            (None, _) => {
+                let message = "Expected '@' or '>' at the start of the file".to_string();
+                return Ok(Some(Err(self.malformed(
+                    "invalid start",
+                    message,
+                    vec![header],
+                )?)));
+            }
+        };
+        self.format = Some(format);
+
+        match format {
+            Fastx::Fasta => self.next_fasta(header),
+            Fastx::Fastq => self.next_fastq(header),
+        }
+    }
+
+    fn next_fasta(&mut self, header: Line) -> io::Result<Option<Result<(Record, u64), Malformed>>> {
+        if header.text.first() != Some(&b'>') {
+            let message = "Expected '>' at the start of the record".to_string();
+            return Ok(Some(Err(self.malformed(
+                "invalid start",
+                message,
+                vec![header],
+            )?)));
+        }
+
+        let mut seq = vec![];
+        while self.peek(0)?.is_some() && !self.starts_with(0, b'>')? {
+            if let Some(line) = self.pop()? {
+                seq.extend(line.text);
+            }
+        }
+        let record = Record::new(header.text[1..].to_vec(), seq, None);
+
+        Ok(Some(Ok((record, header.number))))
+    }
+
+    fn next_fastq(&mut self, header: Line) -> io::Result<Option<Result<(Record, u64), Malformed>>> {
+        let line_number = header.number;
+        let mut raw = vec![header];
+        if raw[0].text.first() != Some(&b'@') {
+            let message = "Expected '@' at the start of the record".to_string();
+            return Ok(Some(Err(self.malformed("invalid start", message, raw)?)));
+        }
+
+        for _ in 0..3 {
+            match self.pop()? {
+                Some(line) => raw.push(line),
+                None => {
+                    let message = "Unexpected end of file in the record".to_string();
+                    return Ok(Some(Err(self.malformed("unexpected end", message, raw)?)));
+                }
+            }
+        }
+        if raw[2].text.first() != Some(&b'+') {
+            let message = "Expected '+' on the line after the sequence".to_string();
+            return Ok(Some(Err(self.malformed(
+                "invalid separator",
+                message,
+                raw,
+            )?)));
+        }
+        if raw[1].text.len() != raw[3].text.len() {
+            let message = format!(
+                "Sequence length is {} but quality length is {}",
+                raw[1].text.len(),
+                raw[3].text.len()
+            );
+            return Ok(Some(Err(self.malformed(
+                "unequal lengths",
+                message,
+                raw,
+            )?)));
+        }
+
+        let mut lines = raw.into_iter().map(|line| line.text);
+        let id = lines.next().unwrap_or_default()[1..].to_vec();
+        let seq = lines.next().unwrap_or_default();
+        let qual = lines.nth(1).unwrap_or_default();
+
+        Ok(Some(Ok((Record::new(id, seq, Some(qual)), line_number))))
+    }
+}
+
+/// A parser for formats with one record per line, TSV or JSON Lines
+struct LineParser {
+    reader: Box<dyn BufRead + Send>,
+    format: Format,
+    read: u64,
+}
+
+impl LineParser {
+    fn next_record(&mut self) -> io::Result<Option<Result<(Record, u64), Malformed>>> {
+        loop {
+            let Some(text) = read_line(&mut self.reader)? else {
+                return Ok(None);
+            };
+            self.read += 1;
+            let line = String::from_utf8_lossy(&text);
+            if line.trim().is_empty() || (self.read == 1 && tabular::is_tsv_header(&line)) {
+                continue;
+            }
+
+            let parsed = match self.format {
+                Format::Jsonl => tabular::parse_jsonl(&line),
+                _ => tabular::parse_tsv(&line),
+            };
+            return Ok(Some(match parsed {
+                Ok(record) => Ok((record, self.read)),
+                Err(message) => Err(Malformed {
+                    reason: "invalid line",
+                    message,
+                    raw: vec![Line {
+                        text,
+                        number: self.read,
+                    }],
+                }),
+            }));
+        }
+    }
+}
+
+enum Source {
+    Strict(Box<dyn FastxReader>),
+    Lenient(LenientParser, ErrorHandler),
+    /// Records of a file that was read as a whole, with their line numbers
+    Records(std::vec::IntoIter<(Record, u64)>, Format, Annotations),
+    /// Entries of a GenBank or EMBL file, without their features
+    FlatFile(flatfile::Reader),
+    Lines(LineParser, ErrorHandler),
+}
+
+pub struct RecordReader {
+    source: Source,
+    file: String,
+    /// Number of records read so far, dropped malformed ones included
+    index: usize,
+    /// Line where the last record returned starts
+    line: Option<u64>,
+}
+
+impl RecordReader {
+    /// Read the records of a FASTX parser. `file` is the name used in errors.
+    pub fn new(reader: Box<dyn FastxReader>, file: impl Into<String>) -> Self {
+        RecordReader {
+            source: Source::Strict(reader),
+            file: file.into(),
+            index: 0,
+            line: None,
+        }
+    }
+
+    /// Read the records of an uncompressed FASTX stream, handing the
+    /// malformed ones over to `handler`
+    pub fn lenient(
+        reader: Box<dyn BufRead + Send>,
+        file: impl Into<String>,
+        handler: ErrorHandler,
+    ) -> Self {
+        RecordReader {
+            source: Source::Lenient(LenientParser::new(reader), handler),
+            file: file.into(),
+            index: 0,
+            line: None,
+        }
+    }
+
+    /// Read the records of an alignment that was already parsed
+    pub fn from_alignment(alignment: Alignment, file: impl Into<String>) -> Self {
+        RecordReader {
+            source: Source::Records(
+                alignment.records.into_iter(),
+                alignment.format,
+                alignment.annotations,
+            ),
+            file: file.into(),
+            index: 0,
+            line: None,
+        }
+    }
+
+    /// Read the records of an uncompressed TSV or JSON Lines stream, handing
+    /// the malformed lines over to `handler`
+    pub fn lines(
+        reader: Box<dyn BufRead + Send>,
+        format: Format,
+        file: impl Into<String>,
+        handler: ErrorHandler,
+    ) -> Self {
+        RecordReader {
+            source: Source::Lines(
+                LineParser {
+                    reader,
+                    format,
+                    read: 0,
+                },
+                handler,
+            ),
+            file: file.into(),
+            index: 0,
+            line: None,
+        }
+    }
+
+    /// Read the sequences of the entries of a GenBank or EMBL file
+    pub fn flatfile(reader: flatfile::Reader, file: impl Into<String>) -> Self {
+        RecordReader {
+            source: Source::FlatFile(reader),
+            file: file.into(),
+            index: 0,
+            line: None,
+        }
+    }
+
+    /// Format of the input, when it is the same for all records. FASTA and
+    /// FASTQ are detected record by record.
+    pub fn format(&self) -> Option<Format> {
+        match self.source {
+            Source::Records(_, format, _) => Some(format),
+            Source::Lines(ref parser, _) => Some(parser.format),
+            _ => None,
+        }
+    }
+
+    /// Annotations of the whole alignment, for inputs in alignment formats
+    pub fn annotations(&self) -> Option<&Annotations> {
+        match &self.source {
+            Source::Records(_, _, annotations) => Some(annotations),
+            _ => None,
+        }
+    }
+
+    /// Location of the last record returned by `next`
+    pub fn location(&self) -> Location {
+        Location {
+            file: Some(self.file.clone()),
+            record: self.index.checked_sub(1),
+            line: self.line,
+        }
+    }
+
+    /// Next record of the parsers that can drop malformed records
+    fn next_lenient(&mut self) -> Option<Result<Record, SeqtoolsError>> {
+        loop {
+            let (next, handler) = match &mut self.source {
+                Source::Lenient(parser, handler) => (parser.next_record(), handler),
+                Source::Lines(parser, handler) => (parser.next_record(), handler),
+                _ => return None,
+            };
+            match next {
+                Ok(None) => return None,
+                Ok(Some(Ok((record, line)))) => {
+                    self.index += 1;
+                    self.line = Some(line);
+                    return Some(Ok(record));
+                }
+                Ok(Some(Err(malformed))) => {
+                    let location = Location {
+                        file: Some(self.file.clone()),
+                        record: Some(self.index),
+                        line: malformed.raw.first().map(|line| line.number),
+                    };
+                    let err = SeqtoolsError::Parse {
+                        message: malformed.message.clone(),
+                        location,
+                    };
+                    self.index += 1;
+                    if handler.on_error() == OnError::Fail {
+                        return Some(Err(err));
+                    }
+                    if let Err(e) = handler.drop_record(&malformed, &err) {
+                        return Some(Err(e.into()));
+                    }
+                }
+                Err(e) => return Some(Err(SeqtoolsError::from(e).with_location(self.location()))),
+            }
+        }
+    }
+}
+
+impl Iterator for RecordReader {
+    type Item = Result<Record, SeqtoolsError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let reader = match &mut self.source {
+            Source::Strict(reader) => reader,
+            Source::Lenient(..) | Source::Lines(..) => return self.next_lenient(),
+            Source::Records(records, ..) => {
+                let (record, line) = records.next()?;
+                self.index += 1;
+                self.line = Some(line);
+                return Some(Ok(record));
+            }
+            Source::FlatFile(entries) => {
+                let result = entries.next()?;
+                self.index += 1;
+                return Some(match result {
+                    Ok((entry, line)) => {
+                        self.line = Some(line);
+                        Ok(entry.record)
+                    }
+                    Err(e) => Err(e.with_location(self.location())),
+                });
+            }
+        };
+
+        let result = reader.next()?;
+        self.index += 1;
+        match result {
+            Ok(record) => {
+                self.line = Some(record.start_line_number());
+                Some(Ok(Record::from(&record)))
+            }
+            Err(e) => {
+                self.line = None;
+                Some(Err(SeqtoolsError::from(e).with_location(self.location())))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::testing::SharedBuffer;
+
+    fn lenient(text: &'static str, handler: &ErrorHandler) -> RecordReader {
+        RecordReader::lenient(Box::new(Cursor::new(text)), "test", handler.clone())
+    }
+
+    /// Identifiers of the records read, with the index of the record in the
+    /// file that each one has
+    fn read_all(reader: &mut RecordReader) -> Vec<(String, Option<usize>)> {
+        let mut records = vec![];
+        while let Some(record) = reader.next() {
+            let id = String::from_utf8(record.unwrap().id).unwrap();
+            records.push((id, reader.location().record));
+        }
+        records
+    }
+
+    fn ids(records: &[(&str, usize)]) -> Vec<(String, Option<usize>)> {
+        records
+            .iter()
+            .map(|(id, index)| (id.to_string(), Some(*index)))
+            .collect()
+    }
+
+    #[test]
+    fn skips_truncated_fastq_records() {
+        let text = "@r1\nACGT\n+\nIIII\n@r2\nACGT\n@r3\nAC\n+\nII\n\
+            @r4\nACG\n+\nI\n@r5\nA\n+\nI\n@r6\nAC\n";
+        let rejects = SharedBuffer::default();
+        let handler = ErrorHandler::new(OnError::Skip, Some(Box::new(rejects.clone())));
+        let records = read_all(&mut lenient(text, &handler));
+
+        assert_eq!(records, ids(&[("r1", 0), ("r3", 2), ("r5", 4)]));
+        assert_eq!(
+            handler.dropped(),
+            BTreeMap::from([
+                ("invalid separator", 1),
+                ("unequal lengths", 1),
+                ("unexpected end", 1)
+            ])
+        );
+        assert_eq!(
+            handler.finish().unwrap().unwrap(),
+            "Dropped 3 malformed records: 1 invalid separator, 1 unequal lengths, 1 unexpected end"
+        );
+        assert_eq!(rejects.text(), "@r2\nACGT\n@r4\nACG\n+\nI\n@r6\nAC\n");
+    }
+
+    #[test]
+    fn drops_wrapped_fastq_records() {
+        let text = "@r1\nAC\nGT\n+\nII\nII\n@r2\nACGT\n+\nIIII\n";
+        let handler = ErrorHandler::new(OnError::Warn, None);
+        let records = read_all(&mut lenient(text, &handler));
+
+        assert_eq!(records, ids(&[("r2", 1)]));
+        assert_eq!(
+            handler.dropped(),
+            BTreeMap::from([("invalid separator", 1)])
+        );
+    }
+
+    #[test]
+    fn skips_fasta_lines_before_the_first_record() {
+        let text = "CGTACG\nTT\n>r1 first\nAC\nGT\n>r2\n>r3\nGG\n";
+        let handler = ErrorHandler::new(OnError::Skip, None);
+        let mut reader = lenient(text, &handler);
+        let first = reader.next().unwrap().unwrap();
+        assert_eq!(first.seq, b"ACGT");
+        assert_eq!(reader.location().line, Some(3));
+        assert_eq!(reader.location().record, Some(1));
+        let records = read_all(&mut reader);
+
+        // A record without sequence is valid FASTA
+        assert_eq!(records, ids(&[("r2", 2), ("r3", 3)]));
+        assert_eq!(
+            handler.finish().unwrap().unwrap(),
+            "Dropped 1 malformed record: 1 invalid start"
+        );
+    }
+
+    #[test]
+    fn fails_on_the_first_malformed_record() {
+        let text = "@r1\nACGT\n+\nIIII\n@r2\nACGT\n+\nII\n@r3\nA\n+\nI\n";
+        let handler = ErrorHandler::new(OnError::Fail, None);
+        let mut reader = lenient(text, &handler);
+        assert!(reader.next().unwrap().is_ok());
+        match reader.next().unwrap().unwrap_err() {
+            SeqtoolsError::Parse { location, .. } => {
+                assert_eq!((location.record, location.line), (Some(1), Some(5)))
+            }
+            err => panic!("unexpected error {err:?}"),
+        }
+        assert_eq!(handler.finish().unwrap(), None);
+    }
+
+    #[test]
+    fn shares_counts_between_inputs() {
+        let handler = ErrorHandler::new(OnError::Skip, None);
+        read_all(&mut lenient("@r1\nA\n+\n\n", &handler));
+        read_all(&mut lenient("x\n>r1\nA\n", &handler));
+        let mut lines = RecordReader::lines(
+            Box::new(Cursor::new("r1\tACGT\nr2\n")),
+            Format::Tsv,
+            "test",
+            handler.clone(),
+        );
+        assert_eq!(read_all(&mut lines), ids(&[("r1", 0)]));
+
+        assert_eq!(
+            handler.dropped(),
+            BTreeMap::from([
+                ("invalid line", 1),
+                ("invalid start", 1),
+                ("unequal lengths", 1)
+            ])
+        );
+    }
+}
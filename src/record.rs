@@ -0,0 +1,60 @@
+//! Owned sequence records, decoupled from the lifetime of the parser buffer.
+use std::ops::Range;
+use std::str::Utf8Error;
+
+use needletail::parser::SequenceRecord;
+
+use crate::sequence;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub id: Vec<u8>,
+    pub seq: Vec<u8>,
+    pub qual: Option<Vec<u8>>,
+    /// `(tag, text)` annotations of the sequence in alignment formats, such
+    /// as the `#=GS` lines of Stockholm files
+    pub annotations: Vec<(String, String)>,
+}
+
+impl Record {
+    pub fn new(id: Vec<u8>, seq: Vec<u8>, qual: Option<Vec<u8>>) -> Self {
+        Record {
+            id,
+            seq,
+            qual,
+            annotations: vec![],
+        }
+    }
+
+    pub fn id_str(&self) -> Result<&str, Utf8Error> {
+        std::str::from_utf8(&self.id)
+    }
+
+    /// Keep only the `range` part of the sequence, and of the qualities if there are any
+    pub fn slice(&mut self, range: Range<usize>) {
+        self.seq.truncate(range.end);
+        self.seq.drain(..range.start);
+        if let Some(qual) = self.qual.as_mut() {
+            qual.truncate(range.end);
+            qual.drain(..range.start);
+        }
+    }
+
+    /// Reverse complement the sequence, and reverse the qualities
+    pub fn reverse_complement(&mut self) {
+        self.seq = sequence::reverse_complement(&self.seq);
+        if let Some(qual) = self.qual.as_mut() {
+            qual.reverse();
+        }
+    }
+}
+
+impl From<&SequenceRecord<'_>> for Record {
+    fn from(record: &SequenceRecord) -> Self {
+        Record::new(
+            record.id().to_vec(),
+            record.seq().into_owned(),
+            record.qual().map(|qual| qual.to_vec()),
+        )
+    }
+}
@@ -0,0 +1,274 @@
+//! Tabular output of report commands in machine readable formats.
+use std::io::{self, Write};
+
+use clap::ValueEnum;
+use serde_json::{Map, Value};
+
+#[derive(Copy, Clone, ValueEnum, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Human readable layout, specific to each command
+    Text,
+    /// Tab separated values with a header line
+    Tsv,
+    /// Comma separated values with a header line
+    Csv,
+    /// A JSON array with one object per row
+    Json,
+    /// One JSON object per line
+    Jsonl,
+}
+
+/// Rows of values with named columns. Column names are the keys of JSON
+/// objects and the header of TSV and CSV output.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Table {
+    columns: Vec<&'static str>,
+    rows: Vec<Vec<Value>>,
+}
+
+impl Table {
+    pub fn new(columns: &[&'static str]) -> Self {
+        Table {
+            columns: columns.to_vec(),
+            rows: vec![],
+        }
+    }
+
+    /// Add a row, with one value per column
+    pub fn push(&mut self, row: Vec<Value>) {
+        assert_eq!(row.len(), self.columns.len(), "wrong number of values");
+        self.rows.push(row);
+    }
+
+    /// Write the table in the given format. The text format is written as
+    /// tab separated values without a header.
+    pub fn write(&self, writer: &mut dyn Write, format: OutputFormat) -> io::Result<()> {
+        let mut rows = RowWriter::new(writer, &self.columns, format)?;
+        for row in self.rows.iter() {
+            rows.write(row)?;
+        }
+        rows.finish()
+    }
+
+    pub fn print(&self, format: OutputFormat) -> io::Result<()> {
+        self.write(&mut io::stdout().lock(), format)
+    }
+}
+
+/// Writes the rows of a table one at a time, in the format of
+/// [`Table::write`], so that they do not have to be kept in memory
+pub struct RowWriter<'a> {
+    writer: &'a mut dyn Write,
+    columns: Vec<&'static str>,
+    format: OutputFormat,
+    written: usize,
+}
+
+impl<'a> RowWriter<'a> {
+    /// Start the table, writing its header if the format has one
+    pub fn new(
+        writer: &'a mut dyn Write,
+        columns: &[&'static str],
+        format: OutputFormat,
+    ) -> io::Result<Self> {
+        let mut rows = RowWriter {
+            writer,
+            columns: columns.to_vec(),
+            format,
+            written: 0,
+        };
+        match format {
+            OutputFormat::Tsv | OutputFormat::Csv => {
+                let columns: Vec<String> = rows.columns.iter().map(|c| c.to_string()).collect();
+                rows.write_delimited(columns)?;
+            }
+            OutputFormat::Json => write!(rows.writer, "[")?,
+            OutputFormat::Text | OutputFormat::Jsonl => {}
+        }
+
+        Ok(rows)
+    }
+
+    fn object(&self, row: &[Value]) -> Value {
+        let map: Map<String, Value> = self
+            .columns
+            .iter()
+            .zip(row.iter())
+            .map(|(column, value)| (column.to_string(), value.clone()))
+            .collect();
+        Value::Object(map)
+    }
+
+    /// Write a line of values. CSV values are quoted when needed, and tabs,
+    /// line breaks and backslashes in tab separated values are escaped as
+    /// `\t`, `\n`, `\r` and `\\`, so that each row stays on one line.
+    fn write_delimited(&mut self, fields: Vec<String>) -> io::Result<()> {
+        let (sep, escape): (&str, fn(String) -> String) = if self.format == OutputFormat::Csv {
+            (",", quote_csv)
+        } else {
+            ("\t", escape_tsv)
+        };
+        let fields: Vec<String> = fields.into_iter().map(escape).collect();
+        writeln!(self.writer, "{}", fields.join(sep))
+    }
+
+    /// Write a row, with one value per column
+    pub fn write(&mut self, row: &[Value]) -> io::Result<()> {
+        assert_eq!(row.len(), self.columns.len(), "wrong number of values");
+        match self.format {
+            OutputFormat::Text | OutputFormat::Tsv | OutputFormat::Csv => {
+                self.write_delimited(row.iter().map(field).collect())?
+            }
+            OutputFormat::Json => {
+                if self.written > 0 {
+                    write!(self.writer, ",")?;
+                }
+                let object = self.object(row);
+                write!(self.writer, "{object}")?;
+            }
+            OutputFormat::Jsonl => {
+                let object = self.object(row);
+                writeln!(self.writer, "{object}")?;
+            }
+        }
+        self.written += 1;
+
+        Ok(())
+    }
+
+    /// End the table
+    pub fn finish(self) -> io::Result<()> {
+        match self.format {
+            OutputFormat::Json => writeln!(self.writer, "]"),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn quote_csv(field: String) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field
+    }
+}
+
+fn escape_tsv(field: String) -> String {
+    if !field.contains(['\\', '\t', '\n', '\r']) {
+        return field;
+    }
+    let mut escaped = String::with_capacity(field.len() + 2);
+    for c in field.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// String representation of a value in delimited output.
+/// Lists are joined with commas.
+fn field(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        Value::Array(values) => values.iter().map(field).collect::<Vec<_>>().join(","),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn written(table: &Table, format: OutputFormat) -> String {
+        let mut out = vec![];
+        table.write(&mut out, format).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn table() -> Table {
+        let mut table = Table::new(&["file", "count", "mean", "ids"]);
+        table.push(vec![json!("a.fa"), json!(3), json!(1.5), json!(["x", "y"])]);
+        table.push(vec![json!("total"), json!(3), Value::Null, json!([])]);
+        table
+    }
+
+    #[test]
+    fn writes_delimited_tables() {
+        let rows = "a.fa\t3\t1.5\tx,y\ntotal\t3\t\t\n";
+        assert_eq!(written(&table(), OutputFormat::Text), rows);
+        assert_eq!(
+            written(&table(), OutputFormat::Tsv),
+            format!("file\tcount\tmean\tids\n{rows}")
+        );
+        assert_eq!(
+            written(&table(), OutputFormat::Csv),
+            "file,count,mean,ids\na.fa,3,1.5,\"x,y\"\ntotal,3,,\n"
+        );
+    }
+
+    #[test]
+    fn writes_json_tables() {
+        let objects = [
+            json!({"file": "a.fa", "count": 3, "mean": 1.5, "ids": ["x", "y"]}),
+            json!({"file": "total", "count": 3, "mean": null, "ids": []}),
+        ];
+        let json: Value = serde_json::from_str(&written(&table(), OutputFormat::Json)).unwrap();
+        assert_eq!(json, json!(objects));
+
+        let jsonl = written(&table(), OutputFormat::Jsonl);
+        let lines: Vec<Value> = jsonl
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines, objects);
+        assert!(jsonl.starts_with("{\"file\":\"a.fa\",\"count\":3,"));
+    }
+
+    #[test]
+    fn writes_empty_tables() {
+        let table = Table::new(&["file", "count"]);
+        assert_eq!(written(&table, OutputFormat::Text), "");
+        assert_eq!(written(&table, OutputFormat::Tsv), "file\tcount\n");
+        assert_eq!(written(&table, OutputFormat::Json), "[]\n");
+        assert_eq!(written(&table, OutputFormat::Jsonl), "");
+    }
+
+    #[test]
+    #[should_panic(expected = "wrong number of values")]
+    fn checks_row_lengths() {
+        Table::new(&["file", "count"]).push(vec![json!("a.fa")]);
+    }
+
+    #[test]
+    fn escapes_tsv_fields() {
+        let mut table = Table::new(&["id", "note"]);
+        table.push(vec![json!("a\tb"), json!("line\nbreak\r")]);
+        table.push(vec![json!("C:\\dir"), json!("plain")]);
+        let expected = "a\\tb\tline\\nbreak\\r\nC:\\\\dir\tplain\n";
+        assert_eq!(
+            written(&table, OutputFormat::Tsv),
+            format!("id\tnote\n{expected}")
+        );
+        assert_eq!(written(&table, OutputFormat::Text), expected);
+    }
+
+    #[test]
+    fn quotes_csv_fields() {
+        let mut table = Table::new(&["id", "note"]);
+        table.push(vec![json!("a,b"), json!("say \"hi\"")]);
+        table.push(vec![json!("line\nbreak"), json!("cr\r")]);
+        table.push(vec![json!("a\tb"), json!("C:\\dir")]);
+        assert_eq!(
+            written(&table, OutputFormat::Csv),
+            "id,note\n\"a,b\",\"say \"\"hi\"\"\"\n\"line\nbreak\",\"cr\r\"\na\tb,C:\\dir\n"
+        );
+    }
+}
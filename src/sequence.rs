@@ -0,0 +1,110 @@
+//! Operations on raw nucleotide sequences.
+
+/// Whether a nucleotide sequence is RNA: it has uracils and no thymines
+pub fn is_rna(seq: &[u8]) -> bool {
+    let has = |base: u8| seq.iter().any(|c| c.eq_ignore_ascii_case(&base));
+    has(b'U') && !has(b'T')
+}
+
+/// Nucleotides that an IUPAC code stands for, as uppercase DNA bases. Other
+/// characters stand for none.
+pub fn bases(code: u8) -> &'static [u8] {
+    match code.to_ascii_uppercase() {
+        b'A' => b"A",
+        b'C' => b"C",
+        b'G' => b"G",
+        b'T' | b'U' => b"T",
+        b'R' => b"AG",
+        b'Y' => b"CT",
+        b'S' => b"CG",
+        b'W' => b"AT",
+        b'K' => b"GT",
+        b'M' => b"AC",
+        b'B' => b"CGT",
+        b'D' => b"AGT",
+        b'H' => b"ACT",
+        b'V' => b"ACG",
+        b'N' => b"ACGT",
+        _ => b"",
+    }
+}
+
+/// Complement of a nucleotide or IUPAC ambiguity code, keeping its case.
+/// Adenine pairs with uracil in RNA and with thymine otherwise. Gaps and
+/// unknown characters are kept as is.
+pub fn complement(base: u8, rna: bool) -> u8 {
+    let complement = match base.to_ascii_uppercase() {
+        b'A' if rna => b'U',
+        b'A' => b'T',
+        b'T' | b'U' => b'A',
+        b'C' => b'G',
+        b'G' => b'C',
+        b'R' => b'Y',
+        b'Y' => b'R',
+        b'K' => b'M',
+        b'M' => b'K',
+        b'B' => b'V',
+        b'V' => b'B',
+        b'D' => b'H',
+        b'H' => b'D',
+        _ => return base,
+    };
+
+    if base.is_ascii_lowercase() {
+        complement.to_ascii_lowercase()
+    } else {
+        complement
+    }
+}
+
+/// Reverse complement of a DNA or RNA sequence
+pub fn reverse_complement(seq: &[u8]) -> Vec<u8> {
+    let rna = is_rna(seq);
+    seq.iter()
+        .rev()
+        .map(|base| complement(*base, rna))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn complements_iupac_codes() {
+        assert_eq!(reverse_complement(b"ACGTRYKMBVDHNSW"), b"WSNDHBVKMRYACGT");
+        for code in b"ACGTRYKMBVDHNSW" {
+            let mut complements: Vec<u8> = bases(*code)
+                .iter()
+                .map(|base| complement(*base, false))
+                .collect();
+            complements.sort();
+            assert_eq!(bases(complement(*code, false)), complements);
+        }
+    }
+
+    #[test]
+    fn keeps_case_gaps_and_unknown_characters() {
+        assert_eq!(reverse_complement(b"aCgTn-ry.X*"), b"*X.ry-nAcGt");
+    }
+
+    #[test]
+    fn complements_rna() {
+        assert!(is_rna(b"acgu"));
+        assert!(!is_rna(b"ACGUT"));
+        assert!(!is_rna(b"ACGN"));
+        assert_eq!(reverse_complement(b"AACGu"), b"aCGUU");
+        assert_eq!(reverse_complement(b"AACGT"), b"ACGTT");
+        assert_eq!(reverse_complement(b"AAAA"), b"TTTT");
+        assert_eq!(reverse_complement(b""), b"");
+    }
+
+    #[test]
+    fn reverses_qualities() {
+        let mut record =
+            crate::record::Record::new(b"r".to_vec(), b"AACGU".to_vec(), Some(b"ABCDE".to_vec()));
+        record.reverse_complement();
+        assert_eq!(record.seq, b"ACGUU");
+        assert_eq!(record.qual.unwrap(), b"EDCBA");
+    }
+}
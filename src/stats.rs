@@ -0,0 +1,508 @@
+//! Functions that summarize the records of a FASTX file without rewriting them.
+//!
+//! Records are read in batches and each batch is processed on the global
+//! rayon thread pool. Results do not depend on the number of threads.
+use std::collections::BTreeMap;
+use std::error::Error;
+
+use histogram::Histogram;
+use rayon::prelude::*;
+
+use crate::errors::SeqtoolsError;
+use crate::kmers::KmerCounts;
+use crate::reader::RecordReader;
+use crate::record::Record;
+
+/// Summary statistics of a distribution of sequence lengths
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LengthStats {
+    pub min: u64,
+    pub max: u64,
+    pub mean: u64,
+    pub std: u64,
+    pub median: u64,
+    pub q1: u64,
+    pub q3: u64,
+}
+
+impl LengthStats {
+    pub fn from_hist(hist: &Histogram) -> Result<Self, Box<dyn Error>> {
+        let (min, max) = (hist.minimum()?, hist.maximum()?);
+        let mean = hist.mean()?;
+        let std = hist.stddev().unwrap_or(0);
+        let (median, q1, q3) = (
+            hist.percentile(50.)?,
+            hist.percentile(25.)?,
+            hist.percentile(75.)?,
+        );
+
+        Ok(LengthStats {
+            min,
+            max,
+            mean,
+            std,
+            median,
+            q1,
+            q3,
+        })
+    }
+}
+
+/// Length of a single sequence
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeqLength {
+    pub id: String,
+    pub length: usize,
+}
+
+/// Number of occurrences of each character in one or several sequences
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Frequencies {
+    pub counts: BTreeMap<u8, u64>,
+}
+
+impl Frequencies {
+    pub fn add(&mut self, seq: &[u8]) {
+        for c in seq {
+            *self.counts.entry(*c).or_insert(0) += 1;
+        }
+    }
+
+    /// Add the counts of `other` to these
+    pub fn merge(&mut self, other: &Frequencies) {
+        for (c, count) in other.counts.iter() {
+            *self.counts.entry(*c).or_insert(0) += count;
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.counts.values().sum()
+    }
+
+    /// Percentage of all counted characters that are `c`
+    pub fn percent(&self, c: u8) -> f64 {
+        let total = self.total();
+        match self.counts.get(&c) {
+            Some(count) if total > 0 => (*count as f64 / total as f64) * 100.,
+            _ => 0.,
+        }
+    }
+}
+
+/// Character frequencies of a single sequence
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeqFrequencies {
+    pub id: String,
+    pub frequencies: Frequencies,
+}
+
+/// K-mer counts of a single sequence
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeqKmers {
+    pub id: String,
+    pub kmers: KmerCounts,
+}
+
+/// Groups of sequence identifiers that share the exact same sequence
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DuplicateGroups {
+    pub groups: Vec<Vec<String>>,
+}
+
+impl DuplicateGroups {
+    /// Keep the groups of identifiers that share their sequence with another record
+    pub fn from_groups(groups: &BTreeMap<String, Vec<String>>) -> Self {
+        let groups = groups
+            .values()
+            .filter(|ids| ids.len() > 1)
+            .cloned()
+            .collect();
+
+        DuplicateGroups { groups }
+    }
+
+    /// Total number of sequences that have at least one duplicate
+    pub fn total(&self) -> usize {
+        self.groups.iter().map(|group| group.len()).sum()
+    }
+}
+
+/// Number of records read before they are handed over to the thread pool
+const BATCH_SIZE: usize = 10_000;
+
+/// Read up to `BATCH_SIZE` records, converting each one with `f` as soon as
+/// it is read so that conversion errors point at the right record
+fn read_batch<T>(
+    reader: &mut RecordReader,
+    mut f: impl FnMut(Record, &RecordReader) -> Result<T, SeqtoolsError>,
+) -> Result<Vec<T>, SeqtoolsError> {
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    while batch.len() < BATCH_SIZE {
+        match reader.next() {
+            Some(record) => batch.push(f(record?, reader)?),
+            None => break,
+        }
+    }
+
+    Ok(batch)
+}
+
+/// Identifier of the last record read from `reader`
+fn id_string(record: &Record, reader: &RecordReader) -> Result<String, SeqtoolsError> {
+    match record.id_str() {
+        Ok(id) => Ok(id.to_string()),
+        Err(e) => Err(
+            SeqtoolsError::record(format!("Error reading id: {e}"), &record.id)
+                .with_location(reader.location()),
+        ),
+    }
+}
+
+/// The last record read from `reader` with its identifier
+fn with_id(record: Record, reader: &RecordReader) -> Result<(String, Record), SeqtoolsError> {
+    Ok((id_string(&record, reader)?, record))
+}
+
+pub fn count(reader: &mut RecordReader) -> Result<usize, SeqtoolsError> {
+    let mut count = 0;
+    loop {
+        let batch = read_batch(reader, |record, _| Ok(record))?;
+        if batch.is_empty() {
+            break;
+        }
+        count += batch.into_par_iter().count();
+    }
+
+    Ok(count)
+}
+
+pub fn ids(reader: &mut RecordReader) -> Result<Vec<String>, SeqtoolsError> {
+    let mut ids = vec![];
+    while let Some(record) = reader.next() {
+        let record = record?;
+        ids.push(id_string(&record, reader)?);
+    }
+
+    Ok(ids)
+}
+
+pub fn lengths(reader: &mut RecordReader) -> Result<Vec<SeqLength>, SeqtoolsError> {
+    let mut lengths = vec![];
+    loop {
+        let batch = read_batch(reader, with_id)?;
+        if batch.is_empty() {
+            break;
+        }
+        lengths.par_extend(batch.into_par_iter().map(|(id, record)| SeqLength {
+            id,
+            length: record.seq.len(),
+        }));
+    }
+
+    Ok(lengths)
+}
+
+/// Build a histogram of the sequence lengths in the reader
+pub fn length_histogram(reader: &mut RecordReader) -> Result<Histogram, Box<dyn Error>> {
+    let mut hist = Histogram::new();
+    loop {
+        let batch = read_batch(reader, |record, _| Ok(record.seq.len() as u64))?;
+        if batch.is_empty() {
+            break;
+        }
+        let batch_hist = batch
+            .into_par_iter()
+            .try_fold(Histogram::new, |mut hist, length| {
+                hist.increment(length).map(|_| hist)
+            })
+            .try_reduce(Histogram::new, |mut hist, other| {
+                hist.merge(&other);
+                Ok(hist)
+            })?;
+        hist.merge(&batch_hist);
+    }
+
+    Ok(hist)
+}
+
+pub fn frequencies(reader: &mut RecordReader) -> Result<Frequencies, SeqtoolsError> {
+    let mut freqs = Frequencies::default();
+    loop {
+        let batch = read_batch(reader, |record, _| Ok(record.seq))?;
+        if batch.is_empty() {
+            break;
+        }
+        let batch_freqs = batch
+            .par_iter()
+            .fold(Frequencies::default, |mut freqs, seq| {
+                freqs.add(seq);
+                freqs
+            })
+            .reduce(Frequencies::default, |mut freqs, other| {
+                freqs.merge(&other);
+                freqs
+            });
+        freqs.merge(&batch_freqs);
+    }
+
+    Ok(freqs)
+}
+
+pub fn frequencies_per_sequence(
+    reader: &mut RecordReader,
+) -> Result<Vec<SeqFrequencies>, SeqtoolsError> {
+    let mut all = vec![];
+    loop {
+        let batch = read_batch(reader, with_id)?;
+        if batch.is_empty() {
+            break;
+        }
+        all.par_extend(batch.into_par_iter().map(|(id, record)| {
+            let mut frequencies = Frequencies::default();
+            frequencies.add(&record.seq);
+            SeqFrequencies { id, frequencies }
+        }));
+    }
+
+    Ok(all)
+}
+
+/// Count the k-mers of all the sequences of the reader
+pub fn kmers(
+    reader: &mut RecordReader,
+    k: usize,
+    canonical: bool,
+) -> Result<KmerCounts, SeqtoolsError> {
+    let empty = KmerCounts::new(k, canonical)?;
+    let mut counts = empty.clone();
+    loop {
+        let batch = read_batch(reader, |record, _| Ok(record.seq))?;
+        if batch.is_empty() {
+            break;
+        }
+        let batch_counts = batch
+            .par_iter()
+            .fold(
+                || empty.clone(),
+                |mut counts, seq| {
+                    counts.add(seq);
+                    counts
+                },
+            )
+            .reduce(
+                || empty.clone(),
+                |mut counts, other| {
+                    counts.merge(other);
+                    counts
+                },
+            );
+        counts.merge(batch_counts);
+    }
+
+    Ok(counts)
+}
+
+/// Count the k-mers of each sequence of the reader, handing the counts over
+/// to `f` in file order as soon as their batch is done, so that only one
+/// batch of counts is kept in memory
+pub fn kmers_per_sequence(
+    reader: &mut RecordReader,
+    k: usize,
+    canonical: bool,
+    mut f: impl FnMut(SeqKmers) -> Result<(), Box<dyn Error>>,
+) -> Result<(), Box<dyn Error>> {
+    let empty = KmerCounts::new(k, canonical)?;
+    loop {
+        let batch = read_batch(reader, with_id)?;
+        if batch.is_empty() {
+            break;
+        }
+        let counted: Vec<SeqKmers> = batch
+            .into_par_iter()
+            .map(|(id, record)| {
+                let mut kmers = empty.clone();
+                kmers.add(&record.seq);
+                SeqKmers { id, kmers }
+            })
+            .collect();
+        for seq_kmers in counted {
+            f(seq_kmers)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Group the identifiers of the reader's records by sequence.
+/// Groups are ordered by sequence and identifiers keep their order in the file.
+pub fn group_by_sequence(
+    reader: &mut RecordReader,
+) -> Result<BTreeMap<String, Vec<String>>, SeqtoolsError> {
+    let mut groups = BTreeMap::new();
+    loop {
+        let batch = read_batch(reader, |record, reader| {
+            let id = id_string(&record, reader)?;
+            match String::from_utf8(record.seq) {
+                Ok(seq) => Ok((id, seq)),
+                Err(e) => Err(SeqtoolsError::record(
+                    format!("Error reading sequence: {e}"),
+                    id.as_bytes(),
+                )
+                .with_location(reader.location())),
+            }
+        })?;
+        if batch.is_empty() {
+            break;
+        }
+        // Reducing keeps the order of the batch, so ids stay in file order
+        let batch_groups = batch
+            .into_par_iter()
+            .fold(BTreeMap::new, |mut groups, (id, seq)| {
+                let entry: &mut Vec<String> = groups.entry(seq).or_default();
+                entry.push(id.trim().to_string());
+                groups
+            })
+            .reduce(BTreeMap::new, |mut groups, other| {
+                merge_groups(&mut groups, other);
+                groups
+            });
+        merge_groups(&mut groups, batch_groups);
+    }
+
+    Ok(groups)
+}
+
+/// Add the sequence groups of `other` to `groups`
+pub fn merge_groups(
+    groups: &mut BTreeMap<String, Vec<String>>,
+    other: BTreeMap<String, Vec<String>>,
+) {
+    for (seq, ids) in other {
+        groups.entry(seq).or_default().extend(ids);
+    }
+}
+
+pub fn duplicates(reader: &mut RecordReader) -> Result<DuplicateGroups, SeqtoolsError> {
+    Ok(DuplicateGroups::from_groups(&group_by_sequence(reader)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    /// A reader of `n` FASTA records, enough for several batches
+    fn reader(n: usize) -> RecordReader {
+        let text: String = (0..n)
+            .map(|i| format!(">r{i}\n{}\n", "A".repeat(i % 7 + 1)))
+            .collect();
+        let parser = needletail::parse_fastx_reader(Cursor::new(text)).unwrap();
+        RecordReader::new(parser, "test")
+    }
+
+    fn parse(text: &str) -> RecordReader {
+        let parser = needletail::parse_fastx_reader(Cursor::new(text.to_string())).unwrap();
+        RecordReader::new(parser, "test")
+    }
+
+    const FASTA: &str = ">a first\nACGT\n>b\nAC\n>c\nACGT\n>d\nGGGGGG\n>e\nAC\n";
+
+    fn with_threads<T: Send>(threads: usize, f: impl FnOnce() -> T + Send) -> T {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap()
+            .install(f)
+    }
+
+    #[test]
+    fn counts_batches_of_records() {
+        let n = 2 * BATCH_SIZE + 3;
+        for threads in [1, 4] {
+            let count = with_threads(threads, || count(&mut reader(n)).unwrap());
+            assert_eq!(count, n);
+        }
+        assert_eq!(count(&mut reader(1)).unwrap(), 1);
+    }
+
+    #[test]
+    fn gives_the_same_results_on_several_threads() {
+        let n = BATCH_SIZE + 10;
+        let serial = with_threads(1, || {
+            (
+                lengths(&mut reader(n)).unwrap(),
+                frequencies(&mut reader(n)).unwrap(),
+            )
+        });
+        let parallel = with_threads(4, || {
+            (
+                lengths(&mut reader(n)).unwrap(),
+                frequencies(&mut reader(n)).unwrap(),
+            )
+        });
+        assert_eq!(serial, parallel);
+        assert_eq!(serial.0[n - 1].id, format!("r{}", n - 1));
+        assert_eq!(
+            serial.1.total(),
+            serial.0.iter().map(|l| l.length as u64).sum::<u64>()
+        );
+    }
+
+    #[test]
+    fn summarizes_lengths() {
+        assert_eq!(
+            ids(&mut parse(FASTA)).unwrap(),
+            ["a first", "b", "c", "d", "e"]
+        );
+        let lengths: Vec<usize> = lengths(&mut parse(FASTA))
+            .unwrap()
+            .iter()
+            .map(|l| l.length)
+            .collect();
+        assert_eq!(lengths, [4, 2, 4, 6, 2]);
+
+        let stats = LengthStats::from_hist(&length_histogram(&mut parse(FASTA)).unwrap()).unwrap();
+        assert_eq!((stats.min, stats.max, stats.mean), (2, 6, 4));
+        assert_eq!(stats.median, 4);
+        assert!(stats.min <= stats.q1 && stats.q1 <= stats.median && stats.median <= stats.q3);
+    }
+
+    #[test]
+    fn counts_characters() {
+        let freqs = frequencies(&mut parse(FASTA)).unwrap();
+        assert_eq!(freqs.total(), 18);
+        assert_eq!(freqs.counts[&b'G'], 8);
+        assert_eq!(freqs.percent(b'T'), 2. / 18. * 100.);
+        assert_eq!(freqs.percent(b'N'), 0.);
+        assert_eq!(Frequencies::default().percent(b'A'), 0.);
+
+        let per_sequence = frequencies_per_sequence(&mut parse(FASTA)).unwrap();
+        assert_eq!(per_sequence[3].id, "d");
+        assert_eq!(
+            per_sequence[3].frequencies.counts,
+            BTreeMap::from([(b'G', 6)])
+        );
+    }
+
+    #[test]
+    fn finds_duplicates() {
+        let groups = duplicates(&mut parse(FASTA)).unwrap();
+        assert_eq!(groups.groups, [vec!["b", "e"], vec!["a first", "c"]]);
+        assert_eq!(groups.total(), 4);
+        assert_eq!(duplicates(&mut parse(">a\nA\n>b\nC\n")).unwrap().total(), 0);
+    }
+
+    #[test]
+    fn points_at_records_with_invalid_ids() {
+        let text = b">a\nACGT\n>b\xFF\nACGT\n".to_vec();
+        let parser = needletail::parse_fastx_reader(Cursor::new(text)).unwrap();
+        match lengths(&mut RecordReader::new(parser, "test")).unwrap_err() {
+            SeqtoolsError::Record { location, .. } => {
+                assert_eq!(location.file.as_deref(), Some("test"));
+                assert_eq!(location.record, Some(1));
+            }
+            err => panic!("unexpected error {err:?}"),
+        }
+    }
+}
@@ -0,0 +1,51 @@
+//! Helpers shared by the unit tests of the library.
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use crate::faidx::index_path;
+
+/// A file in the temporary directory that is removed along with its indexes.
+/// Names must be unique across tests, which run in parallel.
+pub struct TempFile(pub PathBuf);
+
+impl TempFile {
+    pub fn new(name: &str, content: &[u8]) -> Self {
+        let path = std::env::temp_dir().join(format!("seqtools-{}-{name}", std::process::id()));
+        fs::write(&path, content).unwrap();
+        TempFile(path)
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+        for extension in ["fai", "gzi", "idx"] {
+            let _ = fs::remove_file(index_path(&self.0, extension));
+        }
+    }
+}
+
+/// An in-memory writer whose content can still be read once a clone of it
+/// was handed over to a writer or an error handler
+#[derive(Clone, Default)]
+pub struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl SharedBuffer {
+    /// The content written so far, which must be UTF-8
+    pub fn text(&self) -> String {
+        String::from_utf8(self.0.borrow().clone()).unwrap()
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,456 @@
+//! Streaming record transformations.
+//!
+//! Every command that rewrites records is a [`RecordTransform`]: it receives
+//! owned records one at a time and emits zero, one or several records. The
+//! [`process`] driver takes care of reading the input, writing the output
+//! with a [`RecordWriter`], and transforms can be combined with [`Chain`].
+//! Read pairs go through [`process_pairs`], which keeps the mates together.
+use std::error::Error;
+
+use crate::errors::SeqtoolsError;
+use crate::pairs::{Mates, Pair, PairReader, PairWriter};
+use crate::reader::{ErrorHandler, RecordReader};
+use crate::record::Record;
+use crate::writer::RecordWriter;
+use crate::Input;
+
+pub trait RecordTransform {
+    /// Transform a record, pushing the resulting records to `out`
+    fn apply(&mut self, record: Record, out: &mut Vec<Record>) -> Result<(), Box<dyn Error>>;
+
+    /// Called once all the input records have been seen. Transforms that
+    /// buffer records push them to `out` here.
+    fn finish(&mut self, _out: &mut Vec<Record>) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    /// Transform a read pair, pushing the resulting pairs to `out`. By
+    /// default both mates go through `apply` and the pair is kept if both
+    /// mates are. Transforms that select or count records override it to
+    /// handle the pair as a whole.
+    fn apply_pair(&mut self, pair: Pair, out: &mut Vec<Pair>) -> Result<(), Box<dyn Error>> {
+        let (mut first, mut second) = (vec![], vec![]);
+        self.apply(pair.0, &mut first)?;
+        self.apply(pair.1, &mut second)?;
+        out.extend(first.into_iter().zip(second));
+        Ok(())
+    }
+
+    /// Called once all the input pairs have been seen, like `finish`
+    fn finish_pairs(&mut self, _out: &mut Vec<Pair>) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
+
+pub struct Map<F>(F);
+pub struct Filter<F>(F);
+pub struct FlatMap<F>(F);
+
+/// Replace each record with the result of `f`
+pub fn map<F>(f: F) -> Map<F>
+where
+    F: FnMut(Record) -> Result<Record, Box<dyn Error>>,
+{
+    Map(f)
+}
+
+/// Only keep the records for which `f` returns true
+pub fn filter<F>(f: F) -> Filter<F>
+where
+    F: FnMut(&Record) -> Result<bool, Box<dyn Error>>,
+{
+    Filter(f)
+}
+
+/// Replace each record with all the records returned by `f`
+pub fn flat_map<F>(f: F) -> FlatMap<F>
+where
+    F: FnMut(Record) -> Result<Vec<Record>, Box<dyn Error>>,
+{
+    FlatMap(f)
+}
+
+impl<F> RecordTransform for Map<F>
+where
+    F: FnMut(Record) -> Result<Record, Box<dyn Error>>,
+{
+    fn apply(&mut self, record: Record, out: &mut Vec<Record>) -> Result<(), Box<dyn Error>> {
+        out.push((self.0)(record)?);
+        Ok(())
+    }
+}
+
+impl<F> RecordTransform for Filter<F>
+where
+    F: FnMut(&Record) -> Result<bool, Box<dyn Error>>,
+{
+    fn apply(&mut self, record: Record, out: &mut Vec<Record>) -> Result<(), Box<dyn Error>> {
+        if (self.0)(&record)? {
+            out.push(record);
+        }
+        Ok(())
+    }
+}
+
+impl<F> RecordTransform for FlatMap<F>
+where
+    F: FnMut(Record) -> Result<Vec<Record>, Box<dyn Error>>,
+{
+    fn apply(&mut self, record: Record, out: &mut Vec<Record>) -> Result<(), Box<dyn Error>> {
+        out.extend((self.0)(record)?);
+        Ok(())
+    }
+}
+
+/// Apply several transforms one after the other.
+/// An empty chain leaves the records untouched.
+#[derive(Default)]
+pub struct Chain {
+    steps: Vec<Box<dyn RecordTransform>>,
+}
+
+impl Chain {
+    pub fn new(steps: Vec<Box<dyn RecordTransform>>) -> Self {
+        Chain { steps }
+    }
+
+    pub fn push(&mut self, step: Box<dyn RecordTransform>) {
+        self.steps.push(step);
+    }
+
+    /// Run items through the steps starting at `start`, with `apply`
+    /// applying a step to a record or a pair
+    fn feed<T>(
+        &mut self,
+        start: usize,
+        items: Vec<T>,
+        out: &mut Vec<T>,
+        apply: impl Fn(&mut dyn RecordTransform, T, &mut Vec<T>) -> Result<(), Box<dyn Error>>,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut current = items;
+        for step in self.steps[start..].iter_mut() {
+            let mut next = Vec::with_capacity(current.len());
+            for item in current {
+                apply(step.as_mut(), item, &mut next)?;
+            }
+            current = next;
+        }
+        out.extend(current);
+
+        Ok(())
+    }
+}
+
+impl RecordTransform for Chain {
+    fn apply(&mut self, record: Record, out: &mut Vec<Record>) -> Result<(), Box<dyn Error>> {
+        self.feed(0, vec![record], out, |step, record, out| {
+            step.apply(record, out)
+        })
+    }
+
+    fn finish(&mut self, out: &mut Vec<Record>) -> Result<(), Box<dyn Error>> {
+        for i in 0..self.steps.len() {
+            let mut flushed = vec![];
+            self.steps[i].finish(&mut flushed)?;
+            self.feed(i + 1, flushed, out, |step, record, out| {
+                step.apply(record, out)
+            })?;
+        }
+
+        Ok(())
+    }
+
+    fn apply_pair(&mut self, pair: Pair, out: &mut Vec<Pair>) -> Result<(), Box<dyn Error>> {
+        self.feed(0, vec![pair], out, |step, pair, out| {
+            step.apply_pair(pair, out)
+        })
+    }
+
+    fn finish_pairs(&mut self, out: &mut Vec<Pair>) -> Result<(), Box<dyn Error>> {
+        for i in 0..self.steps.len() {
+            let mut flushed = vec![];
+            self.steps[i].finish_pairs(&mut flushed)?;
+            self.feed(i + 1, flushed, out, |step, pair, out| {
+                step.apply_pair(pair, out)
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Run the records of `reader` through `transform` and write the results,
+/// without finishing the transform. Errors raised by the transform point at
+/// the record being processed.
+fn feed(
+    reader: &mut RecordReader,
+    writer: &mut RecordWriter,
+    transform: &mut dyn RecordTransform,
+) -> Result<(), Box<dyn Error>> {
+    writer.set_input_format(reader.format());
+    writer.set_input_annotations(reader.annotations());
+
+    let mut buffer = vec![];
+    while let Some(record) = reader.next() {
+        let record = record?;
+        let id = record.id.clone();
+        transform
+            .apply(record, &mut buffer)
+            .map_err(|e| SeqtoolsError::in_record(e, &id, reader.location()))?;
+        for record in buffer.drain(..) {
+            writer.write(record)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Write the records buffered by `transform` once all inputs have been read
+fn finish(
+    writer: &mut RecordWriter,
+    transform: &mut dyn RecordTransform,
+) -> Result<(), Box<dyn Error>> {
+    let mut buffer = vec![];
+    transform.finish(&mut buffer)?;
+    for record in buffer.drain(..) {
+        writer.write(record)?;
+    }
+    writer.finish()?;
+
+    Ok(())
+}
+
+/// Read all the records, run them through `transform` and write the results.
+pub fn process(
+    reader: &mut RecordReader,
+    writer: &mut RecordWriter,
+    transform: &mut dyn RecordTransform,
+) -> Result<(), Box<dyn Error>> {
+    feed(reader, writer, transform)?;
+    finish(writer, transform)
+}
+
+/// Like [`process`], reading the inputs one after the other as if they
+/// were a single stream of records. Malformed records are handled by `handler`.
+pub fn process_inputs(
+    inputs: &[Input],
+    handler: &ErrorHandler,
+    writer: &mut RecordWriter,
+    transform: &mut dyn RecordTransform,
+) -> Result<(), Box<dyn Error>> {
+    for input in inputs {
+        let mut reader = input.open_with(handler)?;
+        feed(&mut reader, writer, transform)?;
+    }
+    finish(writer, transform)
+}
+
+/// Like [`process_inputs`], for read pairs: each input is read along with its
+/// mates, and the pairs go through [`RecordTransform::apply_pair`].
+pub fn process_pairs(
+    inputs: &[Input],
+    mates: &Mates,
+    handler: &ErrorHandler,
+    writer: &mut PairWriter,
+    transform: &mut dyn RecordTransform,
+) -> Result<(), Box<dyn Error>> {
+    for (i, input) in inputs.iter().enumerate() {
+        let reader = input.open_with(handler)?;
+        let mut reader = match mates {
+            Mates::None => {
+                return Err(SeqtoolsError::invalid_argument("The inputs are not paired").into())
+            }
+            Mates::Interleaved => PairReader::interleaved(reader),
+            Mates::Files(files) => {
+                let mate_file = files.get(i).ok_or_else(|| {
+                    SeqtoolsError::invalid_argument(format!("No mate file for {input}"))
+                })?;
+                PairReader::new(reader, mate_file.open_with(handler)?)
+            }
+        };
+        writer.set_input_format(reader.format());
+
+        let mut buffer = vec![];
+        while let Some(pair) = reader.next() {
+            let pair = pair?;
+            let id = pair.0.id.clone();
+            transform
+                .apply_pair(pair, &mut buffer)
+                .map_err(|e| SeqtoolsError::in_record(e, &id, reader.location()))?;
+            for pair in buffer.drain(..) {
+                writer.write(pair)?;
+            }
+        }
+    }
+
+    let mut buffer = vec![];
+    transform.finish_pairs(&mut buffer)?;
+    for pair in buffer.drain(..) {
+        writer.write(pair)?;
+    }
+    writer.finish()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::testing::{SharedBuffer, TempFile};
+    use crate::writer::WriteOptions;
+
+    fn record(id: &str, seq: &str) -> Record {
+        Record::new(id.into(), seq.into(), None)
+    }
+
+    fn ids(records: &[Record]) -> Vec<String> {
+        records
+            .iter()
+            .map(|r| String::from_utf8_lossy(&r.id).into_owned())
+            .collect()
+    }
+
+    /// Run records through `transform`, finishing it at the end
+    fn run(transform: &mut dyn RecordTransform, records: Vec<Record>) -> Vec<Record> {
+        let mut out = vec![];
+        for record in records {
+            transform.apply(record, &mut out).unwrap();
+        }
+        transform.finish(&mut out).unwrap();
+        out
+    }
+
+    fn suffix(suffix: &'static str) -> Box<dyn RecordTransform> {
+        Box::new(map(move |mut record: Record| {
+            record.id.extend(suffix.as_bytes());
+            Ok(record)
+        }))
+    }
+
+    /// Holds back every record until the end
+    struct Buffer(Vec<Record>);
+
+    impl RecordTransform for Buffer {
+        fn apply(&mut self, record: Record, _: &mut Vec<Record>) -> Result<(), Box<dyn Error>> {
+            self.0.push(record);
+            Ok(())
+        }
+
+        fn finish(&mut self, out: &mut Vec<Record>) -> Result<(), Box<dyn Error>> {
+            out.append(&mut self.0);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn maps_filters_and_flat_maps() {
+        let records = vec![record("a", "ACGT"), record("b", "AC")];
+        assert_eq!(
+            ids(&run(&mut *suffix("_x"), records.clone())),
+            ["a_x", "b_x"]
+        );
+        let mut long = filter(|record: &Record| Ok(record.seq.len() > 2));
+        assert_eq!(ids(&run(&mut long, records.clone())), ["a"]);
+        let mut twice = flat_map(|record: Record| Ok(vec![record.clone(), record]));
+        assert_eq!(ids(&run(&mut twice, records)), ["a", "a", "b", "b"]);
+    }
+
+    #[test]
+    fn chains_steps_in_order() {
+        let records = vec![record("a", "ACGT"), record("b", "AC")];
+        assert_eq!(
+            ids(&run(&mut Chain::default(), records.clone())),
+            ["a", "b"]
+        );
+
+        let mut chain = Chain::new(vec![suffix("_1")]);
+        chain.push(Box::new(flat_map(|record: Record| {
+            Ok(vec![record.clone(), record])
+        })));
+        chain.push(Box::new(filter(|record: &Record| Ok(record.seq.len() > 2))));
+        chain.push(suffix("_2"));
+        assert_eq!(ids(&run(&mut chain, records)), ["a_1_2", "a_1_2"]);
+    }
+
+    #[test]
+    fn finishes_steps_through_the_next_ones() {
+        let mut chain = Chain::new(vec![suffix("_1"), Box::new(Buffer(vec![])), suffix("_2")]);
+        let mut out = vec![];
+        chain.apply(record("a", "A"), &mut out).unwrap();
+        chain.apply(record("b", "A"), &mut out).unwrap();
+        assert!(out.is_empty());
+        chain.finish(&mut out).unwrap();
+        assert_eq!(ids(&out), ["a_1_2", "b_1_2"]);
+    }
+
+    #[test]
+    fn keeps_pairs_whose_mates_are_both_kept() {
+        let mut chain = Chain::new(vec![
+            suffix("_x"),
+            Box::new(filter(|record: &Record| Ok(record.seq.len() > 2))),
+        ]);
+        let mut out = vec![];
+        chain
+            .apply_pair((record("a/1", "ACG"), record("a/2", "ACG")), &mut out)
+            .unwrap();
+        chain
+            .apply_pair((record("b/1", "ACG"), record("b/2", "AC")), &mut out)
+            .unwrap();
+        chain.finish_pairs(&mut out).unwrap();
+        assert_eq!(out.len(), 1);
+        assert_eq!(
+            ids(&[out[0].0.clone(), out[0].1.clone()]),
+            ["a/1_x", "a/2_x"]
+        );
+    }
+
+    #[test]
+    fn reads_inputs_one_after_the_other() {
+        let first = TempFile::new("inputs-1.fa", b">a\nACGT\n>b\nAC\n");
+        let second = TempFile::new("inputs-2.fa", b">c\nACG\n");
+        let inputs = [Input::File(first.0.clone()), Input::File(second.0.clone())];
+        let out = SharedBuffer::default();
+        let mut writer = RecordWriter::new(Box::new(out.clone()), None, WriteOptions::default());
+        let mut buffer = Buffer(vec![]);
+
+        process_inputs(&inputs, &ErrorHandler::default(), &mut writer, &mut buffer).unwrap();
+        assert_eq!(out.text(), ">a\nACGT\n>b\nAC\n>c\nACG\n");
+
+        let missing = [Input::File(first.0.with_extension("missing"))];
+        let mut writer = RecordWriter::new(Box::new(out.clone()), None, WriteOptions::default());
+        let err = process_inputs(&missing, &ErrorHandler::default(), &mut writer, &mut buffer)
+            .unwrap_err();
+        let location = err.downcast::<SeqtoolsError>().unwrap().location().cloned();
+        assert_eq!(location.unwrap().file, Some(missing[0].to_string()));
+    }
+
+    #[test]
+    fn points_errors_at_the_record() {
+        let text = ">a\nACGT\n>b\nNNNN\n>c\nACGT\n";
+        let parser = needletail::parse_fastx_reader(Cursor::new(text)).unwrap();
+        let mut reader = RecordReader::new(parser, "test");
+        let out = SharedBuffer::default();
+        let mut writer = RecordWriter::new(Box::new(out.clone()), None, WriteOptions::default());
+        let mut check = filter(|record: &Record| match record.seq.contains(&b'N') {
+            true => Err("ambiguous base".into()),
+            false => Ok(true),
+        });
+
+        let err = process(&mut reader, &mut writer, &mut check).unwrap_err();
+        match *err.downcast::<SeqtoolsError>().unwrap() {
+            SeqtoolsError::Record {
+                message,
+                id,
+                location,
+            } => {
+                assert_eq!((message.as_str(), id.as_str()), ("ambiguous base", "b"));
+                assert_eq!(location.record, Some(1));
+                assert_eq!(location.line, Some(3));
+            }
+            err => panic!("unexpected error {err:?}"),
+        }
+        assert_eq!(out.text(), ">a\nACGT\n");
+    }
+}
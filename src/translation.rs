@@ -0,0 +1,323 @@
+//! Translation of nucleotide sequences with the NCBI genetic codes.
+//!
+//! Each code lists the amino acids of the 64 codons in the order used by NCBI,
+//! with bases ordered T, C, A, G: `TTT`, `TTC`, `TTA`, `TTG`, `TCT`, ...
+//! Stop codons are `*`.
+use clap::ValueEnum;
+
+use crate::sequence;
+
+/// Reading frames to translate
+#[derive(Copy, Clone, ValueEnum, Debug, PartialEq, Eq)]
+pub enum Frame {
+    #[value(name = "1")]
+    Forward1,
+    #[value(name = "2")]
+    Forward2,
+    #[value(name = "3")]
+    Forward3,
+    #[value(name = "-1")]
+    Reverse1,
+    #[value(name = "-2")]
+    Reverse2,
+    #[value(name = "-3")]
+    Reverse3,
+    All,
+}
+
+impl Frame {
+    /// The frames as given to [`GeneticCode::translate`]
+    pub fn offsets(self) -> Vec<i8> {
+        match self {
+            Frame::Forward1 => vec![1],
+            Frame::Forward2 => vec![2],
+            Frame::Forward3 => vec![3],
+            Frame::Reverse1 => vec![-1],
+            Frame::Reverse2 => vec![-2],
+            Frame::Reverse3 => vec![-3],
+            Frame::All => vec![1, 2, 3, -1, -2, -3],
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct GeneticCode {
+    /// NCBI identifier of the code, e.g. 11 for bacteria
+    pub id: u8,
+    pub name: &'static str,
+    amino_acids: &'static [u8; 64],
+}
+
+/// All the genetic codes of the NCBI taxonomy
+pub const GENETIC_CODES: [GeneticCode; 27] = [
+    GeneticCode {
+        id: 1,
+        name: "Standard",
+        amino_acids: b"FFLLSSSSYY**CC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+    },
+    GeneticCode {
+        id: 2,
+        name: "Vertebrate Mitochondrial",
+        amino_acids: b"FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIMMTTTTNNKKSS**VVVVAAAADDEEGGGG",
+    },
+    GeneticCode {
+        id: 3,
+        name: "Yeast Mitochondrial",
+        amino_acids: b"FFLLSSSSYY**CCWWTTTTPPPPHHQQRRRRIIMMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+    },
+    GeneticCode {
+        id: 4,
+        name: "Mold, Protozoan, and Coelenterate Mitochondrial and Mycoplasma/Spiroplasma",
+        amino_acids: b"FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+    },
+    GeneticCode {
+        id: 5,
+        name: "Invertebrate Mitochondrial",
+        amino_acids: b"FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIMMTTTTNNKKSSSSVVVVAAAADDEEGGGG",
+    },
+    GeneticCode {
+        id: 6,
+        name: "Ciliate, Dasycladacean and Hexamita Nuclear",
+        amino_acids: b"FFLLSSSSYYQQCC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+    },
+    GeneticCode {
+        id: 9,
+        name: "Echinoderm and Flatworm Mitochondrial",
+        amino_acids: b"FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIIMTTTTNNNKSSSSVVVVAAAADDEEGGGG",
+    },
+    GeneticCode {
+        id: 10,
+        name: "Euplotid Nuclear",
+        amino_acids: b"FFLLSSSSYY**CCCWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+    },
+    GeneticCode {
+        id: 11,
+        name: "Bacterial, Archaeal and Plant Plastid",
+        amino_acids: b"FFLLSSSSYY**CC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+    },
+    GeneticCode {
+        id: 12,
+        name: "Alternative Yeast Nuclear",
+        amino_acids: b"FFLLSSSSYY**CC*WLLLSPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+    },
+    GeneticCode {
+        id: 13,
+        name: "Ascidian Mitochondrial",
+        amino_acids: b"FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIMMTTTTNNKKSSGGVVVVAAAADDEEGGGG",
+    },
+    GeneticCode {
+        id: 14,
+        name: "Alternative Flatworm Mitochondrial",
+        amino_acids: b"FFLLSSSSYYY*CCWWLLLLPPPPHHQQRRRRIIIMTTTTNNNKSSSSVVVVAAAADDEEGGGG",
+    },
+    GeneticCode {
+        id: 15,
+        name: "Blepharisma Nuclear",
+        amino_acids: b"FFLLSSSSYY*QCC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+    },
+    GeneticCode {
+        id: 16,
+        name: "Chlorophycean Mitochondrial",
+        amino_acids: b"FFLLSSSSYY*LCC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+    },
+    GeneticCode {
+        id: 21,
+        name: "Trematode Mitochondrial",
+        amino_acids: b"FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIMMTTTTNNNKSSSSVVVVAAAADDEEGGGG",
+    },
+    GeneticCode {
+        id: 22,
+        name: "Scenedesmus obliquus Mitochondrial",
+        amino_acids: b"FFLLSS*SYY*LCC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+    },
+    GeneticCode {
+        id: 23,
+        name: "Thraustochytrium Mitochondrial",
+        amino_acids: b"FF*LSSSSYY**CC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+    },
+    GeneticCode {
+        id: 24,
+        name: "Rhabdopleuridae Mitochondrial",
+        amino_acids: b"FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSSKVVVVAAAADDEEGGGG",
+    },
+    GeneticCode {
+        id: 25,
+        name: "Candidate Division SR1 and Gracilibacteria",
+        amino_acids: b"FFLLSSSSYY**CCGWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+    },
+    GeneticCode {
+        id: 26,
+        name: "Pachysolen tannophilus Nuclear",
+        amino_acids: b"FFLLSSSSYY**CC*WLLLAPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+    },
+    GeneticCode {
+        id: 27,
+        name: "Karyorelict Nuclear",
+        amino_acids: b"FFLLSSSSYYQQCCWWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+    },
+    GeneticCode {
+        id: 28,
+        name: "Condylostoma Nuclear",
+        amino_acids: b"FFLLSSSSYYQQCCWWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+    },
+    GeneticCode {
+        id: 29,
+        name: "Mesodinium Nuclear",
+        amino_acids: b"FFLLSSSSYYYYCC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+    },
+    GeneticCode {
+        id: 30,
+        name: "Peritrich Nuclear",
+        amino_acids: b"FFLLSSSSYYEECC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+    },
+    GeneticCode {
+        id: 31,
+        name: "Blastocrithidia Nuclear",
+        amino_acids: b"FFLLSSSSYYEECCWWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+    },
+    GeneticCode {
+        id: 32,
+        name: "Balanophoraceae Plastid",
+        amino_acids: b"FFLLSSSSYY*WCC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+    },
+    GeneticCode {
+        id: 33,
+        name: "Cephalodiscidae Mitochondrial",
+        amino_acids: b"FFLLSSSSYYY*CCWWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSSKVVVVAAAADDEEGGGG",
+    },
+];
+
+/// The genetic code with NCBI identifier `id`
+pub fn genetic_code(id: u8) -> Option<&'static GeneticCode> {
+    GENETIC_CODES.iter().find(|code| code.id == id)
+}
+
+fn base_index(base: u8) -> usize {
+    match base {
+        b'T' => 0,
+        b'C' => 1,
+        b'A' => 2,
+        _ => 3,
+    }
+}
+
+fn is_gap(base: u8) -> bool {
+    base == b'-' || base == b'.'
+}
+
+impl GeneticCode {
+    /// Amino acid of a codon. A codon with ambiguous bases is translated if
+    /// all the codons it stands for give the same amino acid, and is `X`
+    /// otherwise, like codons with gaps or unknown characters. A codon of
+    /// gaps is a gap.
+    pub fn translate_codon(&self, codon: &[u8]) -> u8 {
+        if codon.iter().all(|base| is_gap(*base)) {
+            return b'-';
+        }
+
+        let mut amino_acid = None;
+        for first in sequence::bases(codon[0]) {
+            for second in sequence::bases(codon[1]) {
+                for third in sequence::bases(codon[2]) {
+                    let index =
+                        16 * base_index(*first) + 4 * base_index(*second) + base_index(*third);
+                    match amino_acid {
+                        None => amino_acid = Some(self.amino_acids[index]),
+                        Some(other) if other != self.amino_acids[index] => return b'X',
+                        Some(_) => {}
+                    }
+                }
+            }
+        }
+
+        amino_acid.unwrap_or(b'X')
+    }
+
+    /// Translate a sequence in reading frame `frame`: 1 to 3 start at the
+    /// first to third base, -1 to -3 at the first to third base of the
+    /// reverse complement. Trailing bases that do not make a codon are
+    /// dropped.
+    pub fn translate(&self, seq: &[u8], frame: i8) -> Vec<u8> {
+        let strand = if frame < 0 {
+            sequence::reverse_complement(seq)
+        } else {
+            seq.to_vec()
+        };
+        let start = (frame.unsigned_abs() as usize - 1).min(strand.len());
+
+        strand[start..]
+            .chunks_exact(3)
+            .map(|codon| self.translate_codon(codon))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn standard() -> &'static GeneticCode {
+        genetic_code(1).unwrap()
+    }
+
+    #[test]
+    fn lists_codes_once() {
+        let mut ids: Vec<u8> = GENETIC_CODES.iter().map(|code| code.id).collect();
+        ids.sort_unstable();
+        ids.dedup();
+        assert_eq!(ids.len(), GENETIC_CODES.len());
+        assert!(genetic_code(7).is_none());
+        assert_eq!(
+            genetic_code(11).unwrap().name,
+            "Bacterial, Archaeal and Plant Plastid"
+        );
+    }
+
+    #[test]
+    fn translates_codons() {
+        let code = standard();
+        assert_eq!(code.translate_codon(b"ATG"), b'M');
+        assert_eq!(code.translate_codon(b"TAA"), b'*');
+        assert_eq!(code.translate_codon(b"ggc"), b'G');
+        assert_eq!(code.translate_codon(b"UUU"), b'F');
+
+        let mitochondrial = genetic_code(2).unwrap();
+        assert_eq!(mitochondrial.translate_codon(b"TGA"), b'W');
+        assert_eq!(mitochondrial.translate_codon(b"AGA"), b'*');
+    }
+
+    #[test]
+    fn translates_ambiguous_codons() {
+        let code = standard();
+        assert_eq!(code.translate_codon(b"GCN"), b'A');
+        assert_eq!(code.translate_codon(b"TTY"), b'F');
+        assert_eq!(code.translate_codon(b"YTR"), b'L');
+        assert_eq!(code.translate_codon(b"TAR"), b'*');
+        assert_eq!(code.translate_codon(b"TTN"), b'X');
+        assert_eq!(code.translate_codon(b"AT?"), b'X');
+    }
+
+    #[test]
+    fn translates_gaps() {
+        let code = standard();
+        assert_eq!(code.translate_codon(b"---"), b'-');
+        assert_eq!(code.translate_codon(b"..."), b'-');
+        assert_eq!(code.translate_codon(b"A-G"), b'X');
+    }
+
+    #[test]
+    fn translates_frames() {
+        let code = standard();
+        let seq = b"ATGGCCTAAC";
+        assert_eq!(code.translate(seq, 1), b"MA*");
+        assert_eq!(code.translate(seq, 2), b"WPN");
+        assert_eq!(code.translate(seq, 3), b"GL");
+        // Reverse complement: GTTAGGCCAT
+        assert_eq!(code.translate(seq, -1), b"VRP");
+        assert_eq!(code.translate(seq, -2), b"LGH");
+        assert_eq!(code.translate(seq, -3), b"*A");
+        assert_eq!(code.translate(b"AT", 3), b"");
+        assert_eq!(Frame::All.offsets(), [1, 2, 3, -1, -2, -3]);
+        assert_eq!(Frame::Reverse2.offsets(), [-2]);
+    }
+}
@@ -35,13 +35,27 @@ struct App {
     ruler: String,
 }
 
-const NUCLEOTIDES: [char; 11] = ['A', 'a', 'T', 't', 'C', 'c', 'G', 'g', 'U', 'u', '-'];
-enum Alphabet {
+const NUCLEOTIDES: [char; 14] = [
+    'A', 'a', 'T', 't', 'C', 'c', 'G', 'g', 'U', 'u', 'N', 'n', '-', '?',
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Alphabet {
     Nucleic,
     Protein,
 }
 
 impl Alphabet {
+    /// Sequences that only contain nucleotides, gaps and unknown bases are
+    /// nucleic, anything else is protein
+    pub fn infer<'a>(seqs: impl IntoIterator<Item = &'a [u8]>) -> Self {
+        let is_nucleic = |seq: &[u8]| seq.iter().all(|c| NUCLEOTIDES.contains(&(*c as char)));
+        match seqs.into_iter().all(is_nucleic) {
+            true => Alphabet::Nucleic,
+            false => Alphabet::Protein,
+        }
+    }
+
     fn colorize(&self, c: char) -> Color {
         let c = c.to_ascii_uppercase();
         match self {
@@ -72,13 +86,7 @@ impl App {
         let maxlen = seqs.iter().map(|seq| seq.len() as u16).max().unwrap_or(0);
         let nseqs = seqs.len() as u16;
 
-        let alphabet = match seqs
-            .iter()
-            .any(|seq| seq.chars().any(|c| !NUCLEOTIDES.contains(&c)))
-        {
-            true => Alphabet::Protein,
-            false => Alphabet::Nucleic,
-        };
+        let alphabet = Alphabet::infer(seqs.iter().map(|seq| seq.as_bytes()));
 
         let mut ruler = " ".to_string();
         let mut i = 1;
@@ -0,0 +1,280 @@
+//! Writing records in any of the supported formats. FASTA and FASTQ records
+//! are written as they come, alignments are buffered until
+//! [`RecordWriter::finish`] since their layout depends on all the records.
+use std::io::Write;
+use std::path::PathBuf;
+
+use needletail::parser::{self, LineEnding};
+
+use crate::errors::SeqtoolsError;
+use crate::formats::{clustal, nexus, phylip, stockholm, tabular, Annotations};
+use crate::record::Record;
+use crate::{init_writer, Format};
+
+/// Output settings that apply to every format
+#[derive(Debug, Clone)]
+pub struct WriteOptions {
+    pub line_ending: LineEnding,
+    /// Number of characters per sequence line in FASTA output, 0 to write each
+    /// sequence on a single line
+    pub line_width: usize,
+    /// Write PHYLIP alignments in interleaved blocks instead of one line per sequence
+    pub phylip_interleaved: bool,
+    /// Where to write the `short name -> identifier` map of strict PHYLIP
+    /// output, needed when identifiers do not fit in 10 characters
+    pub name_map: Option<PathBuf>,
+}
+
+impl Default for WriteOptions {
+    fn default() -> Self {
+        WriteOptions {
+            line_ending: LineEnding::Unix,
+            line_width: 0,
+            phylip_interleaved: false,
+            name_map: None,
+        }
+    }
+}
+
+pub struct RecordWriter {
+    writer: Box<dyn Write>,
+    to: Option<Format>,
+    default: Option<Format>,
+    options: WriteOptions,
+    /// Records of an alignment format, written by `finish`
+    alignment: Vec<Record>,
+    alignment_format: Option<Format>,
+    /// Annotations of the input alignment, written along with the records
+    /// when the output format can hold them
+    annotations: Option<Annotations>,
+}
+
+impl RecordWriter {
+    /// Write records in the `to` format, or in the format they were read in
+    pub fn new(writer: Box<dyn Write>, to: Option<Format>, options: WriteOptions) -> Self {
+        RecordWriter {
+            writer,
+            to,
+            default: None,
+            options,
+            alignment: vec![],
+            alignment_format: None,
+            annotations: None,
+        }
+    }
+
+    /// Format of the input, used when no output format was requested.
+    /// When neither is known, records that have qualities are written as
+    /// FASTQ and the others as FASTA, as are records that lost their qualities
+    /// on the way from a FASTQ input, such as translations.
+    pub fn set_input_format(&mut self, format: Option<Format>) {
+        if self.default.is_none() {
+            self.default = format;
+        }
+    }
+
+    /// Annotations of the input alignment. Only those of the first input are
+    /// kept.
+    pub fn set_input_annotations(&mut self, annotations: Option<&Annotations>) {
+        if self.annotations.is_none() {
+            self.annotations = annotations.cloned();
+        }
+    }
+
+    fn format_of(&self, record: &Record) -> Format {
+        let default = self
+            .default
+            .filter(|format| *format != Format::Fastq || record.qual.is_some());
+        self.to.or(default).unwrap_or(match record.qual {
+            Some(_) => Format::Fastq,
+            None => Format::Fasta,
+        })
+    }
+
+    pub fn write(&mut self, record: Record) -> Result<(), SeqtoolsError> {
+        let line_ending = self.options.line_ending;
+        match self.format_of(&record) {
+            Format::Fasta => self.write_fasta(&record)?,
+            Format::Fastq => parser::write_fastq(
+                &record.id,
+                &record.seq,
+                record.qual.as_deref(),
+                &mut self.writer,
+                line_ending,
+            )?,
+            Format::Tsv => tabular::write_tsv(&mut self.writer, &record, line_ending)?,
+            Format::Jsonl => tabular::write_jsonl(&mut self.writer, &record, line_ending)?,
+            format @ (Format::Phylip
+            | Format::PhylipStrict
+            | Format::Nexus
+            | Format::Stockholm
+            | Format::Clustal) => {
+                self.alignment_format = Some(format);
+                self.alignment.push(record);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_fasta(&mut self, record: &Record) -> Result<(), SeqtoolsError> {
+        let (width, line_ending) = (self.options.line_width, self.options.line_ending);
+        if width == 0 {
+            parser::write_fasta(&record.id, &record.seq, &mut self.writer, line_ending)?;
+            return Ok(());
+        }
+
+        let eol = line_ending.to_bytes();
+        self.writer.write_all(b">")?;
+        self.writer.write_all(&record.id)?;
+        self.writer.write_all(&eol)?;
+        for line in record.seq.chunks(width) {
+            self.writer.write_all(line)?;
+            self.writer.write_all(&eol)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_phylip(&mut self, strict: bool) -> Result<(), SeqtoolsError> {
+        let (names, map) = phylip::names(&self.alignment, strict);
+        if let Some(map) = map {
+            let path = self.options.name_map.clone().ok_or_else(|| {
+                SeqtoolsError::invalid_argument(
+                    "Identifiers do not fit in strict PHYLIP names, write to a file with --out to get a name map",
+                )
+            })?;
+            let mut map_writer = init_writer(Some(path), None)?;
+            for (short, id) in map {
+                writeln!(map_writer, "{short}\t{id}")?;
+            }
+            map_writer.flush()?;
+        }
+
+        phylip::write(
+            &mut self.writer,
+            &names,
+            &self.alignment,
+            strict,
+            self.options.phylip_interleaved,
+            self.options.line_ending,
+        )
+    }
+
+    /// Write the buffered alignment, if any, and flush the output
+    pub fn finish(&mut self) -> Result<(), SeqtoolsError> {
+        match self.alignment_format.take() {
+            Some(Format::Phylip) => self.write_phylip(false)?,
+            Some(Format::PhylipStrict) => self.write_phylip(true)?,
+            Some(Format::Nexus) => {
+                nexus::write(&mut self.writer, &self.alignment, self.options.line_ending)?
+            }
+            Some(Format::Stockholm) => stockholm::write(
+                &mut self.writer,
+                &self.alignment,
+                &self.annotations.clone().unwrap_or_default(),
+                self.options.line_ending,
+            )?,
+            Some(Format::Clustal) => {
+                clustal::write(&mut self.writer, &self.alignment, self.options.line_ending)?
+            }
+            _ => {}
+        }
+        self.alignment.clear();
+        self.writer.flush()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::SharedBuffer;
+
+    fn written(records: &[Record], to: Option<Format>, options: WriteOptions) -> String {
+        let out = SharedBuffer::default();
+        let mut writer = RecordWriter::new(Box::new(out.clone()), to, options);
+        for record in records {
+            writer.write(record.clone()).unwrap();
+        }
+        writer.finish().unwrap();
+        out.text()
+    }
+
+    fn fasta(seqs: &[&str], line_width: usize, line_ending: LineEnding) -> String {
+        let records: Vec<Record> = seqs
+            .iter()
+            .enumerate()
+            .map(|(i, seq)| Record::new(format!("r{i} desc").into(), seq.as_bytes().into(), None))
+            .collect();
+        let options = WriteOptions {
+            line_width,
+            line_ending,
+            ..WriteOptions::default()
+        };
+        written(&records, Some(Format::Fasta), options)
+    }
+
+    #[test]
+    fn wraps_fasta_lines() {
+        let seqs = ["ACG", "ACGT", "ACGTA", "ACGTACGT", ""];
+        assert_eq!(
+            fasta(&seqs, 4, LineEnding::Unix),
+            ">r0 desc\nACG\n>r1 desc\nACGT\n>r2 desc\nACGT\nA\n\
+             >r3 desc\nACGT\nACGT\n>r4 desc\n"
+        );
+        assert_eq!(
+            fasta(&seqs[1..3], 4, LineEnding::Windows),
+            ">r0 desc\r\nACGT\r\n>r1 desc\r\nACGT\r\nA\r\n"
+        );
+        assert_eq!(
+            fasta(&seqs[..2], 1, LineEnding::Unix),
+            ">r0 desc\nA\nC\nG\n>r1 desc\nA\nC\nG\nT\n"
+        );
+    }
+
+    #[test]
+    fn writes_unwrapped_fasta_with_width_0() {
+        let long = "ACGT".repeat(100);
+        assert_eq!(
+            fasta(&[&long, "A"], 0, LineEnding::Unix),
+            format!(">r0 desc\n{long}\n>r1 desc\nA\n")
+        );
+    }
+
+    #[test]
+    fn does_not_wrap_fastq() {
+        let record = Record::new(b"r".to_vec(), b"ACGTA".to_vec(), Some(b"IIIII".to_vec()));
+        let options = WriteOptions {
+            line_width: 2,
+            ..WriteOptions::default()
+        };
+        assert_eq!(written(&[record], None, options), "@r\nACGTA\n+\nIIIII\n");
+    }
+
+    #[test]
+    fn follows_the_input_format() {
+        let fastq = Record::new(b"q".to_vec(), b"AC".to_vec(), Some(b"II".to_vec()));
+        let fasta = Record::new(b"a".to_vec(), b"AC".to_vec(), None);
+        let records = [fastq.clone(), fasta.clone()];
+        let options = WriteOptions::default;
+        assert_eq!(
+            written(&records, None, options()),
+            "@q\nAC\n+\nII\n>a\nAC\n"
+        );
+        assert_eq!(
+            written(&records, Some(Format::Fasta), options()),
+            ">q\nAC\n>a\nAC\n"
+        );
+
+        let out = SharedBuffer::default();
+        let mut writer = RecordWriter::new(Box::new(out.clone()), None, options());
+        writer.set_input_format(Some(Format::Tsv));
+        writer.set_input_format(Some(Format::Fastq));
+        writer.write(fastq).unwrap();
+        writer.write(fasta).unwrap();
+        writer.finish().unwrap();
+        assert_eq!(out.text(), "q\tAC\tII\na\tAC\n");
+    }
+}